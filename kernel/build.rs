@@ -14,35 +14,40 @@ fn main() {
     };
 
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
-    let boot_o = out_dir.join("boot.o");
+    let arch_dir = PathBuf::from(format!("src/arch/{}", target_arch));
+    let linker_ld = arch_dir.join("linker.ld");
 
-    let boot_s = PathBuf::from(format!("src/arch/{}/boot.S", target_arch));
-    let linker_ld = PathBuf::from(format!("src/arch/{}/linker.ld", target_arch));
-    
-    // Tell cargo to rerun this build script if boot.S changes
-    println!("cargo:rerun-if-changed={}", boot_s.display());
+    // Tell cargo to rerun this build script if the linker script changes
     println!("cargo:rerun-if-changed={}", linker_ld.display());
 
-    // Compile boot.S to boot.o
-    let status = Command::new(compiler)
-        .args(&[
-            "-Wall",
-            "-O2", 
-            "-ffreestanding",
-            "-nostdlib",
-            "-nostartfiles",
-            "-c",
-            boot_s.to_str().unwrap(),
-            "-o",
-            boot_o.to_str().unwrap(),
-        ])
-        .status()
-        .expect(&format!("Failed to execute {}", compiler));
-
-    if !status.success() {
-        panic!("Failed to compile boot.S");
-    }
+    for source in ["boot.S", "vectors.S", "switch.S"] {
+        let source_s = arch_dir.join(source);
+        let source_o = out_dir.join(source).with_extension("o");
+
+        // Tell cargo to rerun this build script if the assembly changes
+        println!("cargo:rerun-if-changed={}", source_s.display());
+
+        // Compile the .S file to a .o
+        let status = Command::new(compiler)
+            .args([
+                "-Wall",
+                "-O2",
+                "-ffreestanding",
+                "-nostdlib",
+                "-nostartfiles",
+                "-c",
+                source_s.to_str().unwrap(),
+                "-o",
+                source_o.to_str().unwrap(),
+            ])
+            .status()
+            .unwrap_or_else(|_| panic!("Failed to execute {}", compiler));
 
-    // Tell cargo to link the boot.o file directly
-    println!("cargo:rustc-link-arg={}", boot_o.display());
+        if !status.success() {
+            panic!("Failed to compile {}", source);
+        }
+
+        // Tell cargo to link the .o file directly
+        println!("cargo:rustc-link-arg={}", source_o.display());
+    }
 }
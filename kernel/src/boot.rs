@@ -0,0 +1,28 @@
+use devtree::DevTree;
+
+// What boot.S hands over to kernel_main
+#[derive(Debug, Clone, Copy)]
+pub struct BootInfo {
+    /// Physical address of the DTB, from x0 at entry
+    pub dtb_phys: usize,
+    /// Exception level the bootloader entered the kernel at
+    pub entry_el: u8,
+    /// Where the image was loaded, and how much memory it takes including
+    /// .bss and the boot stack
+    pub image_base: usize,
+    pub image_size: usize,
+    /// MPIDR_EL1 of the boot CPU
+    pub boot_mpidr: u64,
+}
+
+impl BootInfo {
+    pub fn image_end(&self) -> usize {
+        self.image_base + self.image_size
+    }
+
+    /// Parse the DTB the bootloader passed, if it's a valid one
+    pub fn devtree(&self) -> Option<DevTree> {
+        // The bootloader hands us a DTB that stays in place for good
+        unsafe { DevTree::new(self.dtb_phys as *const u8) }
+    }
+}
@@ -0,0 +1,145 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use devtree::DevTree;
+use sync::SpinLockIrq;
+
+use crate::drivers::pl011::{Pl011, Pl011Config};
+use crate::mm::{PAGE_SIZE, paging};
+
+// PL011 that QEMU `virt` always has, usable before the DTB is parsed
+const EARLY_UART_BASE: usize = 0x0900_0000;
+
+// Global console
+//
+// Until the MMU is on the lock can't be taken, since exclusive loads and
+// stores don't work yet. Only the boot CPU runs then, with interrupts
+// masked, so early prints just go around it.
+static CONSOLE: SpinLockIrq<Option<Pl011>> = SpinLockIrq::new(None);
+
+// Base of the UART in CONSOLE, for printing without its lock
+static UART_BASE: AtomicUsize = AtomicUsize::new(0);
+
+fn with_console<R>(f: impl FnOnce(&mut Option<Pl011>) -> R) -> R {
+    if !sync::atomics_available() {
+        return f(unsafe { &mut *CONSOLE.data_ptr() });
+    }
+    f(&mut CONSOLE.lock())
+}
+
+fn with_uart<R>(f: impl FnOnce(&mut Pl011) -> R) -> Option<R> {
+    with_console(|uart| uart.as_mut().map(f))
+}
+
+fn set_uart(uart: Pl011) {
+    let base = uart.base();
+    with_console(|console| *console = Some(uart));
+    UART_BASE.store(base, Ordering::Relaxed);
+}
+
+/// Print through the UART at its fixed QEMU `virt` address, trusting
+/// whatever configuration firmware left behind
+pub fn init_early() {
+    paging::map_device(EARLY_UART_BASE, PAGE_SIZE);
+    set_uart(unsafe { Pl011::new(EARLY_UART_BASE) });
+}
+
+/// Switch to the UART the device tree points at, returns whether one was
+/// found. Prefers `/chosen/stdout-path`, then any `arm,pl011`.
+pub fn init(devtree: &DevTree) -> bool {
+    let config = stdout_config(devtree).or_else(|| {
+        let node = devtree.find_compatible("arm,pl011")?;
+        Pl011Config::from_node(devtree, &node, None)
+    });
+
+    let Some(config) = config else {
+        return false;
+    };
+
+    // Don't cut off whatever the early console is still sending
+    with_uart(|uart| uart.flush());
+
+    paging::map_device(config.base, PAGE_SIZE);
+    let mut uart = unsafe { Pl011::new(config.base) };
+    uart.init(config.clock_hz, config.baud);
+    set_uart(uart);
+
+    true
+}
+
+// `stdout-path` is either a path or an alias, optionally followed by
+// `:<baud><parity><bits>`, e.g. "serial0:115200n8"
+fn stdout_config(devtree: &DevTree) -> Option<Pl011Config> {
+    let chosen = devtree.find_node("/chosen")?;
+    let stdout = chosen
+        .property("stdout-path")
+        .or_else(|| chosen.property("linux,stdout-path"))?
+        .as_string()?;
+
+    let (path, options) = match stdout.split_once(':') {
+        Some((path, options)) => (path, Some(options)),
+        None => (stdout, None),
+    };
+
+    let node = if path.starts_with('/') {
+        devtree.find_node(path)?
+    } else {
+        let alias = devtree.find_node("/aliases")?.property(path)?.as_string()?;
+        devtree.find_node(alias)?
+    };
+
+    let baud = options.and_then(|options| {
+        let digits = options.find(|c: char| !c.is_ascii_digit()).unwrap_or(options.len());
+        options[..digits].parse().ok()
+    });
+
+    Pl011Config::from_node(devtree, &node, baud)
+}
+
+/// Send raw bytes, e.g. a process's standard output
+pub fn write_bytes(bytes: &[u8]) {
+    // A bit at a time, so IRQs aren't held off for the whole buffer
+    for chunk in bytes.chunks(64) {
+        with_uart(|uart| {
+            for &byte in chunk {
+                if byte == b'\n' {
+                    uart.putc(b'\r');
+                }
+                uart.putc(byte);
+            }
+        });
+    }
+}
+
+/// A byte received on the console, if one arrived
+pub fn read_byte() -> Option<u8> {
+    with_uart(|uart| uart.getc()).flatten()
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    with_uart(|uart| {
+        let _ = uart.write_fmt(args);
+    });
+}
+
+/// Print without the console lock, for the panic handler, which can't know
+/// whether the holder will ever let go. May interleave with other output.
+#[doc(hidden)]
+pub fn _emergency_print(args: fmt::Arguments) {
+    let base = UART_BASE.load(Ordering::Relaxed);
+    if base != 0 {
+        let _ = unsafe { Pl011::new(base) }.write_fmt(args);
+    }
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,65 @@
+use core::arch::asm;
+
+use devtree::DevTree;
+
+use crate::drivers::irqchip::Interrupt;
+
+const COMPATIBLE: &[&str] = &["arm,armv8-timer", "arm,armv7-timer"];
+
+// Order of the timer node's `interrupts`: secure physical, non-secure
+// physical, virtual and hypervisor timers
+const VIRTUAL_TIMER_INDEX: usize = 2;
+
+// CNTV_CTL_EL0 bits
+const CTL_ENABLE: u64 = 1 << 0;
+const CTL_IMASK: u64 = 1 << 1;
+
+// ARM generic timer, using the EL1 virtual timer
+//
+// The virtual counter is always accessible from EL1, whatever EL2 set up,
+// and boot.S zeroes the virtual offset when it comes through EL2.
+
+/// Find the virtual timer's interrupt in the device tree
+pub fn interrupt(devtree: &DevTree) -> Option<Interrupt> {
+    let node = COMPATIBLE.iter().find_map(|c| devtree.find_compatible(c))?;
+    Interrupt::from_node(&node, VIRTUAL_TIMER_INDEX)
+}
+
+/// Counter frequency in Hz, as firmware programmed it
+pub fn frequency() -> u64 {
+    let freq: u64;
+    unsafe { asm!("mrs {}, cntfrq_el0", out(reg) freq) };
+    freq
+}
+
+/// Current value of the virtual counter
+pub fn counter() -> u64 {
+    let count: u64;
+    // The ISB keeps the read from happening earlier than it appears to
+    unsafe { asm!("isb", "mrs {}, cntvct_el0", out(reg) count) };
+    count
+}
+
+/// Fire the timer interrupt once the counter reaches `deadline`
+pub fn set_deadline(deadline: u64) {
+    unsafe {
+        asm!(
+            "msr cntv_cval_el0, {}",
+            "msr cntv_ctl_el0, {}",
+            "isb",
+            in(reg) deadline,
+            in(reg) CTL_ENABLE,
+        );
+    }
+}
+
+/// Stop the timer, deasserting its interrupt
+pub fn stop() {
+    unsafe {
+        asm!(
+            "msr cntv_ctl_el0, {}",
+            "isb",
+            in(reg) CTL_IMASK,
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use core::ptr::{read_volatile, write_volatile};
+
+use super::Trigger;
+
+// Register offsets, the same in the distributor and in the GICv3
+// redistributor's SGI frame
+pub const IGROUPR: usize = 0x080;
+pub const ISENABLER: usize = 0x100;
+pub const ICENABLER: usize = 0x180;
+pub const ICPENDR: usize = 0x280;
+pub const IPRIORITYR: usize = 0x400;
+pub const ICFGR: usize = 0xc00;
+
+// Priority everything starts with, in the middle of the range so callers
+// can go either way
+pub const DEFAULT_PRIORITY: u8 = 0xa0;
+
+// Per-interrupt enable, priority and trigger registers
+//
+// The distributor has them for every interrupt, and the GICv3 redistributor
+// repeats the first bank for the calling CPU's SGIs and PPIs.
+#[derive(Clone, Copy)]
+pub struct IrqBanks {
+    base: usize,
+}
+
+impl IrqBanks {
+    /// # Safety
+    ///
+    /// `base` must point at a GIC distributor or redistributor SGI frame.
+    pub const unsafe fn new(base: usize) -> Self {
+        IrqBanks { base }
+    }
+
+    pub fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    pub fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    pub fn write64(&self, offset: usize, value: u64) {
+        unsafe { write_volatile((self.base + offset) as *mut u64, value) }
+    }
+
+    pub fn write_byte(&self, offset: usize, value: u8) {
+        unsafe { write_volatile((self.base + offset) as *mut u8, value) }
+    }
+
+    pub fn enable(&self, irq: u32) {
+        self.write(ISENABLER + (irq as usize / 32) * 4, 1 << (irq % 32));
+    }
+
+    pub fn disable(&self, irq: u32) {
+        self.write(ICENABLER + (irq as usize / 32) * 4, 1 << (irq % 32));
+    }
+
+    pub fn set_priority(&self, irq: u32, priority: u8) {
+        self.write_byte(IPRIORITYR + irq as usize, priority);
+    }
+
+    /// SGIs are always edge triggered, and some PPIs are fixed too
+    pub fn set_trigger(&self, irq: u32, trigger: Trigger) {
+        let offset = ICFGR + (irq as usize / 16) * 4;
+        let shift = (irq % 16) * 2 + 1;
+        let config = match trigger {
+            Trigger::Edge => self.read(offset) | (1 << shift),
+            Trigger::Level => self.read(offset) & !(1 << shift),
+        };
+        self.write(offset, config);
+    }
+
+    /// Disable, clear and reset the priority of interrupts `first..last`,
+    /// both multiples of 32
+    pub fn reset(&self, first: u32, last: u32) {
+        for irq in (first..last).step_by(32) {
+            self.write(ICENABLER + (irq as usize / 32) * 4, u32::MAX);
+            self.write(ICPENDR + (irq as usize / 32) * 4, u32::MAX);
+        }
+        for irq in first..last {
+            self.set_priority(irq, DEFAULT_PRIORITY);
+        }
+    }
+}
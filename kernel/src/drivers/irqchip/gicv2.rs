@@ -0,0 +1,121 @@
+use core::arch::asm;
+use core::ptr::{read_volatile, write_volatile};
+
+use super::banks::IrqBanks;
+use super::{MAX_IRQS, Trigger};
+
+// Distributor register offsets
+const GICD_CTLR: usize = 0x000;
+const GICD_TYPER: usize = 0x004;
+const GICD_ITARGETSR: usize = 0x800;
+const GICD_SGIR: usize = 0xf00;
+
+// CPU interface register offsets
+const GICC_CTLR: usize = 0x00;
+const GICC_PMR: usize = 0x04;
+const GICC_BPR: usize = 0x08;
+const GICC_IAR: usize = 0x0c;
+const GICC_EOIR: usize = 0x10;
+
+const GICD_CTLR_ENABLE: u32 = 1 << 0;
+const GICC_CTLR_ENABLE: u32 = 1 << 0;
+
+// Target list filter sending an SGI to every CPU but the requesting one
+const GICD_SGIR_OTHERS: u32 = 0b01 << 24;
+
+// GICv2, with a memory mapped CPU interface
+pub struct GicV2 {
+    dist: IrqBanks,
+    cpu: usize,
+}
+
+impl GicV2 {
+    /// # Safety
+    ///
+    /// `dist` and `cpu` must be the distributor and CPU interface of the
+    /// same GICv2, which nothing else is driving.
+    pub unsafe fn new(dist: usize, cpu: usize) -> Self {
+        GicV2 {
+            dist: unsafe { IrqBanks::new(dist) },
+            cpu,
+        }
+    }
+
+    fn cpu_read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.cpu + offset) as *const u32) }
+    }
+
+    fn cpu_write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.cpu + offset) as *mut u32, value) }
+    }
+
+    /// Reset the distributor, sending every SPI to the calling CPU, then set
+    /// up this CPU's interface
+    pub fn init(&mut self) {
+        self.dist.write(GICD_CTLR, 0);
+
+        let lines = (32 * ((self.dist.read(GICD_TYPER) & 0x1f) + 1)).min(MAX_IRQS as u32);
+        self.dist.reset(32, lines);
+
+        // Reading any of the first eight targets registers gives the
+        // calling CPU's own mask
+        let target = self.dist.read(GICD_ITARGETSR) & 0xff;
+        for irq in 32..lines {
+            self.dist.write_byte(GICD_ITARGETSR + irq as usize, target as u8);
+        }
+
+        self.dist.write(GICD_CTLR, GICD_CTLR_ENABLE);
+        self.init_cpu();
+    }
+
+    /// Reset the calling CPU's banked SGIs and PPIs and enable its interface
+    pub fn init_cpu(&mut self) {
+        self.dist.reset(0, 32);
+
+        // Let every priority through, without preemption groups
+        self.cpu_write(GICC_PMR, 0xff);
+        self.cpu_write(GICC_BPR, 0);
+        self.cpu_write(GICC_CTLR, GICC_CTLR_ENABLE);
+    }
+
+    pub fn enable(&mut self, irq: u32) {
+        self.dist.enable(irq);
+    }
+
+    pub fn disable(&mut self, irq: u32) {
+        self.dist.disable(irq);
+    }
+
+    pub fn set_priority(&mut self, irq: u32, priority: u8) {
+        self.dist.set_priority(irq, priority);
+    }
+
+    pub fn set_trigger(&mut self, irq: u32, trigger: Trigger) {
+        self.dist.set_trigger(irq, trigger);
+    }
+
+    /// Acknowledge the highest priority pending interrupt, returns its ID
+    /// and the value `eoi` needs, which also names the sender of an SGI
+    pub fn ack(&mut self) -> (u32, u32) {
+        let iar = self.cpu_read(GICC_IAR);
+        (iar & 0x3ff, iar)
+    }
+
+    pub fn eoi(&mut self, iar: u32) {
+        self.cpu_write(GICC_EOIR, iar);
+    }
+}
+
+/// Send SGI `sgi` to every CPU but the calling one, writing the distributor
+/// at `dist` directly rather than through a `GicV2`
+///
+/// # Safety
+///
+/// `dist` must be the distributor of an initialized GICv2.
+pub unsafe fn send_sgi_to_others(dist: usize, sgi: u32) {
+    unsafe {
+        // Make our writes visible to whoever handles it
+        asm!("dsb ishst");
+        write_volatile((dist + GICD_SGIR) as *mut u32, GICD_SGIR_OTHERS | sgi);
+    }
+}
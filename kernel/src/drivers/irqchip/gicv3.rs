@@ -0,0 +1,188 @@
+use core::arch::asm;
+use core::ptr::{read_volatile, write_volatile};
+
+use super::banks::{IGROUPR, IrqBanks};
+use super::{MAX_IRQS, Trigger};
+use crate::machine;
+
+// Distributor register offsets
+const GICD_CTLR: usize = 0x0000;
+const GICD_TYPER: usize = 0x0004;
+const GICD_IROUTER: usize = 0x6000;
+
+const GICD_CTLR_ENABLE_G1: u32 = 1 << 0;
+const GICD_CTLR_ENABLE_G1A: u32 = 1 << 1;
+const GICD_CTLR_ARE_NS: u32 = 1 << 4;
+const GICD_CTLR_RWP: u32 = 1 << 31;
+
+// Redistributor register offsets, in the RD frame
+const GICR_TYPER: usize = 0x0008;
+const GICR_WAKER: usize = 0x0014;
+
+const GICR_TYPER_VLPIS: u64 = 1 << 1;
+const GICR_TYPER_LAST: u64 = 1 << 4;
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+
+// ICC_SGI1R_EL1 fields, IRM sends to every PE but the requesting one
+const ICC_SGI1R_INTID_SHIFT: u64 = 24;
+const ICC_SGI1R_IRM: u64 = 1 << 40;
+
+// Each redistributor has an RD and an SGI frame, plus two more for vLPIs
+// on GICv4
+const GICR_FRAME_SIZE: usize = 0x10000;
+
+// GICv3, whose CPU interface is accessed through system registers
+//
+// Only the first redistributor region is used, which covers every CPU QEMU
+// `virt` boots with up to 123 of them.
+pub struct GicV3 {
+    dist: IrqBanks,
+    redist_base: usize,
+    redist_size: usize,
+}
+
+impl GicV3 {
+    /// # Safety
+    ///
+    /// `dist` and the `redist_size` bytes at `redist_base` must be the
+    /// distributor and redistributors of the same GICv3, which nothing else
+    /// is driving.
+    pub unsafe fn new(dist: usize, redist_base: usize, redist_size: usize) -> Self {
+        GicV3 {
+            dist: unsafe { IrqBanks::new(dist) },
+            redist_base,
+            redist_size,
+        }
+    }
+
+    fn wait_for_rwp(&self) {
+        while self.dist.read(GICD_CTLR) & GICD_CTLR_RWP != 0 {}
+    }
+
+    /// Reset the distributor, routing every SPI to the calling CPU, then set
+    /// up this CPU's redistributor and interface
+    pub fn init(&mut self) {
+        self.dist.write(GICD_CTLR, 0);
+        self.wait_for_rwp();
+
+        let lines = (32 * ((self.dist.read(GICD_TYPER) & 0x1f) + 1)).min(MAX_IRQS as u32);
+        self.dist.reset(32, lines);
+        for irq in (32..lines).step_by(32) {
+            // Non-secure group 1, the one EL1 gets
+            self.dist.write(IGROUPR + (irq as usize / 32) * 4, u32::MAX);
+        }
+
+        let affinity = machine::cpu_id();
+        for irq in 32..lines {
+            self.dist.write64(GICD_IROUTER + irq as usize * 8, affinity);
+        }
+
+        self.dist.write(GICD_CTLR, GICD_CTLR_ARE_NS | GICD_CTLR_ENABLE_G1A | GICD_CTLR_ENABLE_G1);
+        self.wait_for_rwp();
+
+        self.init_cpu();
+    }
+
+    // Find the calling CPU's redistributor by its affinity
+    fn local_redist(&self) -> Option<usize> {
+        let mpidr = machine::cpu_id();
+        // GICR_TYPER packs Aff3.Aff2.Aff1.Aff0 into its top half
+        let affinity = ((mpidr >> 8) & 0xff00_0000) | (mpidr & 0xff_ffff);
+
+        let mut frame = self.redist_base;
+        while frame + 2 * GICR_FRAME_SIZE <= self.redist_base + self.redist_size {
+            let typer = unsafe { read_volatile((frame + GICR_TYPER) as *const u64) };
+            if typer >> 32 == affinity {
+                return Some(frame);
+            }
+            if typer & GICR_TYPER_LAST != 0 {
+                break;
+            }
+            frame += if typer & GICR_TYPER_VLPIS != 0 { 4 } else { 2 } * GICR_FRAME_SIZE;
+        }
+
+        None
+    }
+
+    // SGI and PPI registers of the calling CPU
+    fn local_banks(&self) -> IrqBanks {
+        let redist = self.local_redist().expect("No GICv3 redistributor for this CPU");
+        unsafe { IrqBanks::new(redist + GICR_FRAME_SIZE) }
+    }
+
+    /// Wake the calling CPU's redistributor, reset its SGIs and PPIs and
+    /// enable its interface
+    pub fn init_cpu(&mut self) {
+        let redist = self.local_redist().expect("No GICv3 redistributor for this CPU");
+        let waker = (redist + GICR_WAKER) as *mut u32;
+        unsafe {
+            write_volatile(waker, read_volatile(waker) & !GICR_WAKER_PROCESSOR_SLEEP);
+            while read_volatile(waker) & GICR_WAKER_CHILDREN_ASLEEP != 0 {}
+        }
+
+        let banks = unsafe { IrqBanks::new(redist + GICR_FRAME_SIZE) };
+        banks.reset(0, 32);
+        banks.write(IGROUPR, u32::MAX);
+
+        unsafe {
+            // Use the system register interface, then let every priority
+            // through without preemption groups
+            asm!(
+                "mrs {tmp}, icc_sre_el1",
+                "orr {tmp}, {tmp}, #1",
+                "msr icc_sre_el1, {tmp}",
+                "isb",
+                "msr icc_pmr_el1, {pmr}",
+                "msr icc_bpr1_el1, xzr",
+                "msr icc_igrpen1_el1, {enable}",
+                "isb",
+                tmp = out(reg) _,
+                pmr = in(reg) 0xffu64,
+                enable = in(reg) 1u64,
+            );
+        }
+    }
+
+    fn banks_for(&self, irq: u32) -> IrqBanks {
+        if irq < 32 { self.local_banks() } else { self.dist }
+    }
+
+    pub fn enable(&mut self, irq: u32) {
+        self.banks_for(irq).enable(irq);
+    }
+
+    pub fn disable(&mut self, irq: u32) {
+        self.banks_for(irq).disable(irq);
+    }
+
+    pub fn set_priority(&mut self, irq: u32, priority: u8) {
+        self.banks_for(irq).set_priority(irq, priority);
+    }
+
+    pub fn set_trigger(&mut self, irq: u32, trigger: Trigger) {
+        self.banks_for(irq).set_trigger(irq, trigger);
+    }
+
+    /// Acknowledge the highest priority pending interrupt, returns its ID
+    /// twice to match GICv2, where `eoi` needs more than that
+    pub fn ack(&mut self) -> (u32, u32) {
+        let iar: u64;
+        unsafe { asm!("mrs {}, icc_iar1_el1", out(reg) iar) };
+        (iar as u32, iar as u32)
+    }
+
+    pub fn eoi(&mut self, iar: u32) {
+        unsafe { asm!("msr icc_eoir1_el1, {}", in(reg) iar as u64) };
+    }
+}
+
+/// Send SGI `sgi` to every CPU but the calling one, needs no `GicV3` since
+/// it only goes through the calling CPU's interface
+pub fn send_sgi_to_others(sgi: u32) {
+    let value = ICC_SGI1R_IRM | (sgi as u64) << ICC_SGI1R_INTID_SHIFT;
+    unsafe {
+        // Make our writes visible to whoever handles it
+        asm!("dsb ishst", "msr icc_sgi1r_el1, {}", "isb", in(reg) value);
+    }
+}
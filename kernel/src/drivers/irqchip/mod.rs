@@ -0,0 +1,241 @@
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use devtree::{DevTree, DevTreeNode};
+use sync::SpinLockIrq;
+
+use crate::mm::paging;
+
+use self::gicv2::GicV2;
+use self::gicv3::GicV3;
+
+mod banks;
+pub mod gicv2;
+pub mod gicv3;
+
+// Interrupt IDs from here on are special, 1023 meaning nothing is pending
+pub const MAX_IRQS: usize = 1020;
+
+// First PPI and SPI in the GIC's ID space, device tree specifiers count
+// from them
+const PPI_BASE: u32 = 16;
+pub const MAX_SGIS: u32 = 16;
+const SPI_BASE: u32 = 32;
+
+const GICV2_COMPATIBLE: &[&str] = &["arm,cortex-a15-gic", "arm,gic-400", "arm,cortex-a9-gic"];
+const GICV3_COMPATIBLE: &[&str] = &["arm,gic-v3"];
+
+pub type IrqHandler = fn(irq: u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Edge,
+    Level,
+}
+
+// An entry of a node's `interrupts`, as a GIC interrupt ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupt {
+    pub irq: u32,
+    pub trigger: Trigger,
+}
+
+impl Interrupt {
+    /// Decode entry `index` of `node`'s `interrupts`, in the GIC binding's
+    /// `<type number flags>` format
+    pub fn from_node(node: &DevTreeNode, index: usize) -> Option<Self> {
+        let mut cells = node.property("interrupts")?.cells();
+        cells.take_cells(index * 3)?;
+        let entry = cells.take_cells(3)?;
+
+        let irq = match entry.get(0)? {
+            0 => SPI_BASE + entry.get(1)?,
+            1 => PPI_BASE + entry.get(1)?,
+            _ => return None,
+        };
+        // Low flag bits are the trigger type, 1 and 2 being the edges
+        let trigger = if entry.get(2)? & 0b0011 != 0 { Trigger::Edge } else { Trigger::Level };
+
+        Some(Interrupt { irq, trigger })
+    }
+}
+
+enum Gic {
+    V2(GicV2),
+    V3(GicV3),
+}
+
+impl Gic {
+    fn enable(&mut self, irq: u32) {
+        match self {
+            Gic::V2(gic) => gic.enable(irq),
+            Gic::V3(gic) => gic.enable(irq),
+        }
+    }
+
+    fn disable(&mut self, irq: u32) {
+        match self {
+            Gic::V2(gic) => gic.disable(irq),
+            Gic::V3(gic) => gic.disable(irq),
+        }
+    }
+
+    fn set_priority(&mut self, irq: u32, priority: u8) {
+        match self {
+            Gic::V2(gic) => gic.set_priority(irq, priority),
+            Gic::V3(gic) => gic.set_priority(irq, priority),
+        }
+    }
+
+    fn set_trigger(&mut self, irq: u32, trigger: Trigger) {
+        match self {
+            Gic::V2(gic) => gic.set_trigger(irq, trigger),
+            Gic::V3(gic) => gic.set_trigger(irq, trigger),
+        }
+    }
+
+    fn ack(&mut self) -> (u32, u32) {
+        match self {
+            Gic::V2(gic) => gic.ack(),
+            Gic::V3(gic) => gic.ack(),
+        }
+    }
+
+    fn eoi(&mut self, iar: u32) {
+        match self {
+            Gic::V2(gic) => gic.eoi(iar),
+            Gic::V3(gic) => gic.eoi(iar),
+        }
+    }
+}
+
+// Global interrupt controller and handler table
+//
+// Locked separately so a handler can enable or disable interrupts while the
+// IRQ path has its entry. Handlers are stored before their interrupt gets
+// enabled, so that path never finds one missing.
+static GIC: SpinLockIrq<Option<Gic>> = SpinLockIrq::new(None);
+static HANDLERS: SpinLockIrq<[Option<IrqHandler>; MAX_IRQS]> = SpinLockIrq::new([None; MAX_IRQS]);
+
+// What `send_sgi_to_others` needs, kept out of GIC's lock so a panicking
+// CPU can stop the others whoever holds it. The version is 0 until `init`
+// found a GIC.
+static SGI_GIC_VERSION: AtomicU8 = AtomicU8::new(0);
+static SGI_GICV2_DIST: AtomicUsize = AtomicUsize::new(0);
+
+fn with_gic<R>(f: impl FnOnce(&mut Gic) -> R) -> Option<R> {
+    GIC.lock().as_mut().map(f)
+}
+
+/// Probe the device tree for a GICv3 or GICv2 and bring it up on the calling
+/// CPU, returns whether one was found
+pub fn init(devtree: &DevTree) -> bool {
+    let root = devtree.root();
+    let (address_cells, size_cells) = (root.address_cells(), root.size_cells());
+
+    let gic = if let Some(node) = GICV3_COMPATIBLE.iter().find_map(|c| devtree.find_compatible(c)) {
+        let mut reg = node.reg(address_cells, size_cells);
+        let (Some((dist, dist_size)), Some((redist, redist_size))) = (reg.next(), reg.next()) else {
+            return false;
+        };
+        paging::map_device(dist as usize, dist_size as usize);
+        paging::map_device(redist as usize, redist_size as usize);
+        let mut gic = unsafe { GicV3::new(dist as usize, redist as usize, redist_size as usize) };
+        gic.init();
+        SGI_GIC_VERSION.store(3, Ordering::Relaxed);
+        Gic::V3(gic)
+    } else if let Some(node) = GICV2_COMPATIBLE.iter().find_map(|c| devtree.find_compatible(c)) {
+        let mut reg = node.reg(address_cells, size_cells);
+        let (Some((dist, dist_size)), Some((cpu, cpu_size))) = (reg.next(), reg.next()) else {
+            return false;
+        };
+        paging::map_device(dist as usize, dist_size as usize);
+        paging::map_device(cpu as usize, cpu_size as usize);
+        let mut gic = unsafe { GicV2::new(dist as usize, cpu as usize) };
+        gic.init();
+        SGI_GICV2_DIST.store(dist as usize, Ordering::Relaxed);
+        SGI_GIC_VERSION.store(2, Ordering::Relaxed);
+        Gic::V2(gic)
+    } else {
+        return false;
+    };
+
+    *GIC.lock() = Some(gic);
+    true
+}
+
+/// Bring the calling secondary CPU's interface up, `init` already did the
+/// boot CPU's
+pub fn init_cpu() {
+    with_gic(|gic| match gic {
+        Gic::V2(gic) => gic.init_cpu(),
+        Gic::V3(gic) => gic.init_cpu(),
+    });
+}
+
+/// Install `handler` for `irq` and enable it, replacing any previous one
+pub fn register_irq(irq: u32, handler: IrqHandler) {
+    assert!((irq as usize) < MAX_IRQS, "IRQ {} out of range", irq);
+    HANDLERS.lock()[irq as usize] = Some(handler);
+    enable_irq(irq);
+}
+
+/// Like `register_irq`, also configuring how the line signals
+pub fn register_interrupt(interrupt: Interrupt, handler: IrqHandler) {
+    set_trigger(interrupt.irq, interrupt.trigger);
+    register_irq(interrupt.irq, handler);
+}
+
+pub fn enable_irq(irq: u32) {
+    with_gic(|gic| gic.enable(irq));
+}
+
+pub fn disable_irq(irq: u32) {
+    with_gic(|gic| gic.disable(irq));
+}
+
+/// Lower values are more urgent, only the top bits may be implemented
+pub fn set_priority(irq: u32, priority: u8) {
+    with_gic(|gic| gic.set_priority(irq, priority));
+}
+
+pub fn set_trigger(irq: u32, trigger: Trigger) {
+    with_gic(|gic| gic.set_trigger(irq, trigger));
+}
+
+/// Raise SGI `sgi` on every CPU but the calling one, without taking any
+/// lock. Does nothing before `init`.
+pub fn send_sgi_to_others(sgi: u32) {
+    assert!(sgi < MAX_SGIS, "SGI {} out of range", sgi);
+    match SGI_GIC_VERSION.load(Ordering::Relaxed) {
+        2 => unsafe { gicv2::send_sgi_to_others(SGI_GICV2_DIST.load(Ordering::Relaxed), sgi) },
+        3 => gicv3::send_sgi_to_others(sgi),
+        _ => {}
+    }
+}
+
+/// Dispatch every pending interrupt to its handler, called from the IRQ
+/// vector
+pub fn handle_irq() {
+    loop {
+        let Some((irq, iar)) = with_gic(|gic| gic.ack()) else {
+            return;
+        };
+        if irq as usize >= MAX_IRQS {
+            return;
+        }
+
+        // Neither lock is held while the handler runs, so it can enable or
+        // disable interrupts itself
+        let handler = HANDLERS.lock()[irq as usize];
+        match handler {
+            Some(handler) => handler(irq),
+            None => {
+                println!("Disabling unexpected IRQ {}", irq);
+                disable_irq(irq);
+            }
+        }
+
+        with_gic(|gic| gic.eoi(iar));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod arch_timer;
+pub mod irqchip;
+pub mod pl011;
@@ -0,0 +1,155 @@
+use core::fmt;
+use core::ptr::{read_volatile, write_volatile};
+
+use devtree::{DevTree, DevTreeNode};
+
+// Register offsets
+const UARTDR: usize = 0x00;
+const UARTFR: usize = 0x18;
+const UARTIBRD: usize = 0x24;
+const UARTFBRD: usize = 0x28;
+const UARTLCR_H: usize = 0x2c;
+const UARTCR: usize = 0x30;
+const UARTIMSC: usize = 0x38;
+const UARTICR: usize = 0x44;
+
+// Flag register bits
+const FR_BUSY: u32 = 1 << 3;
+const FR_RXFE: u32 = 1 << 4;
+const FR_TXFF: u32 = 1 << 5;
+
+// Line control bits
+const LCR_H_FEN: u32 = 1 << 4;
+const LCR_H_WLEN_8: u32 = 0b11 << 5;
+
+// Control register bits
+const CR_UARTEN: u32 = 1 << 0;
+const CR_TXE: u32 = 1 << 8;
+const CR_RXE: u32 = 1 << 9;
+
+// QEMU `virt` feeds the UART from a fixed 24 MHz "apb-pclk"
+pub const DEFAULT_CLOCK_HZ: u32 = 24_000_000;
+pub const DEFAULT_BAUD: u32 = 115_200;
+
+// ARM PrimeCell UART (PL011)
+pub struct Pl011 {
+    base: usize,
+}
+
+impl Pl011 {
+    /// # Safety
+    ///
+    /// `base` must be the address of a PL011 register block that nothing
+    /// else is driving.
+    pub const unsafe fn new(base: usize) -> Self {
+        Pl011 { base }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    #[inline(always)]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    #[inline(always)]
+    fn write(&mut self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// Program 8N1 at `baud` with FIFOs enabled and interrupts masked
+    pub fn init(&mut self, clock_hz: u32, baud: u32) {
+        // Disable the UART and let the transmitter drain before touching
+        // the line settings
+        self.write(UARTCR, 0);
+        while self.read(UARTFR) & FR_BUSY != 0 {}
+
+        // Clearing FEN flushes the FIFOs
+        self.write(UARTLCR_H, 0);
+
+        // Divisor in 1/64ths: clock / (16 * baud) * 64
+        let divisor = ((clock_hz as u64 * 4 + baud as u64 / 2) / baud as u64) as u32;
+        self.write(UARTIBRD, divisor >> 6);
+        self.write(UARTFBRD, divisor & 0x3f);
+
+        // The write to LCR_H latches the divisor
+        self.write(UARTLCR_H, LCR_H_WLEN_8 | LCR_H_FEN);
+
+        self.write(UARTIMSC, 0);
+        self.write(UARTICR, 0x7ff);
+
+        self.write(UARTCR, CR_UARTEN | CR_TXE | CR_RXE);
+    }
+
+    /// Send a byte, waiting for room in the FIFO
+    pub fn putc(&mut self, byte: u8) {
+        while self.read(UARTFR) & FR_TXFF != 0 {
+            core::hint::spin_loop();
+        }
+        self.write(UARTDR, byte as u32);
+    }
+
+    /// Take a received byte, if there is one
+    pub fn getc(&mut self) -> Option<u8> {
+        if self.read(UARTFR) & FR_RXFE != 0 {
+            return None;
+        }
+        Some(self.read(UARTDR) as u8)
+    }
+
+    /// Wait until everything written has left the shift register
+    pub fn flush(&mut self) {
+        while self.read(UARTFR) & FR_BUSY != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl fmt::Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.putc(b'\r');
+            }
+            self.putc(byte);
+        }
+        Ok(())
+    }
+}
+
+// Where and how to drive a PL011 found in the device tree
+pub struct Pl011Config {
+    pub base: usize,
+    pub clock_hz: u32,
+    pub baud: u32,
+}
+
+impl Pl011Config {
+    /// Read the register block and clock of `node`. The node is expected to
+    /// sit right under the root, as it does on QEMU `virt`.
+    pub fn from_node(devtree: &DevTree, node: &DevTreeNode<'static>, baud: Option<u32>) -> Option<Self> {
+        if !node.is_compatible("arm,pl011") || !node.is_enabled() {
+            return None;
+        }
+
+        let root = devtree.root();
+        let (base, _) = node.reg(root.address_cells(), root.size_cells()).next()?;
+
+        // "uartclk" is the baud clock, "apb_pclk" only clocks the registers
+        let clock_hz = node
+            .clock(devtree, "uartclk")
+            .or_else(|| node.clocks(devtree).next())
+            .and_then(|clock| clock.ok())
+            .and_then(|clock| clock.provider.property("clock-frequency"))
+            .and_then(|freq| freq.as_u32())
+            .unwrap_or(DEFAULT_CLOCK_HZ);
+
+        Some(Pl011Config {
+            base: base as usize,
+            clock_hz,
+            baud: baud.unwrap_or(DEFAULT_BAUD),
+        })
+    }
+}
@@ -0,0 +1,323 @@
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::Ordering;
+
+use crate::drivers::irqchip;
+use crate::process;
+use crate::smp;
+use crate::task;
+
+// Register state saved on exception entry by arch/aarch64/vectors.S, which
+// hardcodes this layout
+#[repr(C)]
+pub struct TrapFrame {
+    pub x: [u64; 31],
+    /// Stack pointer of the interrupted code, SP_EL0 when coming from EL0
+    pub sp: u64,
+    pub elr: u64,
+    pub spsr: u64,
+    pub esr: u64,
+    pub far: u64,
+    pub fpcr: u64,
+    pub fpsr: u64,
+    pub q: [u128; 32],
+}
+
+const _: () = assert!(size_of::<TrapFrame>() == 816);
+
+impl TrapFrame {
+    /// State to enter EL0 with at `entry` on the stack at `sp`, with IRQs
+    /// unmasked
+    pub fn user(entry: u64, sp: u64) -> TrapFrame {
+        TrapFrame {
+            x: [0; 31],
+            sp,
+            elr: entry,
+            // EL0t with DAIF clear
+            spsr: 0,
+            esr: 0,
+            far: 0,
+            fpcr: 0,
+            fpsr: 0,
+            q: [0; 32],
+        }
+    }
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, x) in self.x.iter().enumerate() {
+            write!(f, "  x{:02}: {:016x}", i, x)?;
+            if i % 4 == 3 {
+                writeln!(f)?;
+            }
+        }
+        writeln!(f, "   sp: {:016x}", self.sp)?;
+        writeln!(f, "  elr: {:016x} spsr: {:016x}", self.elr, self.spsr)?;
+        write!(f, "  esr: {:016x}  far: {:016x}", self.esr, self.far)
+    }
+}
+
+// Which of the four groups of vectors was taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionSource {
+    CurrentElSp0,
+    CurrentElSpx,
+    LowerEl64,
+    LowerEl32,
+}
+
+impl fmt::Display for ExceptionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExceptionSource::CurrentElSp0 => "current EL with SP0",
+            ExceptionSource::CurrentElSpx => "current EL with SPx",
+            ExceptionSource::LowerEl64 => "lower EL (AArch64)",
+            ExceptionSource::LowerEl32 => "lower EL (AArch32)",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionKind {
+    Sync,
+    Irq,
+    Fiq,
+    SError,
+}
+
+// Fault status code of instruction and data aborts (IFSC/DFSC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultStatus {
+    AddressSize { level: u8 },
+    Translation { level: u8 },
+    AccessFlag { level: u8 },
+    Permission { level: u8 },
+    SyncExternal,
+    Alignment,
+    TlbConflict,
+    Other(u8),
+}
+
+impl FaultStatus {
+    fn decode(fsc: u64) -> Self {
+        let level = (fsc & 0b11) as u8;
+        match fsc & 0x3f {
+            0b0000_00..=0b0000_11 => FaultStatus::AddressSize { level },
+            0b0001_00..=0b0001_11 => FaultStatus::Translation { level },
+            0b0010_00..=0b0010_11 => FaultStatus::AccessFlag { level },
+            0b0011_00..=0b0011_11 => FaultStatus::Permission { level },
+            0b010000 => FaultStatus::SyncExternal,
+            0b100001 => FaultStatus::Alignment,
+            0b110000 => FaultStatus::TlbConflict,
+            other => FaultStatus::Other(other as u8),
+        }
+    }
+}
+
+impl fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultStatus::AddressSize { level } => write!(f, "address size fault at level {}", level),
+            FaultStatus::Translation { level } => write!(f, "translation fault at level {}", level),
+            FaultStatus::AccessFlag { level } => write!(f, "access flag fault at level {}", level),
+            FaultStatus::Permission { level } => write!(f, "permission fault at level {}", level),
+            FaultStatus::SyncExternal => f.write_str("synchronous external abort"),
+            FaultStatus::Alignment => f.write_str("alignment fault"),
+            FaultStatus::TlbConflict => f.write_str("TLB conflict"),
+            FaultStatus::Other(fsc) => write!(f, "fault status {:#04x}", fsc),
+        }
+    }
+}
+
+// Cause of a synchronous exception, decoded from ESR_EL1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionClass {
+    /// Undefined instruction, or anything else the CPU has no class for
+    Unknown,
+    /// Trapped WFI or WFE
+    WfiWfe,
+    /// Access to SIMD or floating point registers while they're disabled
+    SimdFpAccess,
+    IllegalState,
+    Svc { imm: u16 },
+    /// Trapped MSR, MRS or system instruction
+    SystemRegister,
+    InstructionAbort { lower_el: bool, far: Option<u64>, status: FaultStatus },
+    PcAlignment { far: u64 },
+    DataAbort { lower_el: bool, far: Option<u64>, write: bool, status: FaultStatus },
+    SpAlignment,
+    SError,
+    Breakpoint,
+    SoftwareStep,
+    Watchpoint { far: u64 },
+    Brk { imm: u16 },
+    Other(u8),
+}
+
+impl ExceptionClass {
+    pub fn decode(esr: u64, far: u64) -> Self {
+        let ec = ((esr >> 26) & 0x3f) as u8;
+        let iss = esr & 0x1ff_ffff;
+        // FnV: FAR doesn't hold a valid address
+        let abort_far = if iss & (1 << 10) == 0 { Some(far) } else { None };
+
+        match ec {
+            0x00 => ExceptionClass::Unknown,
+            0x01 => ExceptionClass::WfiWfe,
+            0x07 => ExceptionClass::SimdFpAccess,
+            0x0e => ExceptionClass::IllegalState,
+            0x15 => ExceptionClass::Svc { imm: iss as u16 },
+            0x18 => ExceptionClass::SystemRegister,
+            0x20 | 0x21 => ExceptionClass::InstructionAbort {
+                lower_el: ec == 0x20,
+                far: abort_far,
+                status: FaultStatus::decode(iss),
+            },
+            0x22 => ExceptionClass::PcAlignment { far },
+            0x24 | 0x25 => ExceptionClass::DataAbort {
+                lower_el: ec == 0x24,
+                far: abort_far,
+                write: iss & (1 << 6) != 0,
+                status: FaultStatus::decode(iss),
+            },
+            0x26 => ExceptionClass::SpAlignment,
+            0x2f => ExceptionClass::SError,
+            0x30 | 0x31 => ExceptionClass::Breakpoint,
+            0x32 | 0x33 => ExceptionClass::SoftwareStep,
+            0x34 | 0x35 => ExceptionClass::Watchpoint { far },
+            0x3c => ExceptionClass::Brk { imm: iss as u16 },
+            other => ExceptionClass::Other(other),
+        }
+    }
+}
+
+struct MaybeAddress(Option<u64>);
+
+impl fmt::Display for MaybeAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(address) => write!(f, "{:#x}", address),
+            None => f.write_str("an unknown address"),
+        }
+    }
+}
+
+impl fmt::Display for ExceptionClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ExceptionClass::Unknown => f.write_str("undefined instruction"),
+            ExceptionClass::WfiWfe => f.write_str("trapped WFI/WFE"),
+            ExceptionClass::SimdFpAccess => f.write_str("SIMD/FP access while disabled"),
+            ExceptionClass::IllegalState => f.write_str("illegal execution state"),
+            ExceptionClass::Svc { imm } => write!(f, "SVC #{:#x}", imm),
+            ExceptionClass::SystemRegister => f.write_str("trapped system register access"),
+            ExceptionClass::InstructionAbort { lower_el, far, status } => write!(
+                f,
+                "instruction abort from {} EL at {}: {}",
+                if lower_el { "a lower" } else { "the current" },
+                MaybeAddress(far),
+                status
+            ),
+            ExceptionClass::PcAlignment { far } => write!(f, "PC alignment fault at {:#x}", far),
+            ExceptionClass::DataAbort { lower_el, far, write, status } => write!(
+                f,
+                "data abort from {} EL {} {}: {}",
+                if lower_el { "a lower" } else { "the current" },
+                if write { "writing" } else { "reading" },
+                MaybeAddress(far),
+                status
+            ),
+            ExceptionClass::SpAlignment => f.write_str("SP alignment fault"),
+            ExceptionClass::SError => f.write_str("SError"),
+            ExceptionClass::Breakpoint => f.write_str("hardware breakpoint"),
+            ExceptionClass::SoftwareStep => f.write_str("software step"),
+            ExceptionClass::Watchpoint { far } => write!(f, "watchpoint hit at {:#x}", far),
+            ExceptionClass::Brk { imm } => write!(f, "BRK #{:#x}", imm),
+            ExceptionClass::Other(ec) => write!(f, "exception class {:#04x}", ec),
+        }
+    }
+}
+
+unsafe extern "C" {
+    static exception_vectors: u8;
+}
+
+/// Point VBAR_EL1 at the vector table, SIMD must be enabled already since
+/// every exception saves its registers
+pub fn init() {
+    unsafe {
+        asm!(
+            "msr vbar_el1, {}",
+            "isb",
+            in(reg) &raw const exception_vectors,
+        );
+    }
+}
+
+fn decode_vector(vector: u64) -> (ExceptionSource, ExceptionKind) {
+    let source = match vector >> 2 {
+        0 => ExceptionSource::CurrentElSp0,
+        1 => ExceptionSource::CurrentElSpx,
+        2 => ExceptionSource::LowerEl64,
+        _ => ExceptionSource::LowerEl32,
+    };
+    let kind = match vector & 0b11 {
+        0 => ExceptionKind::Sync,
+        1 => ExceptionKind::Irq,
+        2 => ExceptionKind::Fiq,
+        _ => ExceptionKind::SError,
+    };
+    (source, kind)
+}
+
+// Called by the vectors with the saved state and the vector's index
+#[unsafe(no_mangle)]
+extern "C" fn handle_exception(frame: &mut TrapFrame, vector: u64) {
+    let (source, kind) = decode_vector(vector);
+
+    match kind {
+        ExceptionKind::Sync if source == ExceptionSource::LowerEl64 => {
+            let class = ExceptionClass::decode(frame.esr, frame.far);
+            process::handle_sync(frame, class);
+        }
+        ExceptionKind::Sync => {
+            let class = ExceptionClass::decode(frame.esr, frame.far);
+            if let ExceptionClass::DataAbort { far: Some(far), .. } = class
+                && let Some((id, name)) = task::guard_page_owner(far)
+            {
+                println!("\nStack overflow in thread {} ({})", id, name);
+            }
+            unhandled(frame, source, class);
+        }
+        ExceptionKind::Irq => {
+            irqchip::handle_irq();
+            task::preempt();
+        }
+        ExceptionKind::Fiq => unhandled(frame, source, "FIQ"),
+        ExceptionKind::SError => unhandled(frame, source, "SError"),
+    }
+}
+
+// Called by the vectors instead of `handle_exception` when the running stack
+// had no room left for the frame, on this CPU's overflow stack
+#[unsafe(no_mangle)]
+extern "C" fn handle_stack_overflow(frame: &mut TrapFrame, vector: u64) -> ! {
+    let (source, kind) = decode_vector(vector);
+    let limit = smp::current().stack_limit.load(Ordering::Relaxed);
+    match task::guard_page_owner(limit as u64 - 1) {
+        Some((id, name)) => println!("\nStack overflow in thread {} ({})", id, name),
+        None => println!("\nStack overflow below {:#x}", limit),
+    }
+
+    match kind {
+        ExceptionKind::Sync => unhandled(frame, source, ExceptionClass::decode(frame.esr, frame.far)),
+        _ => unhandled(frame, source, format_args!("{:?}", kind)),
+    }
+}
+
+fn unhandled(frame: &TrapFrame, source: ExceptionSource, what: impl fmt::Display) -> ! {
+    println!("\nUnhandled exception from {}: {}", source, what);
+    println!("{}", frame);
+    panic!("Unhandled exception at {:#x}", frame.elr);
+}
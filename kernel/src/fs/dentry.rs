@@ -0,0 +1,176 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use sync::Mutex;
+
+use super::{FileType, FsError, Inode};
+
+// A name in the tree, tying a path component to its inode
+//
+// Children are cached for as long as their parent lives and only parents
+// are held weakly, so everything ever looked up stays around until it's
+// unlinked. Mounting puts the root dentry of the new filesystem in
+// `mounted`, and lookups step through to it.
+pub struct Dentry {
+    name: String,
+    inode: Inode,
+    /// Files can't change type, so it's kept here rather than asked for
+    kind: FileType,
+    /// None for the root of a filesystem
+    parent: Option<Weak<Dentry>>,
+    /// For the root of a mounted filesystem, the dentry it's mounted over
+    covers: Option<Weak<Dentry>>,
+    children: Mutex<BTreeMap<String, Arc<Dentry>>>,
+    mounted: Mutex<Option<Arc<Dentry>>>,
+}
+
+impl Dentry {
+    fn new(name: &str, inode: Inode, parent: Option<Weak<Dentry>>, covers: Option<Weak<Dentry>>) -> Result<Arc<Dentry>, FsError> {
+        let kind = inode.metadata()?.kind;
+        Ok(Arc::new(Dentry {
+            name: String::from(name),
+            inode,
+            kind,
+            parent,
+            covers,
+            children: Mutex::new(BTreeMap::new()),
+            mounted: Mutex::new(None),
+        }))
+    }
+
+    /// Dentry for the root of a filesystem, mounted over `covers` unless
+    /// it's the root of the tree
+    pub(super) fn new_root(inode: Inode, covers: Option<&Arc<Dentry>>) -> Result<Arc<Dentry>, FsError> {
+        Dentry::new("", inode, None, covers.map(Arc::downgrade))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> &Inode {
+        &self.inode
+    }
+
+    pub fn kind(&self) -> FileType {
+        self.kind
+    }
+
+    /// The entry `name` in this directory, or what's mounted over it
+    pub(super) fn child(self: &Arc<Self>, name: &str) -> Result<Arc<Dentry>, FsError> {
+        let mut children = self.children.lock();
+        let child = match children.get(name) {
+            Some(child) => child.clone(),
+            None => {
+                let inode = self.inode.lookup(name)?;
+                let child = Dentry::new(name, inode, Some(Arc::downgrade(self)), None)?;
+                children.insert(String::from(name), child.clone());
+                child
+            }
+        };
+        drop(children);
+        Ok(child.follow_mounts())
+    }
+
+    fn follow_mounts(self: Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = self;
+        loop {
+            let mounted = dentry.mounted.lock().clone();
+            match mounted {
+                Some(root) => dentry = root,
+                None => return dentry,
+            }
+        }
+    }
+
+    // Out of any mounted roots this is, to the dentry in the parent
+    // filesystem
+    fn uncovered(self: &Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = self.clone();
+        while let Some(covered) = dentry.covers.as_ref().and_then(Weak::upgrade) {
+            dentry = covered;
+        }
+        dentry
+    }
+
+    /// What ".." leads to, the root being its own parent
+    pub fn parent(self: &Arc<Self>) -> Arc<Dentry> {
+        let dentry = self.uncovered();
+        match dentry.parent.as_ref().and_then(Weak::upgrade) {
+            Some(parent) => parent,
+            None => dentry,
+        }
+    }
+
+    /// Absolute path to this dentry
+    pub fn path(self: &Arc<Self>) -> String {
+        let mut names = Vec::new();
+        let mut dentry = self.uncovered();
+        while let Some(parent) = dentry.parent.as_ref().and_then(Weak::upgrade) {
+            names.push(dentry.name.clone());
+            dentry = parent.uncovered();
+        }
+
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push('/');
+            path.push_str(name);
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        path
+    }
+
+    /// Mount `root` here, only one filesystem at a time
+    pub(super) fn set_mounted(&self, root: Arc<Dentry>) -> Result<(), FsError> {
+        let mut mounted = self.mounted.lock();
+        if mounted.is_some() {
+            return Err(FsError::Busy);
+        }
+        *mounted = Some(root);
+        Ok(())
+    }
+
+    fn check_free(&self, name: &str) -> Result<(), FsError> {
+        match self.inode.lookup(name) {
+            Ok(_) => Err(FsError::Exists),
+            Err(FsError::NotFound) => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub(super) fn create(self: &Arc<Self>, name: &str, kind: FileType, mode: u16) -> Result<Arc<Dentry>, FsError> {
+        let mut children = self.children.lock();
+        self.check_free(name)?;
+        let inode = self.inode.create(name, kind, mode)?;
+        let child = Dentry::new(name, inode, Some(Arc::downgrade(self)), None)?;
+        children.insert(String::from(name), child.clone());
+        Ok(child)
+    }
+
+    pub(super) fn symlink(self: &Arc<Self>, name: &str, target: &str) -> Result<Arc<Dentry>, FsError> {
+        let mut children = self.children.lock();
+        self.check_free(name)?;
+        let inode = self.inode.symlink(name, target)?;
+        let child = Dentry::new(name, inode, Some(Arc::downgrade(self)), None)?;
+        children.insert(String::from(name), child.clone());
+        Ok(child)
+    }
+
+    /// Remove `name` from the directory and the cache, unless something's
+    /// mounted on it
+    pub(super) fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let mut children = self.children.lock();
+        if let Some(child) = children.get(name)
+            && child.mounted.lock().is_some()
+        {
+            return Err(FsError::Busy);
+        }
+        self.inode.unlink(name)?;
+        children.remove(name);
+        Ok(())
+    }
+}
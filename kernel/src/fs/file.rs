@@ -0,0 +1,276 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use sync::Mutex;
+
+use super::{Dentry, DirEntry, FileType, FsError, Metadata};
+use crate::{console, task};
+
+// Most files a process can have open at once
+const MAX_FILES: usize = 1024;
+
+// How often a blocked console read checks for input, there's no RX
+// interrupt yet
+const READ_POLL: Duration = Duration::from_millis(10);
+
+// How a file was opened, the `O_*` flags that matter past `open`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenFlags {
+    pub read: bool,
+    pub write: bool,
+    pub append: bool,
+    pub create: bool,
+    /// With `create`, fail if the file exists
+    pub exclusive: bool,
+    pub truncate: bool,
+    /// Fail unless it's a directory
+    pub directory: bool,
+    /// Don't follow a symlink as the last component
+    pub no_follow: bool,
+}
+
+impl OpenFlags {
+    pub fn read_only() -> OpenFlags {
+        OpenFlags {
+            read: true,
+            ..OpenFlags::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+enum Backing {
+    Console,
+    Dentry(Arc<Dentry>),
+}
+
+// A file someone has open, shared by the descriptors that refer to it
+//
+// Directories read their entries one at a time, counting "." and ".."
+// before whatever the filesystem has, and the offset is the index of the
+// next one.
+pub struct OpenFile {
+    backing: Backing,
+    flags: OpenFlags,
+    offset: Mutex<u64>,
+}
+
+impl OpenFile {
+    pub(super) fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> Arc<OpenFile> {
+        Arc::new(OpenFile {
+            backing: Backing::Dentry(dentry),
+            flags,
+            offset: Mutex::new(0),
+        })
+    }
+
+    /// The console, readable and writable
+    pub fn console() -> Arc<OpenFile> {
+        Arc::new(OpenFile {
+            backing: Backing::Console,
+            flags: OpenFlags {
+                read: true,
+                write: true,
+                ..OpenFlags::default()
+            },
+            offset: Mutex::new(0),
+        })
+    }
+
+    /// Where in the tree the file is, none for the console
+    pub fn dentry(&self) -> Option<&Arc<Dentry>> {
+        match &self.backing {
+            Backing::Console => None,
+            Backing::Dentry(dentry) => Some(dentry),
+        }
+    }
+
+    pub fn metadata(&self) -> Result<Metadata, FsError> {
+        match &self.backing {
+            Backing::Console => Ok(Metadata {
+                ino: 0,
+                kind: FileType::CharDevice,
+                mode: 0o620,
+                size: 0,
+                nlink: 1,
+            }),
+            Backing::Dentry(dentry) => dentry.inode().metadata(),
+        }
+    }
+
+    /// Read from the current offset. The console waits for input and
+    /// returns up to a line.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        if !self.flags.read {
+            return Err(FsError::BadDescriptor);
+        }
+        match &self.backing {
+            Backing::Console => Ok(read_console(buf)),
+            Backing::Dentry(dentry) => {
+                if dentry.kind() == FileType::Directory {
+                    return Err(FsError::IsDirectory);
+                }
+                let mut offset = self.offset.lock();
+                let read = dentry.inode().read_at(*offset, buf)?;
+                *offset += read as u64;
+                Ok(read)
+            }
+        }
+    }
+
+    /// Write at the current offset, or the end when appending
+    pub fn write(&self, data: &[u8]) -> Result<usize, FsError> {
+        if !self.flags.write {
+            return Err(FsError::BadDescriptor);
+        }
+        match &self.backing {
+            Backing::Console => {
+                console::write_bytes(data);
+                Ok(data.len())
+            }
+            Backing::Dentry(dentry) => {
+                let mut offset = self.offset.lock();
+                if self.flags.append {
+                    *offset = dentry.inode().metadata()?.size;
+                }
+                let written = dentry.inode().write_at(*offset, data)?;
+                *offset += written as u64;
+                Ok(written)
+            }
+        }
+    }
+
+    /// Move the offset, returns where it ends up
+    pub fn seek(&self, to: SeekFrom) -> Result<u64, FsError> {
+        let Backing::Dentry(dentry) = &self.backing else {
+            return Err(FsError::NotSeekable);
+        };
+
+        let mut offset = self.offset.lock();
+        let new = match to {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => dentry.inode().metadata()?.size.checked_add_signed(delta),
+        };
+        *offset = new.ok_or(FsError::InvalidArgument)?;
+        Ok(*offset)
+    }
+
+    /// The next entry of a directory, none at the end
+    pub fn read_dir(&self) -> Result<Option<DirEntry>, FsError> {
+        let dentry = match &self.backing {
+            Backing::Dentry(dentry) if dentry.kind() == FileType::Directory => dentry,
+            _ => return Err(FsError::NotDirectory),
+        };
+
+        let mut offset = self.offset.lock();
+        let entry = match *offset {
+            0 => Some(dot_entry(".", dentry)),
+            1 => Some(dot_entry("..", &dentry.parent())),
+            index => dentry.inode().read_dir(index as usize - 2)?,
+        };
+        if entry.is_some() {
+            *offset += 1;
+        }
+        Ok(entry)
+    }
+
+    /// Step back to the entry before, for when it didn't fit
+    pub fn unread_dir(&self) {
+        let mut offset = self.offset.lock();
+        *offset = offset.saturating_sub(1);
+    }
+}
+
+fn dot_entry(name: &str, dentry: &Dentry) -> DirEntry {
+    DirEntry {
+        ino: dentry.inode().ino(),
+        kind: FileType::Directory,
+        name: String::from(name),
+    }
+}
+
+// Wait for console input and return what's there, up to a line. Echoes it
+// back like a terminal would, without any line editing.
+fn read_console(buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+
+    let first = loop {
+        if let Some(byte) = console::read_byte() {
+            break byte;
+        }
+        task::sleep(READ_POLL);
+    };
+
+    let mut len = 0;
+    let mut next = Some(first);
+    while let Some(byte) = next {
+        // Serial terminals send CR for the enter key
+        let byte = if byte == b'\r' { b'\n' } else { byte };
+        buf[len] = byte;
+        len += 1;
+        if byte == b'\n' || len == buf.len() {
+            break;
+        }
+        next = console::read_byte();
+    }
+
+    console::write_bytes(&buf[..len]);
+    len
+}
+
+// A process's open files by descriptor
+#[derive(Default)]
+pub struct FdTable {
+    files: Vec<Option<Arc<OpenFile>>>,
+}
+
+impl FdTable {
+    /// Standard input, output and error all on the console
+    pub fn with_console() -> FdTable {
+        let console = OpenFile::console();
+        FdTable {
+            files: alloc::vec![Some(console.clone()), Some(console.clone()), Some(console)],
+        }
+    }
+
+    /// Give `file` the lowest free descriptor
+    pub fn insert(&mut self, file: Arc<OpenFile>) -> Result<usize, FsError> {
+        if let Some(fd) = self.files.iter().position(Option::is_none) {
+            self.files[fd] = Some(file);
+            return Ok(fd);
+        }
+        if self.files.len() == MAX_FILES {
+            return Err(FsError::TooManyFiles);
+        }
+        self.files.push(Some(file));
+        Ok(self.files.len() - 1)
+    }
+
+    pub fn get(&self, fd: usize) -> Result<Arc<OpenFile>, FsError> {
+        self.files.get(fd).cloned().flatten().ok_or(FsError::BadDescriptor)
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Result<Arc<OpenFile>, FsError> {
+        let file = self.files.get_mut(fd).and_then(Option::take).ok_or(FsError::BadDescriptor)?;
+        while let Some(None) = self.files.last() {
+            self.files.pop();
+        }
+        Ok(file)
+    }
+
+    /// Close everything
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
@@ -0,0 +1,120 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use cpio::{Archive, CpioError, Entry, EntryKind};
+
+use super::{Dentry, FileType, FsError, OpenFlags};
+
+// What came out of an archive
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Unpacked {
+    pub entries: usize,
+    /// Device nodes, FIFOs and sockets, which nothing here can stand for,
+    /// and entries that failed
+    pub skipped: usize,
+}
+
+// Hard links are told apart by the device and inode they came from
+type LinkKey = (u32, u32, u32);
+
+/// Unpack the cpio archive in `data` under `root`, replacing whatever's in
+/// the way. Entries that fail are reported and skipped, only a malformed
+/// archive stops it.
+pub fn unpack(root: &Arc<Dentry>, data: &[u8]) -> Result<Unpacked, CpioError> {
+    let mut unpacked = Unpacked::default();
+    // Names of hard links still waiting for their data, which only comes
+    // with the last of them
+    let mut links = BTreeMap::new();
+
+    for entry in Archive::new(data).entries() {
+        let entry = entry?;
+        let name = entry.name.trim_start_matches("./").trim_start_matches('/');
+        if name.is_empty() || name == "." {
+            continue;
+        }
+
+        match unpack_entry(root, name, &entry, &mut links) {
+            Ok(true) => unpacked.entries += 1,
+            Ok(false) => unpacked.skipped += 1,
+            Err(error) => {
+                println!("initramfs: {}: {}", name, error);
+                unpacked.skipped += 1;
+            }
+        }
+    }
+    Ok(unpacked)
+}
+
+// Returns whether there was anything to make
+fn unpack_entry(
+    root: &Arc<Dentry>,
+    name: &str,
+    entry: &Entry,
+    links: &mut BTreeMap<LinkKey, Vec<String>>,
+) -> Result<bool, FsError> {
+    let mode = entry.permissions();
+    match entry.kind() {
+        EntryKind::Directory => {
+            let dir = match super::lookup(root, name, false) {
+                Ok(dir) if dir.kind() == FileType::Directory => dir,
+                Ok(_) => {
+                    super::unlink(root, name)?;
+                    super::mkdir(root, name, mode)?
+                }
+                Err(FsError::NotFound) => super::mkdir(root, name, mode)?,
+                Err(error) => return Err(error),
+            };
+            dir.inode().set_mode(mode)?;
+        }
+        EntryKind::File => {
+            write_file(root, name, entry.data, mode)?;
+            // There are no hard links in tmpfs, each name gets a copy
+            if entry.nlink > 1 {
+                let names = links.entry((entry.dev.0, entry.dev.1, entry.ino)).or_default();
+                if entry.data.is_empty() {
+                    names.push(String::from(name));
+                } else {
+                    for link in names.drain(..) {
+                        write_file(root, &link, entry.data, mode)?;
+                    }
+                }
+            }
+        }
+        EntryKind::Symlink => {
+            let target = core::str::from_utf8(entry.data).map_err(|_| FsError::InvalidArgument)?;
+            remove_existing(root, name)?;
+            super::symlink(root, target, name)?;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn remove_existing(root: &Arc<Dentry>, name: &str) -> Result<(), FsError> {
+    match super::unlink(root, name) {
+        Ok(()) | Err(FsError::NotFound) => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+fn write_file(root: &Arc<Dentry>, name: &str, data: &[u8], mode: u16) -> Result<(), FsError> {
+    remove_existing(root, name)?;
+    let flags = OpenFlags {
+        write: true,
+        create: true,
+        exclusive: true,
+        ..OpenFlags::default()
+    };
+    let file = super::open(root, name, flags, mode)?;
+
+    let mut written = 0;
+    while written < data.len() {
+        match file.write(&data[written..])? {
+            0 => return Err(FsError::NoSpace),
+            count => written += count,
+        }
+    }
+    Ok(())
+}
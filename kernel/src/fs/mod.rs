@@ -0,0 +1,417 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use sync::{Mutex, Once};
+
+mod dentry;
+mod file;
+pub mod initramfs;
+pub mod tmpfs;
+
+pub use self::dentry::Dentry;
+pub use self::file::{FdTable, OpenFile, OpenFlags, SeekFrom};
+
+// Longest name in a directory, and most symlinks one lookup follows, as on
+// Linux
+const NAME_MAX: usize = 255;
+const MAX_SYMLINKS: u32 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotDirectory,
+    IsDirectory,
+    Exists,
+    /// Removing a directory that still has entries
+    NotEmpty,
+    InvalidArgument,
+    NameTooLong,
+    /// Too many symlinks followed, or one where there mustn't be any
+    SymlinkLoop,
+    /// A mount point in the way
+    Busy,
+    /// Not an open file, or not open for what was asked
+    BadDescriptor,
+    TooManyFiles,
+    NotSeekable,
+    NoSpace,
+    ReadOnly,
+    NotSupported,
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FsError::NotFound => "no such file or directory",
+            FsError::NotDirectory => "not a directory",
+            FsError::IsDirectory => "is a directory",
+            FsError::Exists => "file exists",
+            FsError::NotEmpty => "directory not empty",
+            FsError::InvalidArgument => "invalid argument",
+            FsError::NameTooLong => "file name too long",
+            FsError::SymlinkLoop => "too many levels of symbolic links",
+            FsError::Busy => "mount point busy",
+            FsError::BadDescriptor => "bad file descriptor",
+            FsError::TooManyFiles => "too many open files",
+            FsError::NotSeekable => "illegal seek",
+            FsError::NoSpace => "no space left",
+            FsError::ReadOnly => "read-only filesystem",
+            FsError::NotSupported => "operation not supported",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    pub ino: u64,
+    pub kind: FileType,
+    /// Permission bits, the low 12 of `st_mode`
+    pub mode: u16,
+    pub size: u64,
+    pub nlink: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub ino: u64,
+    pub kind: FileType,
+    pub name: String,
+}
+
+// What a filesystem driver implements
+//
+// Files are named by inode number, and the VFS takes care of everything
+// path related: names given here are single components, never "." or
+// "..", and have been checked against NAME_MAX. Drivers only need to
+// keep their own structures consistent. The ones that can't change
+// anything can leave out the default methods.
+pub trait FileSystem: Send + Sync {
+    /// Name for the mount table, like "tmpfs"
+    fn name(&self) -> &'static str;
+    fn root(&self) -> u64;
+    fn metadata(&self, ino: u64) -> Result<Metadata, FsError>;
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, FsError>;
+    /// The `index`th entry of `dir` in whatever order the driver likes,
+    /// none past the last
+    fn read_dir(&self, dir: u64, index: usize) -> Result<Option<DirEntry>, FsError>;
+    fn read(&self, ino: u64, offset: u64, buf: &mut [u8]) -> Result<usize, FsError>;
+    fn read_link(&self, ino: u64) -> Result<String, FsError>;
+
+    /// Make an empty file or directory, the VFS has checked `name` isn't
+    /// taken
+    fn create(&self, _dir: u64, _name: &str, _kind: FileType, _mode: u16) -> Result<u64, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn symlink(&self, _dir: u64, _name: &str, _target: &str) -> Result<u64, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Remove an entry, directories only if they're empty
+    fn unlink(&self, _dir: u64, _name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn write(&self, _ino: u64, _offset: u64, _data: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&self, _ino: u64, _size: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn set_mode(&self, _ino: u64, _mode: u16) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+// One mounted instance of a filesystem
+pub struct Superblock {
+    dev: u32,
+    fs: Arc<dyn FileSystem>,
+}
+
+static NEXT_DEV: AtomicU32 = AtomicU32::new(1);
+
+impl Superblock {
+    fn new(fs: Arc<dyn FileSystem>) -> Arc<Superblock> {
+        let dev = NEXT_DEV.fetch_add(1, Ordering::Relaxed);
+        Arc::new(Superblock { dev, fs })
+    }
+
+    fn root(self: &Arc<Self>) -> Inode {
+        Inode {
+            sb: self.clone(),
+            ino: self.fs.root(),
+        }
+    }
+}
+
+// A file on some superblock, forwarding to its driver
+#[derive(Clone)]
+pub struct Inode {
+    sb: Arc<Superblock>,
+    ino: u64,
+}
+
+impl Inode {
+    /// Device number of the superblock, for `st_dev`
+    pub fn dev(&self) -> u32 {
+        self.sb.dev
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    pub fn metadata(&self) -> Result<Metadata, FsError> {
+        self.sb.fs.metadata(self.ino)
+    }
+
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.sb.fs.read(self.ino, offset, buf)
+    }
+
+    pub fn write_at(&self, offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        self.sb.fs.write(self.ino, offset, data)
+    }
+
+    pub fn truncate(&self, size: u64) -> Result<(), FsError> {
+        self.sb.fs.truncate(self.ino, size)
+    }
+
+    pub fn set_mode(&self, mode: u16) -> Result<(), FsError> {
+        self.sb.fs.set_mode(self.ino, mode)
+    }
+
+    pub fn read_link(&self) -> Result<String, FsError> {
+        self.sb.fs.read_link(self.ino)
+    }
+
+    fn read_dir(&self, index: usize) -> Result<Option<DirEntry>, FsError> {
+        self.sb.fs.read_dir(self.ino, index)
+    }
+
+    fn with_ino(&self, ino: u64) -> Inode {
+        Inode { sb: self.sb.clone(), ino }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Inode, FsError> {
+        Ok(self.with_ino(self.sb.fs.lookup(self.ino, name)?))
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u16) -> Result<Inode, FsError> {
+        Ok(self.with_ino(self.sb.fs.create(self.ino, name, kind, mode)?))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Inode, FsError> {
+        Ok(self.with_ino(self.sb.fs.symlink(self.ino, name, target)?))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        self.sb.fs.unlink(self.ino, name)
+    }
+}
+
+// Entry of the mount table
+pub struct Mount {
+    pub path: String,
+    pub fs_name: &'static str,
+}
+
+static ROOT: Once<Arc<Dentry>> = Once::new();
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// Make `fs` the root of the tree, only once
+pub fn mount_root(fs: Arc<dyn FileSystem>) {
+    let fs_name = fs.name();
+    let mut first = false;
+    ROOT.call_once(|| {
+        first = true;
+        Dentry::new_root(Superblock::new(fs).root(), None).expect("Root filesystem has no root")
+    });
+    assert!(first, "Root filesystem mounted twice");
+    MOUNTS.lock().push(Mount {
+        path: String::from("/"),
+        fs_name,
+    });
+}
+
+/// Root of the tree, `mount_root` must have been called
+pub fn root() -> Arc<Dentry> {
+    ROOT.get().expect("No root filesystem").clone()
+}
+
+/// Mount `fs` over the directory at `path`
+pub fn mount(cwd: &Arc<Dentry>, path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let mountpoint = lookup(cwd, path, true)?;
+    if mountpoint.kind() != FileType::Directory {
+        return Err(FsError::NotDirectory);
+    }
+
+    let fs_name = fs.name();
+    let root = Dentry::new_root(Superblock::new(fs).root(), Some(&mountpoint))?;
+    mountpoint.set_mounted(root)?;
+    MOUNTS.lock().push(Mount {
+        path: mountpoint.path(),
+        fs_name,
+    });
+    Ok(())
+}
+
+/// Everything mounted, as (path, filesystem name)
+pub fn mounts() -> Vec<(String, &'static str)> {
+    MOUNTS.lock().iter().map(|mount| (mount.path.clone(), mount.fs_name)).collect()
+}
+
+fn check_name(name: &str) -> Result<(), FsError> {
+    if name.len() > NAME_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    Ok(())
+}
+
+/// Find what `path` leads to, relative to `cwd` unless it's absolute.
+/// Symlinks are followed along the way, and at the end too if `follow`.
+pub fn lookup(cwd: &Arc<Dentry>, path: &str, follow: bool) -> Result<Arc<Dentry>, FsError> {
+    let mut links = 0;
+    walk(cwd, path, follow, &mut links)
+}
+
+fn walk(cwd: &Arc<Dentry>, path: &str, follow: bool, links: &mut u32) -> Result<Arc<Dentry>, FsError> {
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+    // A trailing slash means the last component has to be a directory, so
+    // any symlink there gets followed
+    let must_be_dir = path.ends_with('/');
+
+    let mut current = if path.starts_with('/') { root() } else { cwd.clone() };
+    let mut components = path.split('/').filter(|component| !component.is_empty()).peekable();
+    while let Some(name) = components.next() {
+        if current.kind() != FileType::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        current = match name {
+            "." => current,
+            ".." => current.parent(),
+            name => {
+                check_name(name)?;
+                let child = current.child(name)?;
+                let last = components.peek().is_none();
+                if child.kind() == FileType::Symlink && (follow || must_be_dir || !last) {
+                    *links += 1;
+                    if *links > MAX_SYMLINKS {
+                        return Err(FsError::SymlinkLoop);
+                    }
+                    // Relative targets start from the directory holding the link
+                    let target = child.inode().read_link()?;
+                    walk(&current, &target, true, links)?
+                } else {
+                    child
+                }
+            }
+        };
+    }
+
+    if must_be_dir && current.kind() != FileType::Directory {
+        return Err(FsError::NotDirectory);
+    }
+    Ok(current)
+}
+
+// Resolve all but the last component of `path`, which is returned as is
+fn lookup_parent<'p>(cwd: &Arc<Dentry>, path: &'p str) -> Result<(Arc<Dentry>, &'p str), FsError> {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() {
+        // "" doesn't exist, "/" always does
+        return Err(if path.is_empty() { FsError::NotFound } else { FsError::Exists });
+    }
+
+    let (dir, name) = match trimmed.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((dir, name)) => (dir, name),
+        None => (".", trimmed),
+    };
+    if name == "." || name == ".." {
+        return Err(FsError::Exists);
+    }
+    check_name(name)?;
+
+    let dir = lookup(cwd, dir, true)?;
+    if dir.kind() != FileType::Directory {
+        return Err(FsError::NotDirectory);
+    }
+    Ok((dir, name))
+}
+
+/// Open the file at `path`, creating it with `mode` if `flags` say so
+pub fn open(cwd: &Arc<Dentry>, path: &str, flags: OpenFlags, mode: u16) -> Result<Arc<OpenFile>, FsError> {
+    let dentry = match lookup(cwd, path, !flags.no_follow) {
+        Ok(_) if flags.create && flags.exclusive => return Err(FsError::Exists),
+        Ok(dentry) => dentry,
+        Err(FsError::NotFound) if flags.create => {
+            let (dir, name) = lookup_parent(cwd, path)?;
+            dir.create(name, FileType::Regular, mode)?
+        }
+        Err(error) => return Err(error),
+    };
+
+    match dentry.kind() {
+        FileType::Directory if flags.write => return Err(FsError::IsDirectory),
+        FileType::Symlink => return Err(FsError::SymlinkLoop),
+        kind if flags.directory && kind != FileType::Directory => return Err(FsError::NotDirectory),
+        FileType::Regular if flags.truncate && flags.write => dentry.inode().truncate(0)?,
+        _ => {}
+    }
+    Ok(OpenFile::new(dentry, flags))
+}
+
+/// Make a directory at `path`
+pub fn mkdir(cwd: &Arc<Dentry>, path: &str, mode: u16) -> Result<Arc<Dentry>, FsError> {
+    let (dir, name) = lookup_parent(cwd, path)?;
+    dir.create(name, FileType::Directory, mode)
+}
+
+/// Make a symlink at `path` pointing to `target`
+pub fn symlink(cwd: &Arc<Dentry>, target: &str, path: &str) -> Result<Arc<Dentry>, FsError> {
+    let (dir, name) = lookup_parent(cwd, path)?;
+    dir.symlink(name, target)
+}
+
+/// Remove the file, symlink or empty directory at `path`
+pub fn unlink(cwd: &Arc<Dentry>, path: &str) -> Result<(), FsError> {
+    let (dir, name) = lookup_parent(cwd, path)?;
+    dir.unlink(name)
+}
+
+/// Read all of the file at `path`
+pub fn read_file(cwd: &Arc<Dentry>, path: &str) -> Result<Vec<u8>, FsError> {
+    let file = open(cwd, path, OpenFlags::read_only(), 0)?;
+    let size = file.metadata()?.size as usize;
+
+    let mut data = Vec::new();
+    data.try_reserve_exact(size).map_err(|_| FsError::NoSpace)?;
+    data.resize(size, 0);
+    let mut done = 0;
+    while done < size {
+        match file.read(&mut data[done..])? {
+            0 => break,
+            read => done += read,
+        }
+    }
+    data.truncate(done);
+    Ok(data)
+}
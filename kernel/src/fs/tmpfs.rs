@@ -0,0 +1,246 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use sync::Mutex;
+
+use super::{DirEntry, FileSystem, FileType, FsError, Metadata};
+
+const ROOT_INO: u64 = 1;
+
+// Filesystem that lives entirely in the kernel heap
+//
+// Unlinking a file frees it right away, even if it's still open, and
+// reads from it after that fail.
+pub struct TmpFs {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    nodes: BTreeMap<u64, Node>,
+    next_ino: u64,
+}
+
+struct Node {
+    mode: u16,
+    nlink: u32,
+    data: NodeData,
+}
+
+enum NodeData {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, u64>),
+    Symlink(String),
+}
+
+impl Node {
+    fn kind(&self) -> FileType {
+        match self.data {
+            NodeData::File(_) => FileType::Regular,
+            NodeData::Directory(_) => FileType::Directory,
+            NodeData::Symlink(_) => FileType::Symlink,
+        }
+    }
+}
+
+impl Inner {
+    fn node(&self, ino: u64) -> Result<&Node, FsError> {
+        self.nodes.get(&ino).ok_or(FsError::NotFound)
+    }
+
+    fn node_mut(&mut self, ino: u64) -> Result<&mut Node, FsError> {
+        self.nodes.get_mut(&ino).ok_or(FsError::NotFound)
+    }
+
+    fn entries(&self, dir: u64) -> Result<&BTreeMap<String, u64>, FsError> {
+        match &self.node(dir)?.data {
+            NodeData::Directory(entries) => Ok(entries),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    fn entries_mut(&mut self, dir: u64) -> Result<&mut BTreeMap<String, u64>, FsError> {
+        match &mut self.node_mut(dir)?.data {
+            NodeData::Directory(entries) => Ok(entries),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    fn file_mut(&mut self, ino: u64) -> Result<&mut Vec<u8>, FsError> {
+        match &mut self.node_mut(ino)?.data {
+            NodeData::File(data) => Ok(data),
+            NodeData::Directory(_) => Err(FsError::IsDirectory),
+            NodeData::Symlink(_) => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn add(&mut self, dir: u64, name: &str, node: Node) -> Result<u64, FsError> {
+        if self.entries(dir)?.contains_key(name) {
+            return Err(FsError::Exists);
+        }
+        let is_dir = node.kind() == FileType::Directory;
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.nodes.insert(ino, node);
+        self.entries_mut(dir)?.insert(String::from(name), ino);
+        if is_dir {
+            // The new directory's ".."
+            self.node_mut(dir)?.nlink += 1;
+        }
+        Ok(ino)
+    }
+}
+
+// Bytes in a file can only be addressed up to usize
+fn checked_offset(offset: u64) -> Result<usize, FsError> {
+    usize::try_from(offset).map_err(|_| FsError::InvalidArgument)
+}
+
+fn resize(data: &mut Vec<u8>, size: usize) -> Result<(), FsError> {
+    if size > data.len() {
+        data.try_reserve(size - data.len()).map_err(|_| FsError::NoSpace)?;
+    }
+    data.resize(size, 0);
+    Ok(())
+}
+
+impl TmpFs {
+    /// Empty filesystem, just a root directory
+    pub fn new() -> TmpFs {
+        let root = Node {
+            mode: 0o755,
+            nlink: 2,
+            data: NodeData::Directory(BTreeMap::new()),
+        };
+        TmpFs {
+            inner: Mutex::new(Inner {
+                nodes: BTreeMap::from([(ROOT_INO, root)]),
+                next_ino: ROOT_INO + 1,
+            }),
+        }
+    }
+}
+
+impl Default for TmpFs {
+    fn default() -> TmpFs {
+        TmpFs::new()
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> u64 {
+        ROOT_INO
+    }
+
+    fn metadata(&self, ino: u64) -> Result<Metadata, FsError> {
+        let inner = self.inner.lock();
+        let node = inner.node(ino)?;
+        let size = match &node.data {
+            NodeData::File(data) => data.len() as u64,
+            NodeData::Directory(entries) => entries.len() as u64,
+            NodeData::Symlink(target) => target.len() as u64,
+        };
+        Ok(Metadata {
+            ino,
+            kind: node.kind(),
+            mode: node.mode,
+            size,
+            nlink: node.nlink,
+        })
+    }
+
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, FsError> {
+        let inner = self.inner.lock();
+        inner.entries(dir)?.get(name).copied().ok_or(FsError::NotFound)
+    }
+
+    fn read_dir(&self, dir: u64, index: usize) -> Result<Option<DirEntry>, FsError> {
+        let inner = self.inner.lock();
+        let Some((name, &ino)) = inner.entries(dir)?.iter().nth(index) else {
+            return Ok(None);
+        };
+        Ok(Some(DirEntry {
+            ino,
+            kind: inner.node(ino)?.kind(),
+            name: name.clone(),
+        }))
+    }
+
+    fn read(&self, ino: u64, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut inner = self.inner.lock();
+        let data = inner.file_mut(ino)?;
+        let start = checked_offset(offset)?.min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn read_link(&self, ino: u64) -> Result<String, FsError> {
+        let inner = self.inner.lock();
+        match &inner.node(ino)?.data {
+            NodeData::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn create(&self, dir: u64, name: &str, kind: FileType, mode: u16) -> Result<u64, FsError> {
+        let (data, nlink) = match kind {
+            FileType::Regular => (NodeData::File(Vec::new()), 1),
+            FileType::Directory => (NodeData::Directory(BTreeMap::new()), 2),
+            _ => return Err(FsError::NotSupported),
+        };
+        self.inner.lock().add(dir, name, Node { mode, nlink, data })
+    }
+
+    fn symlink(&self, dir: u64, name: &str, target: &str) -> Result<u64, FsError> {
+        let node = Node {
+            mode: 0o777,
+            nlink: 1,
+            data: NodeData::Symlink(String::from(target)),
+        };
+        self.inner.lock().add(dir, name, node)
+    }
+
+    fn unlink(&self, dir: u64, name: &str) -> Result<(), FsError> {
+        let mut inner = self.inner.lock();
+        let ino = *inner.entries(dir)?.get(name).ok_or(FsError::NotFound)?;
+        let is_dir = match &inner.node(ino)?.data {
+            NodeData::Directory(entries) if !entries.is_empty() => return Err(FsError::NotEmpty),
+            NodeData::Directory(_) => true,
+            _ => false,
+        };
+
+        inner.entries_mut(dir)?.remove(name);
+        inner.nodes.remove(&ino);
+        if is_dir {
+            inner.node_mut(dir)?.nlink -= 1;
+        }
+        Ok(())
+    }
+
+    fn write(&self, ino: u64, offset: u64, data: &[u8]) -> Result<usize, FsError> {
+        let mut inner = self.inner.lock();
+        let file = inner.file_mut(ino)?;
+        let start = checked_offset(offset)?;
+        let end = start.checked_add(data.len()).ok_or(FsError::InvalidArgument)?;
+        if end > file.len() {
+            resize(file, end)?;
+        }
+        file[start..end].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn truncate(&self, ino: u64, size: u64) -> Result<(), FsError> {
+        let mut inner = self.inner.lock();
+        resize(inner.file_mut(ino)?, checked_offset(size)?)
+    }
+
+    fn set_mode(&self, ino: u64, mode: u16) -> Result<(), FsError> {
+        self.inner.lock().node_mut(ino)?.mode = mode & 0o7777;
+        Ok(())
+    }
+}
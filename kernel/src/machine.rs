@@ -1,22 +1,65 @@
 use core::arch::asm;
-use devtree::DevTree;
 
-pub fn enable_neon() {
-    unsafe {
-        asm!("mov x24, x0");
-        asm!("mrs x0, CPACR_EL1");
-        asm!("orr x0, x0, #(3 << 20)");
-        asm!("msr CPACR_EL1, x0");
-        asm!("isb");
-        asm!("mov x0, x24");
+// Aff3, Aff2, Aff1 and Aff0 fields of MPIDR_EL1
+pub const MPIDR_AFFINITY_MASK: u64 = 0xff_00ff_ffff;
+
+/// Affinity fields of MPIDR_EL1, identifying the running core
+pub fn cpu_id() -> u64 {
+    let mpidr: u64;
+    unsafe { asm!("mrs {}, mpidr_el1", out(reg) mpidr) };
+    mpidr & MPIDR_AFFINITY_MASK
+}
+
+/// Unmask IRQs on the calling CPU
+pub fn enable_irqs() {
+    unsafe { asm!("msr daifclr, #2") };
+}
+
+/// Mask IRQs on the calling CPU
+pub fn disable_irqs() {
+    unsafe { asm!("msr daifset, #2") };
+}
+
+/// Mask IRQs, returning the previous DAIF for `restore_irqs`
+pub fn save_and_disable_irqs() -> u64 {
+    let daif: u64;
+    unsafe { asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif) };
+    daif
+}
+
+pub fn restore_irqs(daif: u64) {
+    unsafe { asm!("msr daif, {}", in(reg) daif) };
+}
+
+/// Sleep until an interrupt is pending, even a masked one
+pub fn wait_for_interrupt() {
+    unsafe { asm!("wfi") };
+}
+
+/// Mask interrupts and sleep forever
+pub fn halt() -> ! {
+    unsafe { asm!("msr daifset, #0xf") };
+    loop {
+        unsafe { asm!("wfi") };
     }
 }
 
-pub fn get_devtree() -> Option<DevTree> {
-    let mut dtb_ptr: *const u8;
-    unsafe { 
-        asm!("mov {}, x0", out(reg) dtb_ptr) 
-    };
-    
-    DevTree::new(dtb_ptr)
-}
\ No newline at end of file
+/// Make QEMU exit with `code` through a semihosting SYS_EXIT call, only
+/// works when QEMU runs with `-semihosting`
+#[cfg(feature = "qemu-exit")]
+pub fn exit_qemu(code: u64) -> ! {
+    const SYS_EXIT: u64 = 0x18;
+    const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+    let block = [ADP_STOPPED_APPLICATION_EXIT, code];
+    unsafe {
+        asm!(
+            "hlt #0xf000",
+            in("x0") SYS_EXIT,
+            in("x1") block.as_ptr(),
+        );
+    }
+
+    // Not running under QEMU with semihosting after all
+    halt();
+}
@@ -1,51 +1,213 @@
 #![no_std]
 #![no_main]
-use core::arch::asm;
+extern crate alloc;
 
-use devtree::DevTree;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
 
+use devtree::{DevTree, OwnedDevTree};
+
+use crate::boot::BootInfo;
+use crate::fs::FsError;
+use crate::fs::tmpfs::TmpFs;
+use crate::mm::address_space::AddressSpace;
+use crate::mm::paging::Flags;
+use crate::process::Image;
+
+mod boot;
+#[macro_use]
+mod console;
+mod drivers;
+mod exception;
+mod fs;
 mod machine;
+mod mm;
+mod panic;
+mod process;
+mod psci;
+mod smp;
+mod task;
+mod time;
+
+fn main(boot: &BootInfo) {
+    console::init_early();
+    println!("silly-kernel booting :p");
+    exception::init();
+    println!(
+        "Entered at EL{} on CPU {:#x}, image at {:#x} ({} KiB)",
+        boot.entry_el,
+        boot.boot_mpidr & machine::MPIDR_AFFINITY_MASK,
+        boot.image_base,
+        boot.image_size / 1024
+    );
+
+    let Some(devtree) = boot.devtree() else {
+        panic!("No valid DTB at {:#x}", boot.dtb_phys);
+    };
+
+    if !console::init(&devtree) {
+        println!("No PL011 in the device tree, staying on the early console");
+    }
+
+    // Drivers map their registers as they come up, before or after this
+    mm::paging::init(boot, &devtree);
+    mm::frame::init(boot, &devtree);
+
+    let memory = mm::frame::stats();
+    println!(
+        "Memory: {} KiB free of {} KiB",
+        memory.free_pages * mm::PAGE_SIZE / 1024,
+        memory.total_pages * mm::PAGE_SIZE / 1024
+    );
+
+    // The heap works from here on
+    let owned = OwnedDevTree::from_devtree(&devtree);
+    println!("Device tree has {} nodes", owned.len());
+    smp::init_boot_cpu();
+
+    if !drivers::irqchip::init(&devtree) {
+        panic!("No supported interrupt controller in the device tree");
+    }
+    machine::enable_irqs();
+
+    if !time::init(&devtree) {
+        panic!("No architected timer in the device tree");
+    }
+    println!("Booted in {:?}", time::uptime());
+
+    if psci::init(&devtree, boot.entry_el) {
+        match psci::psci_version() {
+            Ok(version) => println!("PSCI {}", version),
+            Err(error) => println!("PSCI version unknown: {}", error),
+        }
+    }
+    println!("{} CPUs online", smp::start_secondaries(&devtree));
+
+    task::init();
+
+    // Some background work while main waits for it
+    let main_thread = task::current().expect("main runs as a thread now");
+    task::spawn("heartbeat", move || {
+        for beat in 1..=3 {
+            task::sleep(Duration::from_millis(100));
+            println!("Heartbeat {} at {:?}", beat, time::uptime());
+        }
+        task::wake(main_thread);
+    })
+    .expect("No memory for the heartbeat thread");
+    task::park();
 
-fn main() {
-    machine::enable_neon();
-    let devtree = machine::get_devtree();
-    if devtree.is_none() {
-        panic!("Failed to get DevTree");
+    // Everything lives in memory for now, starting with whatever the
+    // initrd has
+    fs::mount_root(Arc::new(TmpFs::new()));
+    if let Some((start, end)) = mm::initrd_range(&devtree) {
+        unpack_initrd(&devtree, start, end);
     }
-    let devtree: DevTree = devtree.unwrap();
+    match fs::mkdir(&fs::root(), "/tmp", 0o1777) {
+        Ok(_) | Err(FsError::Exists) => {}
+        Err(error) => panic!("Couldn't make /tmp: {}", error),
+    }
+    fs::mount(&fs::root(), "/tmp", Arc::new(TmpFs::new())).expect("Couldn't mount /tmp");
+    for (path, fs_name) in fs::mounts() {
+        println!("Mounted {} on {}", fs_name, path);
+    }
+
+    // Two tiny user programs, one exiting cleanly and one faulting
+    let exit_42 = [0xd2800540, 0xd2800ba8, 0xd4000001]; // mov x0, #42; mov x8, #93; svc #0
+    let null_load = [0xd2800001, 0xf9400020]; // mov x1, #0; ldr x0, [x1]
+    let pids = [run_program("exit-42", &exit_42), run_program("null-load", &null_load)];
+    for pid in pids {
+        println!("Process {} exited with {:?}", pid, process::wait(pid));
+    }
+
+    // Then the initrd's /init, if it had one
+    match fs::read_file(&fs::root(), "/init") {
+        Ok(file) => match process::spawn_elf("/init", &file, &["/init"], &["HOME=/", "TERM=linux"]) {
+            Ok(pid) => println!("/init exited with {:?}", process::wait(pid)),
+            Err(error) => println!("Couldn't start /init: {}", error),
+        },
+        Err(FsError::NotFound) => {}
+        Err(error) => println!("Couldn't read /init: {}", error),
+    }
+
     let root = devtree.root();
     
     // Iterate over root node properties
     for prop in root.properties() {
-        let _name = prop.name();
-        let _value = prop.value();
+        println!("{} = {}", prop.name(), prop.decode());
     }
     
     let mut count: usize = 0;
     // Iterate over root node children
     for child in root.children() {
-        let _child_name = child.name();
+        println!("{}/", child.name());
         count += 1;
     }
 
-    panic!("Count of children: {}", count);
+    println!("Count of children: {}", count);
     // ... continue working with the DTB
 }
 
+// Unpack the cpio archive the bootloader left at `start..end` into the
+// root, then give its pages back
+fn unpack_initrd(devtree: &DevTree, start: u64, end: u64) {
+    // Only touched if it's all in one bank of RAM, which is mapped and
+    // where its pages can go back to the allocator
+    let in_ram = mm::memory_regions(devtree)
+        .any(|(base, size)| base <= start && base.checked_add(size).is_some_and(|limit| end <= limit));
+    if start >= end || !in_ram {
+        println!("Initrd at {:#x}..{:#x} isn't in RAM, skipping it", start, end);
+        return;
+    }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn kernel_main() -> ! {
-    unsafe {  asm!("mov x11, #0x69") };
-    main();
-    unsafe { asm!("wfi") };
-    loop {}
+    let data = unsafe { core::slice::from_raw_parts(start as *const u8, (end - start) as usize) };
+    match fs::initramfs::unpack(&fs::root(), data) {
+        Ok(unpacked) => println!(
+            "Unpacked {} initrd entries, skipped {}",
+            unpacked.entries, unpacked.skipped
+        ),
+        Err(error) => println!("Couldn't unpack the initrd: {}", error),
+    }
+
+    let freed = mm::frame::free_range(start, end);
+    println!("Freed {} KiB of initrd", freed * mm::PAGE_SIZE / 1024);
 }
 
-#[panic_handler]
-fn panic(_info: &core::panic::PanicInfo) -> ! {
-    unsafe { 
-        asm!("mov x12, #0x6969");
-        asm!("wfi");
-    }
-    loop {}
+// Start a process running `code` from address 0x400000
+fn run_program(name: &str, code: &[u32]) -> process::Pid {
+    const LOAD_ADDRESS: u64 = 0x40_0000;
+
+    let bytes: Vec<u8> = code.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let mut space = AddressSpace::new().expect("No memory for an address space");
+    space
+        .map(LOAD_ADDRESS, bytes.len() as u64, Flags::user(false, true))
+        .expect("Couldn't map the program");
+    space.write(LOAD_ADDRESS, &bytes).expect("Couldn't load the program");
+    space.sync_icache(LOAD_ADDRESS, bytes.len() as u64).expect("Couldn't load the program");
+    let sp = process::map_stack(&mut space, process::DEFAULT_STACK_SIZE, false).expect("Couldn't map the user stack");
+    let image = Image {
+        entry: LOAD_ADDRESS,
+        sp,
+        brk: mm::align_up(LOAD_ADDRESS + bytes.len() as u64, mm::PAGE_SIZE as u64),
+    };
+    process::spawn(name, space, image).expect("No memory for the process")
+}
+
+// Entered from boot.S with the registers the bootloader left us
+#[unsafe(no_mangle)]
+pub extern "C" fn kernel_main(dtb_phys: usize, entry_el: u64, image_base: usize, image_size: usize, mpidr: u64) -> ! {
+    let boot = BootInfo {
+        dtb_phys,
+        entry_el: entry_el as u8,
+        image_base,
+        image_size,
+        boot_mpidr: mpidr,
+    };
+    main(&boot);
+
+    println!("Powering off");
+    let error = psci::system_off();
+    println!("Couldn't power off: {}", error);
+    machine::halt();
 }
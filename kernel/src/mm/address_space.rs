@@ -0,0 +1,252 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::arch::asm;
+
+use super::paging::{self, DESC_ADDRESS, DESC_TABLE, DESC_VALID, Flags, MapError, PageTable, index};
+use super::{PAGE_SIZE, align_down, align_up, frame};
+
+const PAGE: u64 = PAGE_SIZE as u64;
+
+/// End of what TTBR0 translates with its 48 bit T0SZ, user addresses and
+/// ranges have to stay below
+pub const USER_LIMIT: u64 = 1 << 48;
+
+// Smallest D-cache line on any ARMv8 CPU, safe to step by when cleaning
+const CACHE_LINE: u64 = 64;
+
+// The TTBR0 tables of a user process
+//
+// The kernel's identity map has to stay visible under every address space,
+// so a new one starts out as a copy of the kernel's root table. Tables on
+// the way to a user page get copied too before they're changed, which
+// makes them private; everything else stays shared with the kernel. User
+// pages can go anywhere the kernel has nothing mapped, and the kernel's own
+// entries stay out of EL0's reach through their permissions.
+//
+// Kernel mappings made after the copies were taken don't show up in them,
+// so devices should all be mapped before the first process starts. Thread
+// stacks are fine, their region's table is linked in at boot and shared.
+pub struct AddressSpace {
+    root: *mut PageTable,
+    // Tables this space made and frees, root first
+    tables: Vec<u64>,
+    // User page to the frame backing it and its permissions
+    pages: BTreeMap<u64, (u64, Flags)>,
+}
+
+// Every table and frame it points to belongs to it alone
+unsafe impl Send for AddressSpace {}
+
+impl AddressSpace {
+    pub fn new() -> Option<AddressSpace> {
+        let root = frame::alloc_zeroed_frame()? as *mut PageTable;
+        paging::copy_kernel_root(unsafe { &mut *root });
+        Some(AddressSpace {
+            root,
+            tables: alloc::vec![root as u64],
+            pages: BTreeMap::new(),
+        })
+    }
+
+    /// Value for TTBR0 while this space is active
+    pub fn root(&self) -> u64 {
+        self.root as u64
+    }
+
+    /// Map `size` bytes at `virt` to fresh zeroed pages
+    pub fn map(&mut self, virt: u64, size: u64, flags: Flags) -> Result<(), MapError> {
+        let (start, end) = page_range(virt, size).ok_or(MapError::OutOfRange)?;
+        if (start..end).step_by(PAGE_SIZE).any(|page| self.pages.contains_key(&page)) {
+            return Err(MapError::Overlap);
+        }
+
+        let mut page = start;
+        while page < end {
+            let table = self.leaf_table(page)?;
+            let entry = unsafe { &mut (*table).0[index(page, 3)] };
+            if *entry & DESC_VALID != 0 {
+                self.unmap(start, page - start);
+                return Err(MapError::Overlap);
+            }
+            let Some(frame) = frame::alloc_zeroed_frame() else {
+                self.unmap(start, page - start);
+                return Err(MapError::OutOfMemory);
+            };
+            *entry = frame | flags.bits() | DESC_VALID | DESC_TABLE;
+            self.pages.insert(page, (frame, flags));
+            page += PAGE;
+        }
+
+        paging::flush_tlb();
+        Ok(())
+    }
+
+    /// Change the permissions of the mapped pages covering `size` bytes at
+    /// `virt`
+    pub fn protect(&mut self, virt: u64, size: u64, flags: Flags) -> Result<(), MapError> {
+        let (start, end) = page_range(virt, size).ok_or(MapError::OutOfRange)?;
+        if !(start..end).step_by(PAGE_SIZE).all(|page| self.pages.contains_key(&page)) {
+            return Err(MapError::NotMapped);
+        }
+
+        for page in (start..end).step_by(PAGE_SIZE) {
+            let table = self.leaf_table(page)?;
+            let entry = unsafe { &mut (*table).0[index(page, 3)] };
+            *entry = (*entry & DESC_ADDRESS) | flags.bits() | DESC_VALID | DESC_TABLE;
+            if let Some(page) = self.pages.get_mut(&page) {
+                page.1 = flags;
+            }
+        }
+
+        paging::flush_tlb();
+        Ok(())
+    }
+
+    /// Unmap and free whatever pages are mapped in `size` bytes at `virt`
+    pub fn unmap(&mut self, virt: u64, size: u64) {
+        // Nothing can be mapped out there
+        let Some((start, end)) = page_range(virt, size) else {
+            return;
+        };
+        let pages: Vec<_> = self.pages.range(start..end).map(|(&page, &(frame, _))| (page, frame)).collect();
+
+        for (page, frame) in pages {
+            if let Ok(table) = self.leaf_table(page) {
+                unsafe { (*table).0[index(page, 3)] = 0 };
+            }
+            self.pages.remove(&page);
+            frame::free_frame(frame);
+        }
+
+        paging::flush_tlb();
+    }
+
+    /// Whether all of `size` bytes at `virt` are mapped
+    pub fn is_mapped(&self, virt: u64, size: u64) -> bool {
+        let Some((start, end)) = page_range(virt, size.max(1)) else {
+            return false;
+        };
+        (start..end).step_by(PAGE_SIZE).all(|page| self.pages.contains_key(&page))
+    }
+
+    /// Whether all of `size` bytes at `virt` are mapped writable for EL0,
+    /// to check before writing there on a process's behalf
+    pub fn is_writable(&self, virt: u64, size: u64) -> bool {
+        let Some((start, end)) = page_range(virt, size.max(1)) else {
+            return false;
+        };
+        (start..end)
+            .step_by(PAGE_SIZE)
+            .all(|page| self.pages.get(&page).is_some_and(|&(_, flags)| flags.user_writable()))
+    }
+
+    /// Physical address behind user address `virt`
+    pub fn translate(&self, virt: u64) -> Option<u64> {
+        let (frame, _) = self.pages.get(&align_down(virt, PAGE))?;
+        Some(frame + virt % PAGE)
+    }
+
+    /// Copy `data` to user address `virt` through the kernel's identity map,
+    /// whatever the permissions there
+    pub fn write(&mut self, virt: u64, data: &[u8]) -> Result<(), MapError> {
+        self.for_each_chunk(virt, data.len(), |phys, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(data[offset..].as_ptr(), phys as *mut u8, len);
+        })
+    }
+
+    /// Make code written to `size` bytes at `virt` visible to instruction
+    /// fetches
+    pub fn sync_icache(&self, virt: u64, size: u64) -> Result<(), MapError> {
+        self.for_each_chunk(virt, size as usize, |phys, _, len| unsafe { clean_dcache(phys, len as u64) })?;
+        unsafe { asm!("dsb ish", "ic ialluis", "dsb ish", "isb") };
+        Ok(())
+    }
+
+    /// Copy from user address `virt` into `buf`
+    pub fn read(&self, virt: u64, buf: &mut [u8]) -> Result<(), MapError> {
+        let len = buf.len();
+        self.for_each_chunk(virt, len, |phys, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(phys as *const u8, buf[offset..].as_mut_ptr(), len);
+        })
+    }
+
+    // Walk `len` bytes at `virt` a page at a time, as (physical address,
+    // offset into the range, length) pieces
+    fn for_each_chunk(&self, virt: u64, len: usize, mut f: impl FnMut(u64, usize, usize)) -> Result<(), MapError> {
+        if !self.is_mapped(virt, len as u64) {
+            return Err(MapError::NotMapped);
+        }
+
+        let mut offset = 0;
+        while offset < len {
+            let address = virt + offset as u64;
+            let chunk = (PAGE - address % PAGE).min((len - offset) as u64) as usize;
+            let phys = self.translate(address).ok_or(MapError::NotMapped)?;
+            f(phys, offset, chunk);
+            offset += chunk;
+        }
+        Ok(())
+    }
+
+    // Find or make the private level 3 table covering `virt`
+    fn leaf_table(&mut self, virt: u64) -> Result<*mut PageTable, MapError> {
+        let mut table = self.root;
+        for level in 0..3 {
+            let entry = unsafe { &mut (*table).0[index(virt, level)] };
+            let next = *entry & DESC_ADDRESS;
+
+            if *entry & DESC_VALID != 0 && self.tables.contains(&next) {
+                table = next as *mut PageTable;
+                continue;
+            }
+
+            let new = if *entry & DESC_VALID == 0 {
+                frame::alloc_zeroed_frame().ok_or(MapError::OutOfTables)?
+            } else if *entry & DESC_TABLE != 0 && level < 2 {
+                // Shared with the kernel, take a private copy to change
+                let new = frame::alloc_zeroed_frame().ok_or(MapError::OutOfTables)?;
+                unsafe { (*(new as *mut PageTable)).0 = (*(next as *const PageTable)).0 };
+                new
+            } else {
+                // A kernel block, or kernel pages in the same 2 MiB
+                return Err(MapError::Overlap);
+            };
+
+            self.tables.push(new);
+            // Make the table's contents visible to the walker before linking it
+            unsafe { asm!("dsb ishst") };
+            *entry = new | DESC_VALID | DESC_TABLE;
+            table = new as *mut PageTable;
+        }
+        Ok(table)
+    }
+}
+
+// Pages covering `size` bytes at `virt`, none if the range wraps around or
+// doesn't fit below `USER_LIMIT`
+fn page_range(virt: u64, size: u64) -> Option<(u64, u64)> {
+    let end = virt.checked_add(size).filter(|&end| end <= USER_LIMIT)?;
+    Some((align_down(virt, PAGE), align_up(end, PAGE)))
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        debug_assert!(paging::active_root() != self.root(), "Freeing the active address space");
+        for &(frame, _) in self.pages.values() {
+            frame::free_frame(frame);
+        }
+        for &table in &self.tables {
+            frame::free_frame(table);
+        }
+    }
+}
+
+// Write `len` bytes at `phys` back to the point of unification, where
+// instruction fetches see them
+unsafe fn clean_dcache(phys: u64, len: u64) {
+    let mut line = align_down(phys, CACHE_LINE);
+    while line < phys + len {
+        unsafe { asm!("dc cvau, {}", in(reg) line) };
+        line += CACHE_LINE;
+    }
+}
@@ -0,0 +1,305 @@
+use devtree::DevTree;
+use sync::SpinLockIrq;
+
+use crate::boot::BootInfo;
+
+use super::{PAGE_SIZE, align_down, align_up, initrd_range, memory_regions};
+
+const PAGE: u64 = PAGE_SIZE as u64;
+
+// Limits on what the device tree can describe, generous for QEMU `virt`
+const MAX_REGIONS: usize = 8;
+const MAX_RESERVED: usize = 32;
+
+// One bank of RAM and the bitmap tracking it, a set bit meaning the page is
+// in use (or was never usable in the first place)
+#[derive(Clone, Copy)]
+struct Region {
+    base: u64,
+    pages: usize,
+    bitmap: *mut u64,
+    free: usize,
+}
+
+impl Region {
+    const EMPTY: Region = Region {
+        base: 0,
+        pages: 0,
+        bitmap: core::ptr::null_mut(),
+        free: 0,
+    };
+
+    fn words(&self) -> usize {
+        self.pages.div_ceil(64)
+    }
+
+    fn is_used(&self, page: usize) -> bool {
+        unsafe { *self.bitmap.add(page / 64) & (1 << (page % 64)) != 0 }
+    }
+
+    fn set_used(&mut self, page: usize, used: bool) {
+        let word = unsafe { &mut *self.bitmap.add(page / 64) };
+        let bit = 1 << (page % 64);
+        if used && *word & bit == 0 {
+            *word |= bit;
+            self.free -= 1;
+        } else if !used && *word & bit != 0 {
+            *word &= !bit;
+            self.free += 1;
+        }
+    }
+
+    /// Mark the pages overlapping `start..end` as used
+    fn reserve(&mut self, start: u64, end: u64) {
+        let start = align_down(start, PAGE).max(self.base);
+        let end = align_up(end, PAGE).min(self.base + self.pages as u64 * PAGE);
+        let mut address = start;
+        while address < end {
+            self.set_used(((address - self.base) / PAGE) as usize, true);
+            address += PAGE;
+        }
+    }
+
+    // First free run of `count` pages whose address is a multiple of `align`
+    fn find_free(&self, count: usize, align: u64) -> Option<usize> {
+        // The base needn't be aligned, so round physical addresses rather
+        // than page numbers
+        let aligned = |page: usize| ((align_up(self.base + page as u64 * PAGE, align) - self.base) / PAGE) as usize;
+        let mut page = aligned(0);
+
+        while page + count <= self.pages {
+            // Whole words in use can be skipped at once
+            if page.is_multiple_of(64) && unsafe { *self.bitmap.add(page / 64) } == u64::MAX {
+                page = aligned(page + 64);
+                continue;
+            }
+
+            match (page..page + count).find(|&p| self.is_used(p)) {
+                None => return Some(page),
+                // Jump past the page in use, keeping the alignment
+                Some(used) => page = aligned(used + 1),
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub total_pages: usize,
+    pub free_pages: usize,
+}
+
+impl FrameStats {
+    pub fn used_pages(&self) -> usize {
+        self.total_pages - self.free_pages
+    }
+}
+
+// Bitmap allocator of physical pages
+struct FrameAllocator {
+    regions: [Region; MAX_REGIONS],
+    count: usize,
+    total_pages: usize,
+}
+
+// The bitmaps are frames only the allocator uses
+unsafe impl Send for FrameAllocator {}
+
+static FRAMES: SpinLockIrq<FrameAllocator> = SpinLockIrq::new(FrameAllocator {
+    regions: [Region::EMPTY; MAX_REGIONS],
+    count: 0,
+    total_pages: 0,
+});
+
+fn with_allocator<R>(f: impl FnOnce(&mut FrameAllocator) -> R) -> R {
+    f(&mut FRAMES.lock())
+}
+
+// Everything in RAM that isn't ours to hand out
+fn reserved_ranges(boot: &BootInfo, devtree: &DevTree) -> ([(u64, u64); MAX_RESERVED], usize) {
+    let mut ranges = [(0, 0); MAX_RESERVED];
+    let mut count = 0;
+    let mut add = |start: u64, end: u64| {
+        assert!(count < MAX_RESERVED, "Too many reserved memory ranges");
+        ranges[count] = (start, end);
+        count += 1;
+    };
+
+    // The kernel image and its boot stack
+    add(boot.image_base as u64, boot.image_end() as u64);
+
+    let dtb = boot.dtb_phys as u64;
+    add(dtb, dtb + devtree.header().totalsize() as u64);
+
+    if let Some((start, end)) = initrd_range(devtree) {
+        add(start, end);
+    }
+
+    for reservation in devtree.mem_reservations() {
+        add(reservation.address, reservation.address + reservation.size);
+    }
+
+    // Only statically placed regions, dynamic ones would have to be
+    // allocated here first
+    if let Some(reserved) = devtree.find_node("/reserved-memory") {
+        let (address_cells, size_cells) = (reserved.address_cells(), reserved.size_cells());
+        for child in reserved.children() {
+            for (address, size) in child.reg(address_cells, size_cells) {
+                add(address, address + size);
+            }
+        }
+    }
+
+    (ranges, count)
+}
+
+/// Take every page of RAM that isn't reserved, keeping the bitmaps in the
+/// first spot big enough for them
+pub fn init(boot: &BootInfo, devtree: &DevTree) {
+    let (reserved, reserved_count) = reserved_ranges(boot, devtree);
+    let reserved = &reserved[..reserved_count];
+
+    with_allocator(|allocator| {
+        for (base, size) in memory_regions(devtree) {
+            let start = align_up(base, PAGE);
+            let end = align_down(base + size, PAGE);
+            if start >= end {
+                continue;
+            }
+            assert!(allocator.count < MAX_REGIONS, "Too many memory regions");
+            allocator.regions[allocator.count] = Region {
+                base: start,
+                pages: ((end - start) / PAGE) as usize,
+                ..Region::EMPTY
+            };
+            allocator.count += 1;
+        }
+        let regions = &mut allocator.regions[..allocator.count];
+
+        let bitmap_bytes: u64 = regions.iter().map(|region| region.words() as u64 * 8).sum();
+        let bitmap_size = align_up(bitmap_bytes, PAGE);
+        let bitmap_start = find_unreserved(regions, reserved, bitmap_size).expect("No room for the frame bitmap");
+
+        let mut words = bitmap_start as *mut u64;
+        for region in regions.iter_mut() {
+            region.bitmap = words;
+            region.free = region.pages;
+            unsafe {
+                core::ptr::write_bytes(words, 0, region.words());
+                words = words.add(region.words());
+            }
+
+            // Bits past the end of the region don't stand for any page
+            let tail = region.pages % 64;
+            if tail != 0 {
+                unsafe { *region.bitmap.add(region.words() - 1) = !0 << tail };
+            }
+
+            for &(start, end) in reserved {
+                region.reserve(start, end);
+            }
+            region.reserve(bitmap_start, bitmap_start + bitmap_size);
+        }
+
+        allocator.total_pages = regions.iter().map(|region| region.pages).sum();
+    });
+}
+
+// Lowest page aligned spot of `size` bytes in RAM that misses every
+// reserved range
+fn find_unreserved(regions: &[Region], reserved: &[(u64, u64)], size: u64) -> Option<u64> {
+    let overlaps = |start: u64| {
+        reserved
+            .iter()
+            .any(|&(reserved_start, reserved_end)| start < reserved_end && reserved_start < start + size)
+    };
+
+    // A free spot always starts at a region or right after a reservation
+    let candidates = regions
+        .iter()
+        .map(|region| region.base)
+        .chain(reserved.iter().map(|&(_, end)| align_up(end, PAGE)));
+
+    candidates
+        .filter(|&start| {
+            regions
+                .iter()
+                .any(|region| start >= region.base && start + size <= region.base + region.pages as u64 * PAGE)
+        })
+        .filter(|&start| !overlaps(start))
+        .min()
+}
+
+/// Allocate `count` contiguous pages starting at a multiple of `align`
+/// bytes, returns the physical address of the first
+pub fn alloc_frames(count: usize, align: usize) -> Option<u64> {
+    assert!(align.is_power_of_two(), "Frame alignment must be a power of two");
+    with_allocator(|allocator| {
+        for region in allocator.regions[..allocator.count].iter_mut() {
+            if region.free < count {
+                continue;
+            }
+            if let Some(first) = region.find_free(count, align as u64) {
+                for page in first..first + count {
+                    region.set_used(page, true);
+                }
+                return Some(region.base + first as u64 * PAGE);
+            }
+        }
+        None
+    })
+}
+
+/// Allocate a single page
+pub fn alloc_frame() -> Option<u64> {
+    alloc_frames(1, PAGE_SIZE)
+}
+
+/// Allocate a single page, filled with zeroes
+pub fn alloc_zeroed_frame() -> Option<u64> {
+    let frame = alloc_frame()?;
+    unsafe { core::ptr::write_bytes(frame as *mut u8, 0, PAGE_SIZE) };
+    Some(frame)
+}
+
+/// Give back `count` pages starting at `address`
+pub fn free_frames(address: u64, count: usize) {
+    with_allocator(|allocator| {
+        let region = allocator.regions[..allocator.count]
+            .iter_mut()
+            .find(|region| address >= region.base && address < region.base + region.pages as u64 * PAGE)
+            .expect("Freeing a frame outside of RAM");
+
+        let first = ((address - region.base) / PAGE) as usize;
+        assert!(count <= region.pages - first, "Freeing {} frames at {:#x} past the end of RAM", count, address);
+        for page in first..first + count {
+            assert!(region.is_used(page), "Double free of frame {:#x}", region.base + page as u64 * PAGE);
+            region.set_used(page, false);
+        }
+    });
+}
+
+/// Give back the whole pages in `start..end` once whatever was reserved
+/// there at boot isn't needed, returns how many there were
+pub fn free_range(start: u64, end: u64) -> usize {
+    let (first, last) = (align_up(start, PAGE), align_down(end, PAGE));
+    if first >= last {
+        return 0;
+    }
+    let count = ((last - first) / PAGE) as usize;
+    free_frames(first, count);
+    count
+}
+
+pub fn free_frame(address: u64) {
+    free_frames(address, 1);
+}
+
+pub fn stats() -> FrameStats {
+    with_allocator(|allocator| FrameStats {
+        total_pages: allocator.total_pages,
+        free_pages: allocator.regions[..allocator.count].iter().map(|region| region.free).sum(),
+    })
+}
@@ -0,0 +1,167 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use sync::SpinLockIrq;
+
+use super::{PAGE_SIZE, frame};
+
+// Object sizes served from slabs, anything bigger gets whole pages
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// Free objects are threaded into a list through their own first word
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+// Objects of one size class, carved out of whole pages. Pages never go
+// back to the frame allocator once they're part of a slab.
+struct Slab {
+    free: *mut FreeObject,
+    allocated: usize,
+    pages: usize,
+}
+
+impl Slab {
+    const EMPTY: Slab = Slab {
+        free: ptr::null_mut(),
+        allocated: 0,
+        pages: 0,
+    };
+
+    fn alloc(&mut self, size: usize) -> *mut u8 {
+        if self.free.is_null() && !self.grow(size) {
+            return ptr::null_mut();
+        }
+
+        let object = self.free;
+        self.free = unsafe { (*object).next };
+        self.allocated += 1;
+        object as *mut u8
+    }
+
+    fn dealloc(&mut self, object: *mut u8) {
+        let object = object as *mut FreeObject;
+        unsafe { (*object).next = self.free };
+        self.free = object;
+        self.allocated -= 1;
+    }
+
+    // Add a fresh page worth of objects to the free list
+    fn grow(&mut self, size: usize) -> bool {
+        let Some(page) = frame::alloc_frame() else {
+            return false;
+        };
+
+        for offset in (0..PAGE_SIZE).step_by(size).rev() {
+            let object = (page as usize + offset) as *mut FreeObject;
+            unsafe { (*object).next = self.free };
+            self.free = object;
+        }
+        self.pages += 1;
+        true
+    }
+}
+
+struct Heap {
+    slabs: [Slab; SIZE_CLASSES.len()],
+    large_pages: usize,
+}
+
+// The free lists point into memory the heap owns outright
+unsafe impl Send for Heap {}
+
+impl Heap {
+    // Slab objects sit at multiples of their size within a page, so a class
+    // at least as big as the alignment satisfies it too
+    fn size_class(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        SIZE_CLASSES.iter().position(|&class| class >= size)
+    }
+
+    fn large_pages(layout: Layout) -> usize {
+        layout.size().div_ceil(PAGE_SIZE)
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        match Self::size_class(layout) {
+            Some(class) => self.slabs[class].alloc(SIZE_CLASSES[class]),
+            None => {
+                let pages = Self::large_pages(layout);
+                match frame::alloc_frames(pages, layout.align().max(PAGE_SIZE)) {
+                    Some(address) => {
+                        self.large_pages += pages;
+                        address as *mut u8
+                    }
+                    None => ptr::null_mut(),
+                }
+            }
+        }
+    }
+
+    fn dealloc(&mut self, object: *mut u8, layout: Layout) {
+        match Self::size_class(layout) {
+            Some(class) => self.slabs[class].dealloc(object),
+            None => {
+                let pages = Self::large_pages(layout);
+                frame::free_frames(object as u64, pages);
+                self.large_pages -= pages;
+            }
+        }
+    }
+
+    // What `alloc_error_handler` would print, before the default handler
+    // panics with just the size
+    fn report_oom(&self, layout: Layout) {
+        let frames = frame::stats();
+        println!(
+            "\nOut of memory allocating {} bytes aligned to {}",
+            layout.size(),
+            layout.align()
+        );
+        println!(
+            "Frames: {} free, {} used of {}",
+            frames.free_pages,
+            frames.used_pages(),
+            frames.total_pages
+        );
+        for (slab, size) in self.slabs.iter().zip(SIZE_CLASSES) {
+            if slab.pages > 0 {
+                println!(
+                    "  slab {:>4}: {} objects in use, {} pages",
+                    size, slab.allocated, slab.pages
+                );
+            }
+        }
+        println!("  large: {} pages", self.large_pages);
+    }
+}
+
+// Kernel heap, on top of the frame allocator
+//
+// The lock masks IRQs so interrupt handlers can allocate too.
+struct KernelAllocator {
+    heap: SpinLockIrq<Heap>,
+}
+
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator {
+    heap: SpinLockIrq::new(Heap {
+        slabs: [Slab::EMPTY; SIZE_CLASSES.len()],
+        large_pages: 0,
+    }),
+};
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        let object = heap.alloc(layout);
+        if object.is_null() {
+            heap.report_oom(layout);
+        }
+        object
+    }
+
+    unsafe fn dealloc(&self, object: *mut u8, layout: Layout) {
+        self.heap.lock().dealloc(object, layout);
+    }
+}
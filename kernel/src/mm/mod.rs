@@ -0,0 +1,37 @@
+use devtree::DevTree;
+
+pub mod address_space;
+pub mod frame;
+mod heap;
+pub mod paging;
+
+pub const PAGE_SIZE: usize = 4096;
+
+pub const fn align_down(value: u64, align: u64) -> u64 {
+    value & !(align - 1)
+}
+
+pub const fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
+
+/// RAM banks from the `/memory` nodes, as (base, size)
+pub fn memory_regions(devtree: &DevTree) -> impl Iterator<Item = (u64, u64)> {
+    let root = devtree.root();
+    let (address_cells, size_cells) = (root.address_cells(), root.size_cells());
+
+    root.children()
+        .filter(|node| node.property("device_type").and_then(|prop| prop.as_string()) == Some("memory"))
+        .flat_map(move |node| node.reg(address_cells, size_cells))
+}
+
+/// Where the bootloader put the initrd, from `/chosen`
+pub fn initrd_range(devtree: &DevTree) -> Option<(u64, u64)> {
+    let chosen = devtree.find_node("/chosen")?;
+    // Either one or two cells, whatever the bootloader felt like
+    let read = |name| {
+        let prop = chosen.property(name)?;
+        prop.as_u64().or_else(|| prop.as_u32().map(u64::from))
+    };
+    Some((read("linux,initrd-start")?, read("linux,initrd-end")?))
+}
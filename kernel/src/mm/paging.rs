@@ -0,0 +1,473 @@
+use core::arch::asm;
+use core::cell::UnsafeCell;
+
+use devtree::DevTree;
+use sync::SpinLockIrq;
+
+use crate::boot::BootInfo;
+
+use super::{PAGE_SIZE, align_down, align_up, frame, memory_regions};
+
+pub(super) const ENTRIES: usize = 512;
+
+// Bytes covered by one entry at each level, 4 KiB granule
+const LEVEL_SHIFTS: [u32; 4] = [39, 30, 21, 12];
+const BLOCK_SIZE: u64 = 1 << 21;
+
+// Descriptor bits
+pub(super) const DESC_VALID: u64 = 1 << 0;
+// Table at levels 0-2, page at level 3, block when clear
+pub(super) const DESC_TABLE: u64 = 1 << 1;
+const DESC_ATTR_DEVICE: u64 = (MAIR_DEVICE_INDEX as u64) << 2;
+const DESC_ATTR_NORMAL: u64 = (MAIR_NORMAL_INDEX as u64) << 2;
+const DESC_AP_EL0: u64 = 1 << 6;
+const DESC_AP_RO: u64 = 1 << 7;
+const DESC_SH_INNER: u64 = 0b11 << 8;
+const DESC_AF: u64 = 1 << 10;
+const DESC_NG: u64 = 1 << 11;
+const DESC_PXN: u64 = 1 << 53;
+const DESC_UXN: u64 = 1 << 54;
+pub(super) const DESC_ADDRESS: u64 = 0x0000_ffff_ffff_f000;
+
+// MAIR_EL1 slots: Device-nGnRE and write-back cacheable normal memory
+const MAIR_DEVICE_INDEX: usize = 0;
+const MAIR_NORMAL_INDEX: usize = 1;
+const MAIR: u64 = (0x04 << (MAIR_DEVICE_INDEX * 8)) | (0xff << (MAIR_NORMAL_INDEX * 8));
+
+// TCR_EL1: 48-bit TTBR0 space with 4 KiB granules, cacheable inner
+// shareable walks and TTBR1 walks disabled. IPS is filled in at runtime.
+const TCR_T0SZ: u64 = 64 - 48;
+const TCR_IRGN0_WBWA: u64 = 0b01 << 8;
+const TCR_ORGN0_WBWA: u64 = 0b01 << 10;
+const TCR_SH0_INNER: u64 = 0b11 << 12;
+const TCR_EPD1: u64 = 1 << 23;
+const TCR_IPS_SHIFT: u64 = 32;
+
+// SCTLR_EL1 bits
+const SCTLR_M: u64 = 1 << 0;
+const SCTLR_C: u64 = 1 << 2;
+const SCTLR_I: u64 = 1 << 12;
+
+// Attributes of a mapping, as descriptor bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flags(u64);
+
+impl Flags {
+    const NORMAL: u64 = DESC_ATTR_NORMAL | DESC_SH_INNER | DESC_AF;
+
+    pub const KERNEL_TEXT: Flags = Flags(Self::NORMAL | DESC_AP_RO | DESC_UXN);
+    pub const KERNEL_RODATA: Flags = Flags(Self::NORMAL | DESC_AP_RO | DESC_PXN | DESC_UXN);
+    pub const KERNEL_DATA: Flags = Flags(Self::NORMAL | DESC_PXN | DESC_UXN);
+    pub const DEVICE: Flags = Flags(DESC_ATTR_DEVICE | DESC_AF | DESC_PXN | DESC_UXN);
+
+    /// Normal memory EL0 can access, never executable at EL1. Not global,
+    /// since each address space maps it differently.
+    pub const fn user(write: bool, execute: bool) -> Flags {
+        let mut bits = Self::NORMAL | DESC_AP_EL0 | DESC_NG | DESC_PXN;
+        if !write {
+            bits |= DESC_AP_RO;
+        }
+        if !execute {
+            bits |= DESC_UXN;
+        }
+        Flags(bits)
+    }
+
+    /// Whether EL0 can write through the mapping
+    pub fn user_writable(self) -> bool {
+        self.0 & (DESC_AP_EL0 | DESC_AP_RO) == DESC_AP_EL0
+    }
+
+    pub(super) fn bits(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// No memory left for another translation table
+    OutOfTables,
+    /// No memory left for the pages themselves
+    OutOfMemory,
+    /// Part of the range is already mapped, by the kernel or before
+    Overlap,
+    /// Part of the range isn't mapped
+    NotMapped,
+    /// The range wraps around or goes past `address_space::USER_LIMIT`
+    OutOfRange,
+}
+
+#[repr(C, align(4096))]
+pub struct PageTable(pub(super) [u64; ENTRIES]);
+
+impl PageTable {
+    pub const fn new() -> Self {
+        PageTable([0; ENTRIES])
+    }
+}
+
+// A tree of translation tables
+//
+// Everything is identity mapped, so table addresses work as pointers
+// whether the MMU is on or not.
+pub struct PageTables {
+    root: *mut PageTable,
+}
+
+impl PageTables {
+    /// # Safety
+    ///
+    /// `root` must be a zeroed or valid level 0 table that stays around for
+    /// as long as the tree is used.
+    pub unsafe fn new(root: *mut PageTable) -> Self {
+        PageTables { root }
+    }
+
+    /// Physical address to load into a TTBR
+    pub fn root_address(&self) -> u64 {
+        self.root as u64
+    }
+
+    /// Map the pages covering `size` bytes at `virt` to `phys`, using 2 MiB
+    /// blocks where alignment allows. `new_table` hands out zeroed tables.
+    /// Whatever was mapped in the range before is replaced, break before
+    /// make, and blocks it only partly covers get split.
+    pub fn map(
+        &mut self,
+        virt: u64,
+        phys: u64,
+        size: u64,
+        flags: Flags,
+        new_table: &mut dyn FnMut() -> Option<*mut PageTable>,
+    ) -> Result<(), MapError> {
+        let mut virt_addr = align_down(virt, PAGE_SIZE as u64);
+        let mut phys_addr = align_down(phys, PAGE_SIZE as u64);
+        let end = align_up(virt + size, PAGE_SIZE as u64);
+
+        while virt_addr < end {
+            let mut table = self.root;
+            for level in 0..2 {
+                let entry = unsafe { &mut (*table).0[index(virt_addr, level)] };
+                table = next_table(entry, level, new_table)?;
+            }
+
+            // Blocks can't replace a table that may hold finer mappings
+            let entry = unsafe { &mut (*table).0[index(virt_addr, 2)] };
+            let can_block = virt_addr.is_multiple_of(BLOCK_SIZE)
+                && phys_addr.is_multiple_of(BLOCK_SIZE)
+                && end - virt_addr >= BLOCK_SIZE
+                && *entry & (DESC_VALID | DESC_TABLE) != (DESC_VALID | DESC_TABLE);
+            if can_block {
+                break_entry(entry);
+                *entry = phys_addr | flags.0 | DESC_VALID;
+                virt_addr += BLOCK_SIZE;
+                phys_addr += BLOCK_SIZE;
+                continue;
+            }
+
+            table = next_table(entry, 2, new_table)?;
+            let entry = unsafe { &mut (*table).0[index(virt_addr, 3)] };
+            break_entry(entry);
+            *entry = phys_addr | flags.0 | DESC_VALID | DESC_TABLE;
+            virt_addr += PAGE_SIZE as u64;
+            phys_addr += PAGE_SIZE as u64;
+        }
+
+        flush_tlb();
+        Ok(())
+    }
+
+    /// Whether `virt` is mapped, by a page or a block
+    pub fn is_mapped(&self, virt: u64) -> bool {
+        let mut table = self.root;
+        for level in 0..3 {
+            let entry = unsafe { (*table).0[index(virt, level)] };
+            if entry & DESC_VALID == 0 || entry & DESC_TABLE == 0 {
+                // Nothing there, or a block
+                return entry & DESC_VALID != 0;
+            }
+            table = (entry & DESC_ADDRESS) as *mut PageTable;
+        }
+        unsafe { (*table).0[index(virt, 3)] & DESC_VALID != 0 }
+    }
+
+    /// Remove the mappings of the pages covering `size` bytes at `virt`,
+    /// splitting blocks that are only partly unmapped
+    pub fn unmap(
+        &mut self,
+        virt: u64,
+        size: u64,
+        new_table: &mut dyn FnMut() -> Option<*mut PageTable>,
+    ) -> Result<(), MapError> {
+        let mut virt_addr = align_down(virt, PAGE_SIZE as u64);
+        let end = align_up(virt + size, PAGE_SIZE as u64);
+
+        'pages: while virt_addr < end {
+            let mut table = self.root;
+            for level in 0..3 {
+                let entry = unsafe { &mut (*table).0[index(virt_addr, level)] };
+                if *entry & DESC_VALID == 0 {
+                    virt_addr += PAGE_SIZE as u64;
+                    continue 'pages;
+                }
+                table = next_table(entry, level, new_table)?;
+            }
+
+            unsafe { (*table).0[index(virt_addr, 3)] = 0 };
+            virt_addr += PAGE_SIZE as u64;
+        }
+
+        flush_tlb();
+        Ok(())
+    }
+}
+
+pub(super) fn index(virt: u64, level: usize) -> usize {
+    ((virt >> LEVEL_SHIFTS[level]) as usize) & (ENTRIES - 1)
+}
+
+// Follow `entry` down a level, creating the table if there's none yet and
+// splitting it up if it's a block
+fn next_table(
+    entry: &mut u64,
+    level: usize,
+    new_table: &mut dyn FnMut() -> Option<*mut PageTable>,
+) -> Result<*mut PageTable, MapError> {
+    if *entry & DESC_VALID != 0 && *entry & DESC_TABLE != 0 {
+        return Ok((*entry & DESC_ADDRESS) as *mut PageTable);
+    }
+
+    let table = new_table().ok_or(MapError::OutOfTables)?;
+    if *entry & DESC_VALID != 0 {
+        let block = *entry & DESC_ADDRESS;
+        let attributes = *entry & !DESC_ADDRESS;
+        let child_size = 1u64 << LEVEL_SHIFTS[level + 1];
+        // Level 3 entries need the page bit that blocks don't have
+        let page = if level + 1 == 3 { DESC_TABLE } else { 0 };
+        for (i, child) in unsafe { (*table).0.iter_mut() }.enumerate() {
+            *child = (block + i as u64 * child_size) | attributes | page;
+        }
+        break_entry(entry);
+    }
+
+    // Make the table's contents visible to the walker before linking it
+    unsafe { asm!("dsb ishst") };
+    *entry = table as u64 | DESC_VALID | DESC_TABLE;
+    Ok(table)
+}
+
+// Break before make: a live entry has to be invalidated and flushed out of
+// every TLB before it's replaced, or the walker may see both at once. The
+// range is unmapped in between, so this mustn't be the block we're running
+// from or whose stack we're on.
+fn break_entry(entry: &mut u64) {
+    if *entry & DESC_VALID != 0 {
+        *entry = 0;
+        flush_tlb();
+    }
+}
+
+pub(super) fn flush_tlb() {
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vmalle1is",
+            "dsb ish",
+            "isb",
+        );
+    }
+}
+
+/// Where thread stacks get mapped, well clear of RAM and of anything user
+/// space can map. It only ever holds pages, so unmapped guard pages between
+/// stacks never mean splitting a block something else is running from.
+pub const KERNEL_STACKS: u64 = 0x0000_ff00_0000_0000;
+pub const KERNEL_STACKS_SIZE: u64 = 1 << 30;
+
+// Tables for the kernel's own mappings, handed out from a fixed pool in
+// .bss since they're needed before there's any allocator. The first one is
+// the root.
+const KERNEL_TABLE_POOL: usize = 32;
+
+struct TablePool(UnsafeCell<[PageTable; KERNEL_TABLE_POOL]>);
+
+unsafe impl Sync for TablePool {}
+
+// Only ever touched through `KERNEL_TABLES`, so the pool stays all zeroes
+// and out of .data
+static TABLE_POOL: TablePool = TablePool(UnsafeCell::new([const { PageTable::new() }; KERNEL_TABLE_POOL]));
+
+// The kernel's tables and how much of the pool they've used
+struct KernelTables {
+    tables: PageTables,
+    used: usize,
+}
+
+// The tables are only reached through the lock
+unsafe impl Send for KernelTables {}
+
+static KERNEL_TABLES: SpinLockIrq<KernelTables> = SpinLockIrq::new(KernelTables {
+    tables: PageTables {
+        root: TABLE_POOL.0.get() as *mut PageTable,
+    },
+    used: 1,
+});
+
+impl KernelTables {
+    fn map(&mut self, virt: u64, phys: u64, size: u64, flags: Flags) -> Result<(), MapError> {
+        let used = &mut self.used;
+        self.tables.map(virt, phys, size, flags, &mut || new_kernel_table(used))
+    }
+
+    fn unmap(&mut self, virt: u64, size: u64) -> Result<(), MapError> {
+        let used = &mut self.used;
+        self.tables.unmap(virt, size, &mut || new_kernel_table(used))
+    }
+}
+
+fn new_kernel_table(used: &mut usize) -> Option<*mut PageTable> {
+    if *used == KERNEL_TABLE_POOL {
+        // Only works once the frame allocator is up
+        return frame::alloc_zeroed_frame().map(|frame| frame as *mut PageTable);
+    }
+    *used += 1;
+    Some(unsafe { (TABLE_POOL.0.get() as *mut PageTable).add(*used - 1) })
+}
+
+/// Identity map `size` bytes at `phys` in the kernel's tables
+pub fn kernel_map(phys: u64, size: u64, flags: Flags) -> Result<(), MapError> {
+    KERNEL_TABLES.lock().map(phys, phys, size, flags)
+}
+
+/// Map `size` bytes at `virt` to `phys` in the kernel's tables, for the
+/// regions outside the identity map
+pub fn kernel_map_at(virt: u64, phys: u64, size: u64, flags: Flags) -> Result<(), MapError> {
+    KERNEL_TABLES.lock().map(virt, phys, size, flags)
+}
+
+/// Whether the kernel's tables map `virt`
+pub fn kernel_is_mapped(virt: u64) -> bool {
+    KERNEL_TABLES.lock().tables.is_mapped(virt)
+}
+
+/// Remove `size` bytes at `virt` from the kernel's tables
+pub fn kernel_unmap(virt: u64, size: u64) -> Result<(), MapError> {
+    KERNEL_TABLES.lock().unmap(virt, size)
+}
+
+/// Map a device's registers, drivers call this before touching them so it
+/// works whether the MMU is on yet or not
+pub fn map_device(base: usize, size: usize) {
+    kernel_map(base as u64, size as u64, Flags::DEVICE).expect("Out of kernel page tables");
+}
+
+/// Root of the kernel's tables, for other CPUs to load
+pub fn kernel_root() -> u64 {
+    KERNEL_TABLES.lock().tables.root_address()
+}
+
+/// Copy the kernel's root table into `table`, the start of a new address
+/// space
+pub(super) fn copy_kernel_root(table: &mut PageTable) {
+    let kernel = KERNEL_TABLES.lock();
+    table.0 = unsafe { (*kernel.tables.root).0 };
+}
+
+/// Root of the tables the calling CPU uses
+pub fn active_root() -> u64 {
+    let root: u64;
+    unsafe { asm!("mrs {}, ttbr0_el1", out(reg) root) };
+    root
+}
+
+/// Switch the calling CPU to the tables at `root`, which must map the
+/// kernel like its own tables do
+pub fn activate(root: u64) {
+    unsafe {
+        asm!(
+            "msr ttbr0_el1, {}",
+            "isb",
+            "tlbi vmalle1",
+            "dsb nsh",
+            "isb",
+            in(reg) root,
+        );
+    }
+}
+
+unsafe extern "C" {
+    static __rodata_start: u8;
+    static __data_start: u8;
+}
+
+/// Map RAM and the kernel image with their section permissions, then turn
+/// the MMU and caches on
+pub fn init(boot: &BootInfo, devtree: &DevTree) {
+    let kernel_start = boot.image_base as u64;
+    let rodata_start = &raw const __rodata_start as u64;
+    let data_start = &raw const __data_start as u64;
+    let kernel_end = boot.image_end() as u64;
+
+    let sections = [
+        (kernel_start, rodata_start, Flags::KERNEL_TEXT),
+        (rodata_start, data_start, Flags::KERNEL_RODATA),
+        (data_start, kernel_end, Flags::KERNEL_DATA),
+    ];
+    for (start, end, flags) in sections {
+        kernel_map(start, end - start, flags).expect("Out of kernel page tables");
+    }
+
+    // The rest of RAM is data, the kernel's range was mapped above
+    for (base, size) in memory_regions(devtree) {
+        let end = base + size;
+        if base < kernel_start {
+            kernel_map(base, end.min(kernel_start) - base, Flags::KERNEL_DATA).expect("Out of kernel page tables");
+        }
+        if end > kernel_end {
+            let start = base.max(kernel_end);
+            kernel_map(start, end - start, Flags::KERNEL_DATA).expect("Out of kernel page tables");
+        }
+    }
+
+    // Address spaces copy the root table, so the stack region's table has
+    // to be linked in before there are any for later stacks to show up in
+    let mut kernel = KERNEL_TABLES.lock();
+    let KernelTables { tables, used } = &mut *kernel;
+    let entry = unsafe { &mut (*tables.root).0[index(KERNEL_STACKS, 0)] };
+    assert!(*entry & DESC_VALID == 0, "RAM overlaps the kernel stack region");
+    next_table(entry, 0, &mut || new_kernel_table(used)).expect("Out of kernel page tables");
+    drop(kernel);
+
+    enable(kernel_root());
+}
+
+/// Load `root` into TTBR0 and turn the MMU and caches on for the calling
+/// CPU
+pub fn enable(root: u64) {
+    let mmfr0: u64;
+    unsafe { asm!("mrs {}, id_aa64mmfr0_el1", out(reg) mmfr0) };
+    // Output addresses as wide as the CPU supports
+    let tcr = TCR_T0SZ | TCR_IRGN0_WBWA | TCR_ORGN0_WBWA | TCR_SH0_INNER | TCR_EPD1 | ((mmfr0 & 0b111) << TCR_IPS_SHIFT);
+
+    unsafe {
+        asm!(
+            "msr mair_el1, {mair}",
+            "msr tcr_el1, {tcr}",
+            "msr ttbr0_el1, {root}",
+            "isb",
+            "tlbi vmalle1",
+            "ic iallu",
+            "dsb nsh",
+            "isb",
+            "mrs {tmp}, sctlr_el1",
+            "orr {tmp}, {tmp}, {bits}",
+            "msr sctlr_el1, {tmp}",
+            "isb",
+            mair = in(reg) MAIR,
+            tcr = in(reg) tcr,
+            root = in(reg) root,
+            bits = in(reg) SCTLR_M | SCTLR_C | SCTLR_I,
+            tmp = out(reg) _,
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{console, machine, smp, task};
+
+// Frames to print before giving up on the backtrace
+const MAX_FRAMES: usize = 32;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+// Like `println!`, but never waits for the console lock
+macro_rules! panic_println {
+    ($($arg:tt)*) => (console::_emergency_print(format_args!("{}\n", format_args!($($arg)*))));
+}
+
+unsafe extern "C" {
+    static stack_bottom: u8;
+    static stack_top: u8;
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // A plain load and store rather than a swap, exclusives don't work
+    // until the MMU is on
+    if PANICKING.load(Ordering::Relaxed) {
+        panic_println!("\n!!! Panicked while panicking");
+        if let Some(location) = info.location() {
+            panic_println!("at {}", location);
+        }
+        stop();
+    }
+    PANICKING.store(true, Ordering::Relaxed);
+
+    // Keep the rest of the kernel from running on, or printing over us
+    smp::stop_others();
+
+    panic_println!("\n!!! KERNEL PANIC on CPU {} !!!", machine::cpu_id());
+    if let Some(location) = info.location() {
+        panic_println!("at {}", location);
+    }
+    panic_println!("{}", info.message());
+
+    backtrace();
+    stop();
+}
+
+// Walk the frame records x29 points to. Each one holds the caller's x29
+// followed by the return address, and they live at increasing addresses
+// as we go up the call chain.
+fn backtrace() {
+    let mut fp: usize;
+    unsafe { asm!("mov {}, x29", out(reg) fp) };
+
+    // Before per-CPU data exists only the boot CPU runs, on the linker's stack
+    let (bottom, top) = match (task::current_stack(), smp::try_current()) {
+        (Some(stack), _) => stack,
+        (None, Some(cpu)) => (cpu.stack_bottom, cpu.stack_top),
+        (None, None) => (&raw const stack_bottom as usize, &raw const stack_top as usize),
+    };
+
+    panic_println!("Backtrace:");
+    for depth in 0..MAX_FRAMES {
+        if fp < bottom || fp + 16 > top || fp % 8 != 0 {
+            break;
+        }
+
+        let next = unsafe { *(fp as *const usize) };
+        let lr = unsafe { *((fp + 8) as *const usize) };
+        if lr == 0 {
+            break;
+        }
+
+        // The return address points right after the call
+        panic_println!("  #{:<2} {:#018x}", depth, lr - 4);
+
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+}
+
+fn stop() -> ! {
+    #[cfg(feature = "qemu-exit")]
+    machine::exit_qemu(1);
+
+    #[cfg(not(feature = "qemu-exit"))]
+    {
+        // Only returns when there's no firmware to power off through
+        crate::psci::system_off();
+        machine::halt();
+    }
+}
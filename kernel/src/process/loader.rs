@@ -0,0 +1,187 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use elf::stack::{self, InitialStack};
+use elf::{Elf, ElfError, ElfType, PROGRAM_HEADER_SIZE, SegmentFlags};
+
+use super::{DEFAULT_STACK_SIZE, Image, MAX_STACK_SIZE, MIN_ADDRESS, USER_STACK_TOP, map_stack};
+use crate::drivers::arch_timer;
+use crate::mm::address_space::AddressSpace;
+use crate::mm::paging::{Flags, MapError};
+use crate::mm::{PAGE_SIZE, align_down, align_up};
+
+const PAGE: u64 = PAGE_SIZE as u64;
+
+// Where position independent programs go, clear of the kernel's identity
+// map below and the stack above
+const DYN_BASE: u64 = 0x0000_5555_5555_0000;
+
+// AT_HWCAP bits for what every ARMv8-A CPU the kernel runs on has
+const HWCAP_FP: u64 = 1 << 0;
+const HWCAP_ASIMD: u64 = 1 << 1;
+
+// Clock ticks per second times() counts in, as on Linux
+const CLOCK_TICKS: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    Elf(ElfError),
+    /// Dynamically linked, which needs a dynamic linker the kernel can't
+    /// load
+    NeedsInterpreter,
+    /// A segment below `MIN_ADDRESS` or in the way of the stack
+    BadAddress,
+    Map(MapError),
+    /// No memory for the address space or thread
+    OutOfMemory,
+}
+
+impl From<ElfError> for LoadError {
+    fn from(error: ElfError) -> Self {
+        LoadError::Elf(error)
+    }
+}
+
+impl From<MapError> for LoadError {
+    fn from(error: MapError) -> Self {
+        LoadError::Map(error)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Elf(error) => write!(f, "bad ELF file: {}", error),
+            LoadError::NeedsInterpreter => f.write_str("dynamically linked programs aren't supported"),
+            LoadError::BadAddress => f.write_str("segment at an unusable address"),
+            LoadError::Map(error) => write!(f, "couldn't map segment: {:?}", error),
+            LoadError::OutOfMemory => f.write_str("out of memory"),
+        }
+    }
+}
+
+/// Map the ELF program in `file` into `space` along with its stack, which
+/// gets `argv`, `envp` and the auxiliary vector. `path` is where the file
+/// came from, for `AT_EXECFN`.
+pub fn load(space: &mut AddressSpace, file: &[u8], path: &str, argv: &[&str], envp: &[&str]) -> Result<Image, LoadError> {
+    let elf = Elf::parse(file)?;
+    if elf.interpreter().is_some() {
+        return Err(LoadError::NeedsInterpreter);
+    }
+
+    let bias = match elf.kind() {
+        ElfType::Executable => 0,
+        ElfType::SharedObject => {
+            let align = elf.segments().map(|ph| ph.align).fold(PAGE, u64::max);
+            let lowest = elf.segments().map(|ph| ph.vaddr).min().unwrap_or(0);
+            align_up(DYN_BASE, align).wrapping_sub(align_down(lowest, align))
+        }
+    };
+
+    let (stack_flags, stack_size) = elf.stack();
+    // Capped before rounding, the file can ask for anything
+    let stack_size = align_up(stack_size.min(MAX_STACK_SIZE), PAGE).max(DEFAULT_STACK_SIZE);
+    let stack_bottom = USER_STACK_TOP.checked_sub(stack_size).ok_or(LoadError::BadAddress)?;
+
+    // Segments can share a page at their ends, which then gets the
+    // permissions of both
+    let mut pages: BTreeMap<u64, (bool, bool)> = BTreeMap::new();
+    let mut image_end = 0;
+    for ph in elf.loaded_segments() {
+        let start = ph.vaddr.wrapping_add(bias);
+        let end = start.checked_add(ph.mem_size).ok_or(LoadError::BadAddress)?;
+        if start < MIN_ADDRESS || end > stack_bottom {
+            return Err(LoadError::BadAddress);
+        }
+        image_end = image_end.max(end);
+
+        let write = ph.flags.contains(SegmentFlags::WRITE);
+        let execute = ph.flags.contains(SegmentFlags::EXECUTE);
+        for page in (align_down(start, PAGE)..align_up(end, PAGE)).step_by(PAGE_SIZE) {
+            let permissions = pages.entry(page).or_default();
+            permissions.0 |= write;
+            permissions.1 |= execute;
+        }
+    }
+
+    // In runs of pages with the same permissions
+    let mut pages = pages.into_iter().peekable();
+    while let Some((start, permissions)) = pages.next() {
+        let mut end = start + PAGE;
+        while let Some(&(page, next)) = pages.peek()
+            && page == end
+            && next == permissions
+        {
+            pages.next();
+            end += PAGE;
+        }
+        let (write, execute) = permissions;
+        space.map(start, end - start, Flags::user(write, execute))?;
+    }
+
+    // Pages come zeroed, which takes care of .bss and anything else past
+    // the file data
+    for ph in elf.loaded_segments() {
+        let address = ph.vaddr.wrapping_add(bias);
+        space.write(address, elf.segment_data(&ph))?;
+        if ph.flags.contains(SegmentFlags::EXECUTE) {
+            space.sync_icache(address, ph.file_size)?;
+        }
+    }
+
+    let top = map_stack(space, stack_size, stack_flags.contains(SegmentFlags::EXECUTE))?;
+    let entry = elf.entry().wrapping_add(bias);
+    let mut auxv = Vec::from([
+        (stack::AT_PHENT, PROGRAM_HEADER_SIZE as u64),
+        (stack::AT_PHNUM, elf.program_header_count() as u64),
+        (stack::AT_PAGESZ, PAGE),
+        (stack::AT_BASE, 0),
+        (stack::AT_FLAGS, 0),
+        (stack::AT_ENTRY, entry),
+        (stack::AT_UID, 0),
+        (stack::AT_EUID, 0),
+        (stack::AT_GID, 0),
+        (stack::AT_EGID, 0),
+        (stack::AT_HWCAP, HWCAP_FP | HWCAP_ASIMD),
+        (stack::AT_CLKTCK, CLOCK_TICKS),
+        (stack::AT_SECURE, 0),
+    ]);
+    if let Some(phdr) = elf.program_header_address() {
+        auxv.push((stack::AT_PHDR, phdr.wrapping_add(bias)));
+    }
+
+    let initial = InitialStack {
+        argv,
+        envp,
+        auxv: &auxv,
+        execfn: path,
+        random: random_seed(),
+    };
+    let (sp, bytes) = initial.build(top);
+    space.write(sp, &bytes)?;
+
+    Ok(Image {
+        entry,
+        sp,
+        brk: align_up(image_end, PAGE),
+    })
+}
+
+// Bytes for AT_RANDOM, mixed from the timer count with splitmix64. Not
+// unpredictable in any real sense, there's no entropy source yet.
+fn random_seed() -> [u8; 16] {
+    let mut state = arch_timer::counter();
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+
+    let mut seed = [0; 16];
+    seed[..8].copy_from_slice(&next().to_le_bytes());
+    seed[8..].copy_from_slice(&next().to_le_bytes());
+    seed
+}
@@ -0,0 +1,79 @@
+use super::{MIN_ADDRESS, MMAP_TOP};
+use crate::mm::address_space::AddressSpace;
+use crate::mm::paging::{Flags, MapError};
+use crate::mm::{PAGE_SIZE, align_up};
+
+const PAGE: u64 = PAGE_SIZE as u64;
+
+// A process's address space, plus where its heap and anonymous mappings go
+//
+// The break starts right after the loaded image and grows up. Mappings
+// without a fixed address get handed out going down from `MMAP_TOP`, and
+// addresses freed by `munmap` aren't reused.
+pub(super) struct Memory {
+    pub(super) space: AddressSpace,
+    brk_start: u64,
+    brk: u64,
+    mmap_next: u64,
+}
+
+impl Memory {
+    pub(super) fn new(space: AddressSpace, brk: u64) -> Memory {
+        Memory {
+            space,
+            brk_start: brk,
+            brk,
+            mmap_next: MMAP_TOP,
+        }
+    }
+
+    /// Move the break to `address`, returns where it is afterwards, which is
+    /// where it was if it can't go there
+    pub(super) fn set_brk(&mut self, address: u64) -> u64 {
+        if address < self.brk_start || address > self.mmap_next {
+            return self.brk;
+        }
+
+        let old_end = align_up(self.brk, PAGE);
+        let new_end = align_up(address, PAGE);
+        if new_end > old_end {
+            if self.space.map(old_end, new_end - old_end, Flags::user(true, false)).is_err() {
+                return self.brk;
+            }
+        } else if new_end < old_end {
+            self.space.unmap(new_end, old_end - new_end);
+        }
+
+        self.brk = address;
+        address
+    }
+
+    /// Map `size` bytes of zeroes, at exactly `fixed` if given, replacing
+    /// whatever was there. Returns the address.
+    pub(super) fn map_anonymous(&mut self, fixed: Option<u64>, size: u64, flags: Flags) -> Result<u64, MapError> {
+        let size = size.checked_next_multiple_of(PAGE).ok_or(MapError::OutOfMemory)?;
+        let address = match fixed {
+            Some(address) => {
+                if address < MIN_ADDRESS || address.checked_add(size).is_none_or(|end| end > MMAP_TOP) {
+                    return Err(MapError::Overlap);
+                }
+                self.space.unmap(address, size);
+                address
+            }
+            None => {
+                let address = self.mmap_next.checked_sub(size).ok_or(MapError::OutOfMemory)?;
+                if address < align_up(self.brk, PAGE) {
+                    return Err(MapError::OutOfMemory);
+                }
+                address
+            }
+        };
+
+        self.space.map(address, size, flags)?;
+        // Only taken once it's really mapped, a failed mmap gives it back
+        if fixed.is_none() {
+            self.mmap_next = address;
+        }
+        Ok(address)
+    }
+}
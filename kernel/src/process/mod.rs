@@ -0,0 +1,222 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use sync::{Mutex, SpinLockIrq};
+
+use crate::exception::{ExceptionClass, FaultStatus, TrapFrame};
+use crate::fs::{self, Dentry, FdTable};
+use crate::machine;
+use crate::mm::PAGE_SIZE;
+use crate::mm::address_space::AddressSpace;
+use crate::mm::paging::{self, Flags, MapError};
+use crate::task::{self, ThreadId};
+
+use self::memory::Memory;
+
+mod loader;
+mod memory;
+mod syscall;
+
+pub use self::loader::LoadError;
+
+/// Top of the user stack, well clear of anything the kernel maps
+pub const USER_STACK_TOP: u64 = 0x0000_7fff_ffff_0000;
+/// Stack size unless the program asks for more
+pub const DEFAULT_STACK_SIZE: u64 = 256 * 1024;
+// Most a program gets if it asks for more, anonymous mappings go below
+const MAX_STACK_SIZE: u64 = 8 * 1024 * 1024;
+const MMAP_TOP: u64 = USER_STACK_TOP - MAX_STACK_SIZE - PAGE_SIZE as u64;
+
+// Nothing gets mapped below this, so null pointers keep faulting
+const MIN_ADDRESS: u64 = 0x1_0000;
+
+// Signals a fault kills a process with, reported as 128 + signal like a
+// shell would
+const SIGILL: i32 = 4;
+const SIGTRAP: i32 = 5;
+const SIGBUS: i32 = 7;
+const SIGSEGV: i32 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u32);
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// A user program running on one thread in its own address space
+//
+// The process stays around as a zombie after it exits, until `wait`
+// collects its exit code.
+pub struct Process {
+    pid: Pid,
+    name: String,
+    thread: ThreadId,
+    /// None once the process has exited
+    memory: Mutex<Option<Memory>>,
+    files: Mutex<FdTable>,
+    cwd: Mutex<Arc<Dentry>>,
+    exit: SpinLockIrq<ExitState>,
+}
+
+#[derive(Default)]
+struct ExitState {
+    code: Option<i32>,
+    /// Threads parked in `wait`, all woken at once
+    waiters: Vec<ThreadId>,
+}
+
+// Where a program starts once it's in memory
+pub struct Image {
+    pub entry: u64,
+    pub sp: u64,
+    /// Initial program break, right after the image
+    pub brk: u64,
+}
+
+static PROCESSES: SpinLockIrq<BTreeMap<Pid, Arc<Process>>> = SpinLockIrq::new(BTreeMap::new());
+static NEXT_PID: AtomicU32 = AtomicU32::new(1);
+
+unsafe extern "C" {
+    fn return_to_user(frame: *const TrapFrame) -> !;
+}
+
+/// Map a fresh stack of `size` bytes below `USER_STACK_TOP`, returns its
+/// top
+pub fn map_stack(space: &mut AddressSpace, size: u64, executable: bool) -> Result<u64, MapError> {
+    space.map(USER_STACK_TOP - size, size, Flags::user(true, executable))?;
+    Ok(USER_STACK_TOP)
+}
+
+/// Start a process running `image` in `space`, none if there's no memory
+/// for its kernel stack
+pub fn spawn(name: &str, space: AddressSpace, image: Image) -> Option<Pid> {
+    let files = FdTable::with_console();
+    let cwd = fs::root();
+
+    // Held until the process is in the table, which also keeps the new
+    // thread from running before then since IRQs are masked
+    let mut processes = PROCESSES.lock();
+    let pid = Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed));
+    let (entry, sp) = (image.entry, image.sp);
+    let thread = task::spawn_with_tables("user", space.root(), move || enter(entry, sp))?;
+
+    let process = Process {
+        pid,
+        name: String::from(name),
+        thread,
+        memory: Mutex::new(Some(Memory::new(space, image.brk))),
+        files: Mutex::new(files),
+        cwd: Mutex::new(cwd),
+        exit: SpinLockIrq::new(ExitState::default()),
+    };
+    processes.insert(pid, Arc::new(process));
+    Some(pid)
+}
+
+/// Start the ELF program in `file`, which came from `path`, with `argv`
+/// and `envp`
+pub fn spawn_elf(path: &str, file: &[u8], argv: &[&str], envp: &[&str]) -> Result<Pid, LoadError> {
+    let mut space = AddressSpace::new().ok_or(LoadError::OutOfMemory)?;
+    let image = loader::load(&mut space, file, path, argv, envp)?;
+    spawn(path, space, image).ok_or(LoadError::OutOfMemory)
+}
+
+fn enter(entry: u64, sp: u64) -> ! {
+    machine::disable_irqs();
+    let frame = TrapFrame::user(entry, sp);
+    unsafe { return_to_user(&frame) }
+}
+
+/// The process the calling thread belongs to
+pub fn current() -> Option<Arc<Process>> {
+    let thread = task::current()?;
+    PROCESSES.lock().values().find(|process| process.thread == thread).cloned()
+}
+
+/// Deal with a synchronous exception from EL0: a syscall, or a fault that
+/// kills the process
+pub fn handle_sync(frame: &mut TrapFrame, class: ExceptionClass) {
+    let process = current().expect("Exception from EL0 outside a process");
+
+    let signal = match class {
+        ExceptionClass::Svc { .. } => {
+            // Syscalls can take a while, let the tick preempt them
+            machine::enable_irqs();
+            if let Some(code) = syscall::dispatch(&process, frame) {
+                drop(process);
+                exit(code);
+            }
+            machine::disable_irqs();
+            return;
+        }
+        ExceptionClass::DataAbort { status: FaultStatus::Alignment, .. }
+        | ExceptionClass::InstructionAbort { status: FaultStatus::Alignment, .. }
+        | ExceptionClass::PcAlignment { .. }
+        | ExceptionClass::SpAlignment => SIGBUS,
+        ExceptionClass::DataAbort { .. } | ExceptionClass::InstructionAbort { .. } => SIGSEGV,
+        ExceptionClass::Brk { .. }
+        | ExceptionClass::Breakpoint
+        | ExceptionClass::SoftwareStep
+        | ExceptionClass::Watchpoint { .. } => SIGTRAP,
+        _ => SIGILL,
+    };
+
+    println!("Process {} ({}) killed: {} at {:#x}", process.pid, process.name, class, frame.elr);
+    drop(process);
+    machine::enable_irqs();
+    exit(128 + signal);
+}
+
+/// End the calling process with `code`, waking whoever waits for it. Takes
+/// its mutexes, so IRQs have to be unmasked.
+pub fn exit(code: i32) -> ! {
+    let process = current().expect("Only processes can exit");
+
+    // Off the process's tables before they're freed
+    task::set_tables(paging::kernel_root());
+    process.memory.lock().take();
+    process.files.lock().clear();
+
+    let waiters = {
+        let mut exit = process.exit.lock();
+        exit.code = Some(code);
+        core::mem::take(&mut exit.waiters)
+    };
+    for waiter in waiters {
+        task::wake(waiter);
+    }
+
+    drop(process);
+    task::exit();
+}
+
+/// Wait for `pid` to exit and collect its exit code, none if there's no
+/// such process. Threads waiting together all get the code.
+pub fn wait(pid: Pid) -> Option<i32> {
+    let process = PROCESSES.lock().get(&pid).cloned()?;
+    let waiter = task::current().expect("Only threads can wait");
+
+    let code = loop {
+        {
+            let mut exit = process.exit.lock();
+            if let Some(code) = exit.code {
+                break code;
+            }
+            // Parks can end early, only get in line once
+            if !exit.waiters.contains(&waiter) {
+                exit.waiters.push(waiter);
+            }
+        }
+        task::park();
+    };
+
+    PROCESSES.lock().remove(&pid);
+    Some(code)
+}
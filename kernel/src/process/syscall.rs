@@ -0,0 +1,529 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::memory::Memory;
+use super::Process;
+use crate::exception::TrapFrame;
+use crate::fs::{self, Dentry, FileType, FsError, Metadata, OpenFile, OpenFlags, SeekFrom};
+use crate::mm::PAGE_SIZE;
+use crate::mm::address_space::USER_LIMIT;
+use crate::mm::paging::Flags;
+use crate::time;
+
+// Linux AArch64 syscall numbers
+const SYS_GETCWD: u64 = 17;
+const SYS_IOCTL: u64 = 29;
+const SYS_MKDIRAT: u64 = 34;
+const SYS_CHDIR: u64 = 49;
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_GETDENTS64: u64 = 61;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_WRITEV: u64 = 66;
+const SYS_NEWFSTATAT: u64 = 79;
+const SYS_FSTAT: u64 = 80;
+const SYS_EXIT: u64 = 93;
+const SYS_EXIT_GROUP: u64 = 94;
+const SYS_SET_TID_ADDRESS: u64 = 96;
+const SYS_CLOCK_GETTIME: u64 = 113;
+const SYS_UNAME: u64 = 160;
+const SYS_GETPID: u64 = 172;
+const SYS_GETTID: u64 = 178;
+const SYS_BRK: u64 = 214;
+const SYS_MUNMAP: u64 = 215;
+const SYS_MMAP: u64 = 222;
+
+// mmap arguments
+const PROT_WRITE: u64 = 1 << 1;
+const PROT_EXEC: u64 = 1 << 2;
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+
+// Clocks for clock_gettime, all of them count from boot since there's no
+// RTC driver to tell the time of day
+const CLOCK_REALTIME: u64 = 0;
+const CLOCK_MONOTONIC: u64 = 1;
+const CLOCK_MONOTONIC_RAW: u64 = 4;
+const CLOCK_REALTIME_COARSE: u64 = 5;
+const CLOCK_MONOTONIC_COARSE: u64 = 6;
+const CLOCK_BOOTTIME: u64 = 7;
+
+const PATH_MAX: usize = 4096;
+const IOV_MAX: u64 = 1024;
+
+// openat flags, the AArch64 values
+const O_ACCMODE: u64 = 0o3;
+const O_RDONLY: u64 = 0o0;
+const O_WRONLY: u64 = 0o1;
+const O_CREAT: u64 = 0o100;
+const O_EXCL: u64 = 0o200;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+const O_DIRECTORY: u64 = 0o40000;
+const O_NOFOLLOW: u64 = 0o100000;
+
+// The *at syscalls
+const AT_FDCWD: i32 = -100;
+const AT_SYMLINK_NOFOLLOW: u64 = 0x100;
+const AT_EMPTY_PATH: u64 = 0x1000;
+
+const SEEK_SET: u64 = 0;
+const SEEK_CUR: u64 = 1;
+const SEEK_END: u64 = 2;
+
+// Most bytes one read or write moves, anything bigger is a short one
+const MAX_IO: u64 = 64 * 1024;
+
+// An error number, handed back to the process negated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Errno(i64);
+
+impl Errno {
+    pub const ENOENT: Errno = Errno(2);
+    pub const EBADF: Errno = Errno(9);
+    pub const ENOMEM: Errno = Errno(12);
+    pub const EFAULT: Errno = Errno(14);
+    pub const EBUSY: Errno = Errno(16);
+    pub const EEXIST: Errno = Errno(17);
+    pub const ENODEV: Errno = Errno(19);
+    pub const ENOTDIR: Errno = Errno(20);
+    pub const EISDIR: Errno = Errno(21);
+    pub const EINVAL: Errno = Errno(22);
+    pub const EMFILE: Errno = Errno(24);
+    pub const ENOTTY: Errno = Errno(25);
+    pub const ENOSPC: Errno = Errno(28);
+    pub const ESPIPE: Errno = Errno(29);
+    pub const EROFS: Errno = Errno(30);
+    pub const ERANGE: Errno = Errno(34);
+    pub const ENAMETOOLONG: Errno = Errno(36);
+    pub const ENOSYS: Errno = Errno(38);
+    pub const ENOTEMPTY: Errno = Errno(39);
+    pub const ELOOP: Errno = Errno(40);
+    pub const EOPNOTSUPP: Errno = Errno(95);
+}
+
+impl From<FsError> for Errno {
+    fn from(error: FsError) -> Errno {
+        match error {
+            FsError::NotFound => Errno::ENOENT,
+            FsError::NotDirectory => Errno::ENOTDIR,
+            FsError::IsDirectory => Errno::EISDIR,
+            FsError::Exists => Errno::EEXIST,
+            FsError::NotEmpty => Errno::ENOTEMPTY,
+            FsError::InvalidArgument => Errno::EINVAL,
+            FsError::NameTooLong => Errno::ENAMETOOLONG,
+            FsError::SymlinkLoop => Errno::ELOOP,
+            FsError::Busy => Errno::EBUSY,
+            FsError::BadDescriptor => Errno::EBADF,
+            FsError::TooManyFiles => Errno::EMFILE,
+            FsError::NotSeekable => Errno::ESPIPE,
+            FsError::NoSpace => Errno::ENOSPC,
+            FsError::ReadOnly => Errno::EROFS,
+            FsError::NotSupported => Errno::EOPNOTSUPP,
+        }
+    }
+}
+
+type SyscallResult = Result<u64, Errno>;
+
+/// Run the syscall in `frame`, number in x8 and arguments in x0-x5, and
+/// put the result in x0. Returns the exit code instead if the process
+/// asked to exit.
+pub(super) fn dispatch(process: &Process, frame: &mut TrapFrame) -> Option<i32> {
+    let [a0, a1, a2, a3, a4, _] = [frame.x[0], frame.x[1], frame.x[2], frame.x[3], frame.x[4], frame.x[5]];
+    let number = frame.x[8];
+
+    let result = match number {
+        SYS_GETCWD => getcwd(process, a0, a1),
+        SYS_IOCTL => ioctl(process, a0),
+        SYS_MKDIRAT => mkdirat(process, a0, a1, a2),
+        SYS_CHDIR => chdir(process, a0),
+        SYS_OPENAT => openat(process, a0, a1, a2, a3),
+        SYS_CLOSE => close(process, a0),
+        SYS_GETDENTS64 => getdents64(process, a0, a1, a2),
+        SYS_LSEEK => lseek(process, a0, a1, a2),
+        SYS_READ => read(process, a0, a1, a2),
+        SYS_WRITE => write(process, a0, a1, a2),
+        SYS_WRITEV => writev(process, a0, a1, a2),
+        SYS_NEWFSTATAT => newfstatat(process, a0, a1, a2, a3),
+        SYS_FSTAT => fstat(process, a0, a1),
+        SYS_EXIT | SYS_EXIT_GROUP => return Some(a0 as i32 & 0xff),
+        // Processes have one thread, so its ID is the PID. Nothing waits
+        // on the address, so it's not kept.
+        SYS_SET_TID_ADDRESS | SYS_GETPID | SYS_GETTID => Ok(process.pid.0 as u64),
+        SYS_CLOCK_GETTIME => clock_gettime(process, a0, a1),
+        SYS_UNAME => uname(process, a0),
+        SYS_BRK => Ok(with_memory(process, |memory| memory.set_brk(a0))),
+        SYS_MUNMAP => munmap(process, a0, a1),
+        SYS_MMAP => mmap(process, a0, a1, a2, a3, a4),
+        _ => {
+            println!("Process {} ({}): unsupported syscall {}", process.pid, process.name, number);
+            Err(Errno::ENOSYS)
+        }
+    };
+
+    frame.x[0] = match result {
+        Ok(value) => value,
+        Err(Errno(errno)) => -errno as u64,
+    };
+    None
+}
+
+fn with_memory<R>(process: &Process, f: impl FnOnce(&mut Memory) -> R) -> R {
+    let mut memory = process.memory.lock();
+    f(memory.as_mut().expect("Syscall from a process that exited"))
+}
+
+fn copy_from_user(memory: &Memory, address: u64, buf: &mut [u8]) -> Result<(), Errno> {
+    memory.space.read(address, buf).map_err(|_| Errno::EFAULT)
+}
+
+fn copy_to_user(memory: &mut Memory, address: u64, data: &[u8]) -> Result<(), Errno> {
+    if !memory.space.is_writable(address, data.len() as u64) {
+        return Err(Errno::EFAULT);
+    }
+    memory.space.write(address, data).map_err(|_| Errno::EFAULT)
+}
+
+// NUL-terminated string at `address`, paths that aren't UTF-8 can't exist
+// so they count as not found
+fn read_c_string(memory: &Memory, address: u64) -> Result<String, Errno> {
+    let mut bytes = Vec::new();
+    let mut byte = [0];
+    loop {
+        let at = address.checked_add(bytes.len() as u64).ok_or(Errno::EFAULT)?;
+        copy_from_user(memory, at, &mut byte)?;
+        if byte[0] == 0 {
+            break;
+        }
+        if bytes.len() == PATH_MAX {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        bytes.push(byte[0]);
+    }
+    String::from_utf8(bytes).map_err(|_| Errno::ENOENT)
+}
+
+fn read_path(process: &Process, address: u64) -> Result<String, Errno> {
+    with_memory(process, |memory| read_c_string(memory, address))
+}
+
+// File descriptors are ints, whatever's in the upper half of the register
+fn fd_index(fd: u64) -> Result<usize, Errno> {
+    usize::try_from(fd as i32).map_err(|_| Errno::EBADF)
+}
+
+fn file(process: &Process, fd: u64) -> Result<Arc<OpenFile>, Errno> {
+    Ok(process.files.lock().get(fd_index(fd)?)?)
+}
+
+// Where a path given to one of the *at syscalls starts from
+fn start_dir(process: &Process, dirfd: u64, path: &str) -> Result<Arc<Dentry>, Errno> {
+    if path.starts_with('/') || dirfd as i32 == AT_FDCWD {
+        return Ok(process.cwd.lock().clone());
+    }
+    match file(process, dirfd)?.dentry() {
+        Some(dentry) if dentry.kind() == FileType::Directory => Ok(dentry.clone()),
+        _ => Err(Errno::ENOTDIR),
+    }
+}
+
+fn ioctl(process: &Process, fd: u64) -> SyscallResult {
+    file(process, fd)?;
+    // No terminal control yet
+    Err(Errno::ENOTTY)
+}
+
+fn openat(process: &Process, dirfd: u64, path: u64, flags: u64, mode: u64) -> SyscallResult {
+    let path = read_path(process, path)?;
+    let start = start_dir(process, dirfd, &path)?;
+
+    let access = flags & O_ACCMODE;
+    if access == O_ACCMODE {
+        return Err(Errno::EINVAL);
+    }
+    let flags = OpenFlags {
+        read: access != O_WRONLY,
+        write: access != O_RDONLY,
+        append: flags & O_APPEND != 0,
+        create: flags & O_CREAT != 0,
+        exclusive: flags & O_EXCL != 0,
+        truncate: flags & O_TRUNC != 0,
+        directory: flags & O_DIRECTORY != 0,
+        no_follow: flags & O_NOFOLLOW != 0,
+    };
+
+    let file = fs::open(&start, &path, flags, (mode & 0o7777) as u16)?;
+    Ok(process.files.lock().insert(file)? as u64)
+}
+
+fn close(process: &Process, fd: u64) -> SyscallResult {
+    process.files.lock().remove(fd_index(fd)?)?;
+    Ok(0)
+}
+
+fn read(process: &Process, fd: u64, buf: u64, count: u64) -> SyscallResult {
+    let file = file(process, fd)?;
+    // Nothing to check the buffer for, and a console read shouldn't wait
+    if count == 0 {
+        return Ok(0);
+    }
+    let len = count.min(MAX_IO);
+    // Checked before a console read blocks for input that'd then be lost
+    if !with_memory(process, |memory| memory.space.is_writable(buf, len)) {
+        return Err(Errno::EFAULT);
+    }
+
+    let mut data = vec![0; len as usize];
+    let read = file.read(&mut data)?;
+    with_memory(process, |memory| copy_to_user(memory, buf, &data[..read]))?;
+    Ok(read as u64)
+}
+
+fn write(process: &Process, fd: u64, buf: u64, count: u64) -> SyscallResult {
+    let file = file(process, fd)?;
+    // musl's writev-based stdio starts with an empty iovec
+    if count == 0 {
+        return Ok(0);
+    }
+    let mut data = vec![0; count.min(MAX_IO) as usize];
+    with_memory(process, |memory| copy_from_user(memory, buf, &mut data))?;
+    Ok(file.write(&data)? as u64)
+}
+
+fn writev(process: &Process, fd: u64, iov: u64, iovcnt: u64) -> SyscallResult {
+    if iovcnt > IOV_MAX {
+        return Err(Errno::EINVAL);
+    }
+
+    let mut total = 0;
+    for i in 0..iovcnt {
+        // struct iovec { void *iov_base; size_t iov_len; }
+        let mut entry = [0; 16];
+        let at = iov.checked_add(i * 16).ok_or(Errno::EFAULT)?;
+        with_memory(process, |memory| copy_from_user(memory, at, &mut entry))?;
+        let base = u64::from_le_bytes(entry[..8].try_into().unwrap());
+        let len = u64::from_le_bytes(entry[8..].try_into().unwrap());
+        let written = match write(process, fd, base, len) {
+            Ok(written) => written,
+            // What went out before counts, like a short write
+            Err(_) if total > 0 => break,
+            Err(error) => return Err(error),
+        };
+        total += written;
+        if written < len {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+fn lseek(process: &Process, fd: u64, offset: u64, whence: u64) -> SyscallResult {
+    let to = match whence {
+        SEEK_SET => SeekFrom::Start(offset),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return Err(Errno::EINVAL),
+    };
+    Ok(file(process, fd)?.seek(to)?)
+}
+
+/// Fill `dirp` with as many `struct linux_dirent64` as fit in `count`
+fn getdents64(process: &Process, fd: u64, dirp: u64, count: u64) -> SyscallResult {
+    // struct linux_dirent64 { u64 d_ino; s64 d_off; u16 d_reclen;
+    //                         u8 d_type; char d_name[]; }, 8 byte aligned
+    const HEADER: usize = 19;
+
+    let file = file(process, fd)?;
+    let mut out = Vec::new();
+    while let Some(entry) = file.read_dir()? {
+        let reclen = (HEADER + entry.name.len() + 1).next_multiple_of(8);
+        if (out.len() + reclen) as u64 > count {
+            file.unread_dir();
+            if out.is_empty() {
+                return Err(Errno::EINVAL);
+            }
+            break;
+        }
+
+        // The offset to seek back to for the entry after this one
+        let next = file.seek(SeekFrom::Current(0))?;
+        let start = out.len();
+        out.extend_from_slice(&entry.ino.to_le_bytes());
+        out.extend_from_slice(&next.to_le_bytes());
+        out.extend_from_slice(&(reclen as u16).to_le_bytes());
+        out.push(dirent_type(entry.kind));
+        out.extend_from_slice(entry.name.as_bytes());
+        out.resize(start + reclen, 0);
+    }
+
+    with_memory(process, |memory| copy_to_user(memory, dirp, &out))?;
+    Ok(out.len() as u64)
+}
+
+fn dirent_type(kind: FileType) -> u8 {
+    match kind {
+        FileType::CharDevice => 2,
+        FileType::Directory => 4,
+        FileType::Regular => 8,
+        FileType::Symlink => 10,
+    }
+}
+
+// struct stat as the generic syscall ABI lays it out
+fn stat_bytes(dev: u32, metadata: &Metadata) -> [u8; 128] {
+    let file_type = match metadata.kind {
+        FileType::Regular => 0o100000,
+        FileType::Directory => 0o040000,
+        FileType::Symlink => 0o120000,
+        FileType::CharDevice => 0o020000,
+    };
+
+    let mut stat = [0; 128];
+    stat[0..8].copy_from_slice(&(dev as u64).to_le_bytes());
+    stat[8..16].copy_from_slice(&metadata.ino.to_le_bytes());
+    stat[16..20].copy_from_slice(&(file_type | metadata.mode as u32).to_le_bytes());
+    stat[20..24].copy_from_slice(&metadata.nlink.to_le_bytes());
+    // st_uid and st_gid stay 0, everything runs as root
+    stat[48..56].copy_from_slice(&metadata.size.to_le_bytes());
+    stat[56..60].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+    stat[64..72].copy_from_slice(&metadata.size.div_ceil(512).to_le_bytes());
+    stat
+}
+
+fn fstat(process: &Process, fd: u64, statbuf: u64) -> SyscallResult {
+    let file = file(process, fd)?;
+    let dev = file.dentry().map_or(0, |dentry| dentry.inode().dev());
+    let stat = stat_bytes(dev, &file.metadata()?);
+    with_memory(process, |memory| copy_to_user(memory, statbuf, &stat))?;
+    Ok(0)
+}
+
+fn newfstatat(process: &Process, dirfd: u64, path: u64, statbuf: u64, flags: u64) -> SyscallResult {
+    let path = read_path(process, path)?;
+    if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        return fstat(process, dirfd, statbuf);
+    }
+
+    let start = start_dir(process, dirfd, &path)?;
+    let dentry = fs::lookup(&start, &path, flags & AT_SYMLINK_NOFOLLOW == 0)?;
+    let stat = stat_bytes(dentry.inode().dev(), &dentry.inode().metadata()?);
+    with_memory(process, |memory| copy_to_user(memory, statbuf, &stat))?;
+    Ok(0)
+}
+
+/// Returns the length of the path including its NUL, like the kernel does
+/// rather than libc
+fn getcwd(process: &Process, buf: u64, size: u64) -> SyscallResult {
+    let mut path = process.cwd.lock().path().into_bytes();
+    path.push(0);
+    if path.len() as u64 > size {
+        return Err(Errno::ERANGE);
+    }
+    with_memory(process, |memory| copy_to_user(memory, buf, &path))?;
+    Ok(path.len() as u64)
+}
+
+fn chdir(process: &Process, path: u64) -> SyscallResult {
+    let path = read_path(process, path)?;
+    let mut cwd = process.cwd.lock();
+    let dentry = fs::lookup(&cwd, &path, true)?;
+    if dentry.kind() != FileType::Directory {
+        return Err(Errno::ENOTDIR);
+    }
+    *cwd = dentry;
+    Ok(0)
+}
+
+fn mkdirat(process: &Process, dirfd: u64, path: u64, mode: u64) -> SyscallResult {
+    let path = read_path(process, path)?;
+    let start = start_dir(process, dirfd, &path)?;
+    fs::mkdir(&start, &path, (mode & 0o7777) as u16)?;
+    Ok(0)
+}
+
+fn clock_gettime(process: &Process, clock: u64, timespec: u64) -> SyscallResult {
+    match clock {
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_REALTIME_COARSE | CLOCK_MONOTONIC_COARSE
+        | CLOCK_BOOTTIME => {}
+        _ => return Err(Errno::EINVAL),
+    }
+
+    // struct timespec { time_t tv_sec; long tv_nsec; }
+    let now = time::uptime();
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&now.as_secs().to_le_bytes());
+    bytes[8..].copy_from_slice(&(now.subsec_nanos() as u64).to_le_bytes());
+    with_memory(process, |memory| copy_to_user(memory, timespec, &bytes))?;
+    Ok(0)
+}
+
+fn uname(process: &Process, buf: u64) -> SyscallResult {
+    // struct utsname: six NUL-padded 65 byte fields
+    const FIELD: usize = 65;
+    let fields = [
+        "silly-kernel",
+        "silly",
+        env!("CARGO_PKG_VERSION"),
+        "#1",
+        "aarch64",
+        "(none)",
+    ];
+
+    let mut utsname = [0; 6 * FIELD];
+    for (i, field) in fields.iter().enumerate() {
+        utsname[i * FIELD..i * FIELD + field.len()].copy_from_slice(field.as_bytes());
+    }
+    with_memory(process, |memory| copy_to_user(memory, buf, &utsname))?;
+    Ok(0)
+}
+
+/// Anonymous private or shared mappings only, which are the same thing
+/// without fork
+fn mmap(process: &Process, address: u64, len: u64, prot: u64, flags: u64, fd: u64) -> SyscallResult {
+    if len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    if flags & MAP_ANONYMOUS == 0 || fd as i64 != -1 {
+        // No file mappings yet
+        return Err(Errno::ENODEV);
+    }
+    let fixed = if flags & MAP_FIXED != 0 {
+        if !address.is_multiple_of(PAGE_SIZE as u64) {
+            return Err(Errno::EINVAL);
+        }
+        Some(address)
+    } else {
+        None
+    };
+
+    // There's no way to deny EL0 reads, so PROT_NONE still reads as zeroes
+    let execute = prot & PROT_EXEC != 0;
+    let page_flags = Flags::user(prot & PROT_WRITE != 0, execute);
+    with_memory(process, |memory| {
+        let address = memory
+            .map_anonymous(fixed, len, page_flags)
+            .map_err(|_| Errno::ENOMEM)?;
+        if execute {
+            // Whatever the frames held before may still be in the
+            // instruction cache
+            memory.space.sync_icache(address, len).map_err(|_| Errno::ENOMEM)?;
+        }
+        Ok(address)
+    })
+}
+
+fn munmap(process: &Process, address: u64, len: u64) -> SyscallResult {
+    if len == 0 || !address.is_multiple_of(PAGE_SIZE as u64) {
+        return Err(Errno::EINVAL);
+    }
+    if address.checked_add(len).is_none_or(|end| end > USER_LIMIT) {
+        return Err(Errno::EINVAL);
+    }
+    with_memory(process, |memory| memory.space.unmap(address, len));
+    Ok(0)
+}
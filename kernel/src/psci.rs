@@ -0,0 +1,254 @@
+use core::arch::asm;
+use core::fmt;
+
+use devtree::{DevTree, DevTreeNode};
+use sync::Once;
+
+// PSCI 0.2+ function IDs, SMC64 where there's a choice
+const PSCI_VERSION: u32 = 0x8400_0000;
+const PSCI_CPU_OFF: u32 = 0x8400_0002;
+const PSCI_CPU_ON: u32 = 0xc400_0003;
+const PSCI_AFFINITY_INFO: u32 = 0xc400_0004;
+const PSCI_SYSTEM_OFF: u32 = 0x8400_0008;
+const PSCI_SYSTEM_RESET: u32 = 0x8400_0009;
+
+// How calls reach the firmware, as `/psci`'s `method` says
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conduit {
+    Hvc,
+    Smc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsciError {
+    NotSupported,
+    InvalidParameters,
+    Denied,
+    AlreadyOn,
+    OnPending,
+    InternalFailure,
+    NotPresent,
+    Disabled,
+    InvalidAddress,
+    /// There's no PSCI firmware, or it lacks the function
+    Unavailable,
+    Unknown(i32),
+}
+
+impl PsciError {
+    fn check(ret: u64) -> Result<u64, PsciError> {
+        let error = match ret as i32 {
+            0.. => return Ok(ret),
+            -1 => PsciError::NotSupported,
+            -2 => PsciError::InvalidParameters,
+            -3 => PsciError::Denied,
+            -4 => PsciError::AlreadyOn,
+            -5 => PsciError::OnPending,
+            -6 => PsciError::InternalFailure,
+            -7 => PsciError::NotPresent,
+            -8 => PsciError::Disabled,
+            -9 => PsciError::InvalidAddress,
+            other => PsciError::Unknown(other),
+        };
+        Err(error)
+    }
+}
+
+impl fmt::Display for PsciError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PsciError::NotSupported => write!(f, "not supported"),
+            PsciError::InvalidParameters => write!(f, "invalid parameters"),
+            PsciError::Denied => write!(f, "denied"),
+            PsciError::AlreadyOn => write!(f, "already on"),
+            PsciError::OnPending => write!(f, "on pending"),
+            PsciError::InternalFailure => write!(f, "internal failure"),
+            PsciError::NotPresent => write!(f, "not present"),
+            PsciError::Disabled => write!(f, "disabled"),
+            PsciError::InvalidAddress => write!(f, "invalid address"),
+            PsciError::Unavailable => write!(f, "no PSCI firmware"),
+            PsciError::Unknown(code) => write!(f, "unknown error {}", code),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AffinityState {
+    On,
+    Off,
+    OnPending,
+}
+
+// Function IDs the firmware implements, none for ones it doesn't
+#[derive(Debug, Clone, Copy)]
+struct Functions {
+    version: Option<u32>,
+    cpu_off: Option<u32>,
+    cpu_on: Option<u32>,
+    affinity_info: Option<u32>,
+    system_off: Option<u32>,
+    system_reset: Option<u32>,
+}
+
+impl Functions {
+    // PSCI 0.2 fixed the IDs, 0.1 firmware lists its own in the node and
+    // has no system calls at all
+    fn from_node(node: &DevTreeNode) -> Functions {
+        if node.is_compatible("arm,psci-1.0") || node.is_compatible("arm,psci-0.2") {
+            return Functions {
+                version: Some(PSCI_VERSION),
+                cpu_off: Some(PSCI_CPU_OFF),
+                cpu_on: Some(PSCI_CPU_ON),
+                affinity_info: Some(PSCI_AFFINITY_INFO),
+                system_off: Some(PSCI_SYSTEM_OFF),
+                system_reset: Some(PSCI_SYSTEM_RESET),
+            };
+        }
+
+        let id = |name| node.property(name).and_then(|prop| prop.as_u32());
+        Functions {
+            version: None,
+            cpu_off: id("cpu_off"),
+            cpu_on: id("cpu_on"),
+            affinity_info: None,
+            system_off: None,
+            system_reset: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Firmware {
+    conduit: Conduit,
+    functions: Functions,
+}
+
+static FIRMWARE: Once<Firmware> = Once::new();
+
+/// Find the firmware through `/psci`, returns whether it's usable from the
+/// EL the kernel was entered at
+pub fn init(devtree: &DevTree, entry_el: u8) -> bool {
+    let Some(node) = devtree.find_node("/psci").filter(|node| node.is_enabled()) else {
+        return false;
+    };
+    let conduit = match node.property("method").and_then(|prop| prop.as_string()) {
+        Some("hvc") => Conduit::Hvc,
+        Some("smc") => Conduit::Smc,
+        _ => return false,
+    };
+
+    // Entered above EL1 we own the EL the conduit traps to, and there's
+    // nothing there to answer
+    let target_el = match conduit {
+        Conduit::Hvc => 2,
+        Conduit::Smc => 3,
+    };
+    if entry_el >= target_el {
+        return false;
+    }
+
+    let firmware = Firmware {
+        conduit,
+        functions: Functions::from_node(&node),
+    };
+    FIRMWARE.call_once(|| firmware);
+    true
+}
+
+fn firmware() -> Result<Firmware, PsciError> {
+    FIRMWARE.get().copied().ok_or(PsciError::Unavailable)
+}
+
+fn call(function: fn(&Functions) -> Option<u32>, arg0: u64, arg1: u64, arg2: u64) -> Result<u64, PsciError> {
+    let firmware = firmware()?;
+    let id = function(&firmware.functions).ok_or(PsciError::Unavailable)?;
+
+    let mut ret = id as u64;
+    unsafe {
+        match firmware.conduit {
+            Conduit::Hvc => asm!(
+                "hvc #0",
+                inout("x0") ret,
+                inout("x1") arg0 => _,
+                inout("x2") arg1 => _,
+                inout("x3") arg2 => _,
+                clobber_abi("C"),
+            ),
+            Conduit::Smc => asm!(
+                "smc #0",
+                inout("x0") ret,
+                inout("x1") arg0 => _,
+                inout("x2") arg1 => _,
+                inout("x3") arg2 => _,
+                clobber_abi("C"),
+            ),
+        }
+    }
+
+    PsciError::check(ret)
+}
+
+/// Get the PSCI version the firmware implements, 0.1 can't tell
+pub fn psci_version() -> Result<Version, PsciError> {
+    if firmware()?.functions.version.is_none() {
+        return Ok(Version { major: 0, minor: 1 });
+    }
+    let version = call(|f| f.version, 0, 0, 0)?;
+    Ok(Version {
+        major: (version >> 16) as u16,
+        minor: version as u16,
+    })
+}
+
+/// Start the CPU with affinity `mpidr` at physical address `entry`, with
+/// `context` in its x0
+pub fn cpu_on(mpidr: u64, entry: u64, context: u64) -> Result<(), PsciError> {
+    call(|f| f.cpu_on, mpidr, entry, context).map(|_| ())
+}
+
+/// Power the calling CPU down, only returns if the firmware refuses
+pub fn cpu_off() -> PsciError {
+    match call(|f| f.cpu_off, 0, 0, 0) {
+        Ok(_) => PsciError::InternalFailure,
+        Err(error) => error,
+    }
+}
+
+/// Get whether the CPU with affinity `mpidr` is running
+pub fn affinity_info(mpidr: u64) -> Result<AffinityState, PsciError> {
+    // Lowest affinity level, the CPU itself
+    match call(|f| f.affinity_info, mpidr, 0, 0)? {
+        0 => Ok(AffinityState::On),
+        1 => Ok(AffinityState::Off),
+        2 => Ok(AffinityState::OnPending),
+        other => Err(PsciError::Unknown(other as i32)),
+    }
+}
+
+/// Power the machine off, only returns if that isn't possible
+pub fn system_off() -> PsciError {
+    match call(|f| f.system_off, 0, 0, 0) {
+        Ok(_) => PsciError::InternalFailure,
+        Err(error) => error,
+    }
+}
+
+/// Reset the machine, only returns if that isn't possible
+pub fn system_reset() -> PsciError {
+    match call(|f| f.system_reset, 0, 0, 0) {
+        Ok(_) => PsciError::InternalFailure,
+        Err(error) => error,
+    }
+}
@@ -0,0 +1,281 @@
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use devtree::DevTree;
+use sync::SpinLockIrq;
+
+use crate::drivers::irqchip;
+use crate::mm::{PAGE_SIZE, frame, paging};
+use crate::time::{self, Instant, TimerState};
+use crate::{exception, machine, psci};
+
+const SECONDARY_STACK_SIZE: usize = 64 * 1024;
+const OVERFLOW_STACK_SIZE: usize = 16 * 1024;
+const START_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Raised on every other CPU by a panicking one
+const STOP_SGI: u32 = 0;
+
+// Data private to each CPU, reached through TPIDR_EL1
+//
+// The first three fields are for the exception vectors, src/arch/aarch64/
+// vectors.S has their offsets.
+#[derive(Debug)]
+#[repr(C)]
+pub struct PerCpu {
+    /// Bottom of the stack this CPU is running on, exception frames that
+    /// would go below it go on the overflow stack instead
+    pub stack_limit: AtomicUsize,
+    pub overflow_stack_top: usize,
+    // Where the vectors keep x1 on their way to the overflow stack
+    overflow_scratch: AtomicUsize,
+    /// 0 for the boot CPU, then in the order the others were started,
+    /// including any that never came up
+    pub index: usize,
+    pub mpidr: u64,
+    pub stack_bottom: usize,
+    pub stack_top: usize,
+    pub timer: SpinLockIrq<TimerState>,
+}
+
+/// This CPU's data, none before `init_boot_cpu`
+pub fn try_current() -> Option<&'static PerCpu> {
+    let per_cpu: usize;
+    unsafe { asm!("mrs {}, tpidr_el1", out(reg) per_cpu) };
+    unsafe { (per_cpu as *const PerCpu).as_ref() }
+}
+
+pub fn current() -> &'static PerCpu {
+    try_current().expect("Per-CPU data isn't set up")
+}
+
+fn set_current(per_cpu: &'static PerCpu) {
+    unsafe { asm!("msr tpidr_el1, {}", in(reg) per_cpu as *const PerCpu) };
+}
+
+unsafe extern "C" {
+    static stack_bottom: u8;
+    static stack_top: u8;
+    fn secondary_entry();
+}
+
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+// Index for the next CPU to be started
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(1);
+
+/// Number of CPUs running the kernel
+pub fn cpus_online() -> usize {
+    CPUS_ONLINE.load(Ordering::Relaxed)
+}
+
+// Top of a stack to handle exceptions on once the running one is full
+fn alloc_overflow_stack() -> Option<usize> {
+    let stack = frame::alloc_frames(OVERFLOW_STACK_SIZE / PAGE_SIZE, PAGE_SIZE)?;
+    Some(stack as usize + OVERFLOW_STACK_SIZE)
+}
+
+/// Give the boot CPU its per-CPU data, on the stack from linker.ld
+pub fn init_boot_cpu() {
+    let overflow_stack = alloc_overflow_stack().expect("No memory for the overflow stack");
+    let per_cpu = Box::leak(Box::new(PerCpu {
+        stack_limit: AtomicUsize::new(&raw const stack_bottom as usize),
+        overflow_stack_top: overflow_stack,
+        overflow_scratch: AtomicUsize::new(0),
+        index: 0,
+        mpidr: machine::cpu_id(),
+        stack_bottom: &raw const stack_bottom as usize,
+        stack_top: &raw const stack_top as usize,
+        timer: SpinLockIrq::new(TimerState::new()),
+    }));
+    set_current(per_cpu);
+    CPUS_ONLINE.store(1, Ordering::Relaxed);
+}
+
+// Everything a secondary CPU needs before it can run Rust code, read by
+// boot.S with the MMU and caches still off. One CPU starts at a time, a
+// CPU that times out may still read it later, so it only ever takes the
+// mailbox while `mpidr` names it.
+#[repr(C, align(64))]
+struct SecondaryBoot {
+    stack_top: u64,
+    per_cpu: u64,
+    mair: u64,
+    tcr: u64,
+    ttbr0: u64,
+    mpidr: u64,
+}
+
+// Matches no CPU, its affinity fields leave out the high bits
+const NO_CPU: u64 = u64::MAX;
+
+struct Mailbox(UnsafeCell<SecondaryBoot>);
+
+unsafe impl Sync for Mailbox {}
+
+#[unsafe(export_name = "secondary_boot")]
+static SECONDARY_BOOT: Mailbox = Mailbox(UnsafeCell::new(SecondaryBoot {
+    stack_top: 0,
+    per_cpu: 0,
+    mair: 0,
+    tcr: 0,
+    ttbr0: 0,
+    mpidr: NO_CPU,
+}));
+
+// Set by each secondary once it's done with the mailbox
+static SECONDARY_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy)]
+enum EnableMethod {
+    Psci,
+    SpinTable { release_addr: u64 },
+}
+
+/// Start every other CPU in `/cpus`, returns how many are online after
+pub fn start_secondaries(devtree: &DevTree) -> usize {
+    irqchip::register_irq(STOP_SGI, handle_stop);
+
+    let Some(cpus) = devtree.find_node("/cpus") else {
+        return cpus_online();
+    };
+    let address_cells = cpus.address_cells();
+    let boot_mpidr = machine::cpu_id();
+
+    let nodes = cpus
+        .children()
+        .filter(|node| node.property("device_type").and_then(|prop| prop.as_string()) == Some("cpu"))
+        .filter(|node| node.is_enabled());
+
+    for node in nodes {
+        let Some((mpidr, _)) = node.reg(address_cells, 0).next() else {
+            continue;
+        };
+        if mpidr == boot_mpidr {
+            continue;
+        }
+
+        let method = match node.property("enable-method").and_then(|prop| prop.as_string()) {
+            Some("psci") => EnableMethod::Psci,
+            Some("spin-table") => match node.property("cpu-release-addr").and_then(|prop| prop.as_u64()) {
+                Some(release_addr) => EnableMethod::SpinTable { release_addr },
+                None => {
+                    println!("CPU {:#x}: spin-table without cpu-release-addr", mpidr);
+                    continue;
+                }
+            },
+            other => {
+                println!("CPU {:#x}: unsupported enable-method {:?}", mpidr, other);
+                continue;
+            }
+        };
+
+        // A CPU that failed keeps its index, stack and per-CPU data, should
+        // it turn up after all
+        if let Err(error) = start_cpu(mpidr, method) {
+            println!("CPU {:#x} failed to start: {}", mpidr, error);
+        }
+    }
+
+    cpus_online()
+}
+
+fn start_cpu(mpidr: u64, method: EnableMethod) -> Result<(), &'static str> {
+    let stack = frame::alloc_frames(SECONDARY_STACK_SIZE / PAGE_SIZE, PAGE_SIZE).ok_or("no memory for its stack")?;
+    let overflow_stack = alloc_overflow_stack().ok_or("no memory for its overflow stack")?;
+    let per_cpu = Box::leak(Box::new(PerCpu {
+        stack_limit: AtomicUsize::new(stack as usize),
+        overflow_stack_top: overflow_stack,
+        overflow_scratch: AtomicUsize::new(0),
+        index: NEXT_INDEX.fetch_add(1, Ordering::Relaxed),
+        mpidr,
+        stack_bottom: stack as usize,
+        stack_top: stack as usize + SECONDARY_STACK_SIZE,
+        timer: SpinLockIrq::new(TimerState::new()),
+    }));
+
+    let (mair, tcr): (u64, u64);
+    unsafe { asm!("mrs {}, mair_el1", "mrs {}, tcr_el1", out(reg) mair, out(reg) tcr) };
+    let mailbox = SECONDARY_BOOT.0.get();
+    unsafe {
+        // Taken from whichever CPU had it before anything else changes,
+        // and only handed over once the rest is in memory
+        (*mailbox).mpidr = NO_CPU;
+        clean_to_memory(mailbox as usize);
+        *mailbox = SecondaryBoot {
+            stack_top: per_cpu.stack_top as u64,
+            per_cpu: per_cpu as *const PerCpu as u64,
+            mair,
+            tcr,
+            ttbr0: paging::kernel_root(),
+            mpidr: NO_CPU,
+        };
+        clean_to_memory(mailbox as usize);
+        (*mailbox).mpidr = mpidr;
+        clean_to_memory(mailbox as usize);
+    }
+    SECONDARY_STARTED.store(false, Ordering::Relaxed);
+
+    let entry = secondary_entry as *const () as u64;
+    match method {
+        EnableMethod::Psci => psci::cpu_on(mpidr, entry, 0).map_err(|_| "PSCI CPU_ON refused")?,
+        EnableMethod::SpinTable { release_addr } => unsafe {
+            // The CPU polls this with its caches off, then waits for an event.
+            // In RAM it's mapped already, and mapping it again would split
+            // the live block around it.
+            if !paging::kernel_is_mapped(release_addr) {
+                paging::kernel_map(release_addr, 8, paging::Flags::KERNEL_DATA)
+                    .map_err(|_| "can't map its release address")?;
+            }
+            (release_addr as *mut u64).write_volatile(entry);
+            clean_to_memory(release_addr as usize);
+            asm!("sev");
+        },
+    }
+
+    let deadline = Instant::now() + START_TIMEOUT;
+    while !SECONDARY_STARTED.load(Ordering::Acquire) {
+        if Instant::now() >= deadline {
+            return Err("timed out");
+        }
+        core::hint::spin_loop();
+    }
+
+    Ok(())
+}
+
+// Push a cache line out to where a CPU with its caches off can read it
+unsafe fn clean_to_memory(address: usize) {
+    unsafe { asm!("dc cvac, {}", "dsb sy", in(reg) address) };
+}
+
+// Where every secondary CPU lands once boot.S has its MMU on
+#[unsafe(no_mangle)]
+extern "C" fn secondary_main(per_cpu: &'static PerCpu) -> ! {
+    exception::init();
+    irqchip::init_cpu();
+    irqchip::enable_irq(STOP_SGI);
+    time::init_cpu();
+
+    println!("CPU {} online ({:#x})", per_cpu.index, per_cpu.mpidr);
+    CPUS_ONLINE.fetch_add(1, Ordering::Relaxed);
+    SECONDARY_STARTED.store(true, Ordering::Release);
+
+    machine::enable_irqs();
+    loop {
+        machine::wait_for_interrupt();
+    }
+}
+
+/// Halt every other CPU that still takes interrupts, for the panic handler.
+/// Doesn't wait for them.
+pub fn stop_others() {
+    irqchip::send_sgi_to_others(STOP_SGI);
+}
+
+fn handle_stop(_irq: u32) {
+    machine::halt();
+}
@@ -0,0 +1,232 @@
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use sync::SpinLockIrq;
+
+use crate::machine;
+use crate::mm::paging;
+use crate::smp;
+use crate::time::{self, Instant};
+
+use self::scheduler::Scheduler;
+use self::thread::{Context, Entry, Stack, State};
+
+mod scheduler;
+mod thread;
+
+pub use self::thread::ThreadId;
+
+// Time slice of a thread before the next ready one gets the CPU
+const TICK: Duration = Duration::from_millis(10);
+
+// Threads run on the CPU that called `init` only, the others keep idling
+// in secondary_main
+static SCHEDULER: SpinLockIrq<Scheduler> = SpinLockIrq::new(Scheduler::new());
+static SCHEDULER_CPU: AtomicU64 = AtomicU64::new(u64::MAX);
+
+// The thread running on the scheduler CPU, for the lock owner checks,
+// which can't take the scheduler's lock themselves
+static CURRENT_THREAD: AtomicU64 = AtomicU64::new(0);
+
+// Set by the tick, acted on once the IRQ has been dealt with
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" {
+    fn switch_context(from: *mut Context, to: *const Context);
+}
+
+/// Turn the calling flow into the "main" thread and start preempting it on
+/// the timer tick, needs the timer and heap up
+pub fn init() {
+    let main = SCHEDULER.lock().adopt_current("main");
+    CURRENT_THREAD.store(main.0, Ordering::Relaxed);
+    let stack = Stack::new().expect("No memory for the idle thread's stack");
+    SCHEDULER.lock().spawn_idle(stack, Box::new(idle));
+    SCHEDULER_CPU.store(machine::cpu_id(), Ordering::Relaxed);
+
+    time::set_tick_handler(tick);
+    time::start_periodic_tick(TICK);
+    sync::set_yield_hook(yield_now);
+    sync::set_thread_hook(current_thread);
+}
+
+fn current_thread() -> Option<usize> {
+    on_scheduler_cpu().then(|| CURRENT_THREAD.load(Ordering::Relaxed) as usize)
+}
+
+fn idle() {
+    loop {
+        machine::wait_for_interrupt();
+    }
+}
+
+fn on_scheduler_cpu() -> bool {
+    SCHEDULER_CPU.load(Ordering::Relaxed) == machine::cpu_id()
+}
+
+/// Start a thread running `entry`, none if there's no memory for its stack
+pub fn spawn(name: &'static str, entry: impl FnOnce() + Send + 'static) -> Option<ThreadId> {
+    spawn_with_tables(name, paging::kernel_root(), entry)
+}
+
+/// Like `spawn`, but the thread runs under the tables at `ttbr0`, which
+/// have to map the kernel too
+pub fn spawn_with_tables(
+    name: &'static str,
+    ttbr0: u64,
+    entry: impl FnOnce() + Send + 'static,
+) -> Option<ThreadId> {
+    let stack = Stack::new()?;
+    Some(SCHEDULER.lock().spawn(name, stack, ttbr0, Box::new(entry)))
+}
+
+/// Where spawned threads start, from thread_trampoline
+#[unsafe(no_mangle)]
+extern "C" fn thread_start(entry: *mut Entry) -> ! {
+    // Switched to from schedule(), which has IRQs masked
+    finish_switch();
+    machine::enable_irqs();
+
+    let entry = unsafe { Box::from_raw(entry) };
+    entry();
+    exit();
+}
+
+// Switch to the next thread, if there's another to run
+fn schedule() {
+    let daif = machine::save_and_disable_irqs();
+    let switch = {
+        let mut scheduler = SCHEDULER.lock();
+        let switch = scheduler.pick_next();
+        if let Some(id) = scheduler.current_id() {
+            CURRENT_THREAD.store(id.0, Ordering::Relaxed);
+            // Only "main" has no stack of its own, it runs on the CPU's
+            let cpu = smp::current();
+            let limit = scheduler.current().stack.as_ref().map_or(cpu.stack_bottom, |stack| stack.bottom() as usize);
+            cpu.stack_limit.store(limit, Ordering::Relaxed);
+        }
+        switch
+    };
+    if let Some((from, to, ttbr0)) = switch {
+        if paging::active_root() != ttbr0 {
+            paging::activate(ttbr0);
+        }
+        // Comes back once this thread is picked again
+        unsafe { switch_context(from, to) };
+        finish_switch();
+    }
+    machine::restore_irqs(daif);
+}
+
+// Clean up after the thread that was just switched away from
+fn finish_switch() {
+    SCHEDULER.lock().reap();
+}
+
+fn tick() {
+    SCHEDULER.lock().wake_sleepers(Instant::now());
+    NEED_RESCHED.store(true, Ordering::Relaxed);
+}
+
+/// Switch threads if the tick asked for it, called on the way out of an
+/// IRQ
+pub fn preempt() {
+    if on_scheduler_cpu() && NEED_RESCHED.swap(false, Ordering::Relaxed) {
+        schedule();
+    }
+}
+
+/// Let the other ready threads run first
+pub fn yield_now() {
+    if !on_scheduler_cpu() {
+        core::hint::spin_loop();
+        return;
+    }
+    schedule();
+}
+
+/// Put the current thread to sleep for at least `duration`, rounded up to
+/// the tick
+pub fn sleep(duration: Duration) {
+    if !on_scheduler_cpu() {
+        time::sleep(duration);
+        return;
+    }
+    let until = Instant::now() + duration;
+    SCHEDULER.lock().current().state = State::Sleeping(until);
+    schedule();
+}
+
+/// Block until another thread calls `wake` on this one, returns right away
+/// if that already happened since the last `park`
+pub fn park() {
+    assert!(on_scheduler_cpu(), "Only threads can park");
+    {
+        let mut scheduler = SCHEDULER.lock();
+        let thread = scheduler.current();
+        if thread.wake_pending {
+            thread.wake_pending = false;
+            return;
+        }
+        thread.state = State::Blocked;
+    }
+    schedule();
+}
+
+/// Make a parked thread runnable again, returns false if it doesn't exist
+/// or has exited
+pub fn wake(id: ThreadId) -> bool {
+    SCHEDULER.lock().wake(id)
+}
+
+/// End the current thread, spawned threads do when their entry returns
+pub fn exit() -> ! {
+    assert!(on_scheduler_cpu(), "Only threads can exit");
+    SCHEDULER.lock().current().state = State::Exited;
+    schedule();
+    unreachable!("Exited thread was scheduled again");
+}
+
+/// Run the current thread under the tables at `ttbr0` from now on, which
+/// have to map the kernel too
+pub fn set_tables(ttbr0: u64) {
+    let mut scheduler = SCHEDULER.lock();
+    scheduler.current().ttbr0 = ttbr0;
+    paging::activate(ttbr0);
+}
+
+/// The calling thread, none before `init` or on other CPUs
+pub fn current() -> Option<ThreadId> {
+    if !on_scheduler_cpu() {
+        return None;
+    }
+    SCHEDULER.try_lock()?.current_id()
+}
+
+/// Bounds of the running thread's own stack, none for threads on the stack
+/// they were adopted with. Doesn't wait for the lock, so it's fine to call
+/// while panicking.
+pub fn current_stack() -> Option<(usize, usize)> {
+    if !on_scheduler_cpu() {
+        return None;
+    }
+    let mut scheduler = SCHEDULER.try_lock()?;
+    scheduler.current_id()?;
+    let stack = scheduler.current().stack.as_ref()?;
+    Some((stack.bottom() as usize, stack.top() as usize))
+}
+
+/// The thread whose guard page `address` is in, to tell a stack overflow
+/// from any other fault
+pub fn guard_page_owner(address: u64) -> Option<(ThreadId, &'static str)> {
+    if !on_scheduler_cpu() {
+        return None;
+    }
+    let scheduler = SCHEDULER.try_lock()?;
+    scheduler
+        .threads
+        .values()
+        .find(|thread| thread.stack.as_ref().is_some_and(|stack| stack.guard_contains(address)))
+        .map(|thread| (thread.id, thread.name))
+}
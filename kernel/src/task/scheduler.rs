@@ -0,0 +1,153 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+
+use super::thread::{Context, Entry, Stack, State, Thread, ThreadId};
+use crate::mm::paging;
+use crate::time::Instant;
+
+// Round-robin scheduler state
+//
+// Ready threads queue up in order, the idle thread only runs when the queue
+// is empty and is never queued itself.
+pub(super) struct Scheduler {
+    pub(super) threads: BTreeMap<ThreadId, Box<Thread>>,
+    ready: VecDeque<ThreadId>,
+    current: Option<ThreadId>,
+    idle: Option<ThreadId>,
+    next_id: u64,
+}
+
+impl Scheduler {
+    pub(super) const fn new() -> Scheduler {
+        Scheduler {
+            threads: BTreeMap::new(),
+            ready: VecDeque::new(),
+            current: None,
+            idle: None,
+            next_id: 0,
+        }
+    }
+
+    fn insert(
+        &mut self,
+        name: &'static str,
+        state: State,
+        context: Context,
+        stack: Option<Stack>,
+        ttbr0: u64,
+    ) -> ThreadId {
+        let id = ThreadId(self.next_id);
+        self.next_id += 1;
+        let thread = Thread {
+            id,
+            name,
+            state,
+            context,
+            stack,
+            ttbr0,
+            wake_pending: false,
+        };
+        self.threads.insert(id, Box::new(thread));
+        id
+    }
+
+    /// Turn whatever is running into a thread, its context gets filled in
+    /// the first time it's switched out
+    pub(super) fn adopt_current(&mut self, name: &'static str) -> ThreadId {
+        let id = self.insert(name, State::Running, Context::default(), None, paging::kernel_root());
+        self.current = Some(id);
+        id
+    }
+
+    /// Add a thread that runs `entry` on `stack` under the tables at
+    /// `ttbr0` once its turn comes
+    pub(super) fn spawn(&mut self, name: &'static str, stack: Stack, ttbr0: u64, entry: Entry) -> ThreadId {
+        let entry = Box::into_raw(Box::new(entry));
+        let context = Context::new(stack.top(), entry);
+        let id = self.insert(name, State::Ready, context, Some(stack), ttbr0);
+        self.ready.push_back(id);
+        id
+    }
+
+    /// Like `spawn`, but only ever run when nothing else is ready
+    pub(super) fn spawn_idle(&mut self, stack: Stack, entry: Entry) -> ThreadId {
+        let id = self.spawn("idle", stack, paging::kernel_root(), entry);
+        self.ready.retain(|&ready| ready != id);
+        self.idle = Some(id);
+        id
+    }
+
+    pub(super) fn current_id(&self) -> Option<ThreadId> {
+        self.current
+    }
+
+    pub(super) fn current(&mut self) -> &mut Thread {
+        let id = self.current.expect("Scheduler isn't running");
+        self.threads.get_mut(&id).expect("Current thread is gone")
+    }
+
+    fn make_ready(&mut self, id: ThreadId) {
+        if let Some(thread) = self.threads.get_mut(&id) {
+            thread.state = State::Ready;
+            if Some(id) != self.idle {
+                self.ready.push_back(id);
+            }
+        }
+    }
+
+    /// Wake `id` if it's parked, otherwise make its next park return right
+    /// away. Returns false if there's no such thread.
+    pub(super) fn wake(&mut self, id: ThreadId) -> bool {
+        let Some(thread) = self.threads.get_mut(&id) else {
+            return false;
+        };
+        match thread.state {
+            State::Blocked => self.make_ready(id),
+            State::Exited => return false,
+            _ => thread.wake_pending = true,
+        }
+        true
+    }
+
+    pub(super) fn wake_sleepers(&mut self, now: Instant) {
+        let due: alloc::vec::Vec<_> = self
+            .threads
+            .values()
+            .filter(|thread| matches!(thread.state, State::Sleeping(until) if until <= now))
+            .map(|thread| thread.id)
+            .collect();
+        for id in due {
+            self.make_ready(id);
+        }
+    }
+
+    /// Requeue the current thread if it's still runnable and pick the next
+    /// one, returns the contexts to switch between and the next one's
+    /// tables unless that's the same thread
+    pub(super) fn pick_next(&mut self) -> Option<(*mut Context, *const Context, u64)> {
+        let current_id = self.current?;
+        if self.current().state == State::Running {
+            self.make_ready(current_id);
+        }
+
+        let next_id = self.ready.pop_front().or(self.idle).expect("No idle thread");
+        self.current = Some(next_id);
+        let next = self.threads.get_mut(&next_id).expect("Ready thread is gone");
+        next.state = State::Running;
+        if next_id == current_id {
+            return None;
+        }
+
+        let to = &raw const next.context;
+        let ttbr0 = next.ttbr0;
+        let from = &raw mut self.threads.get_mut(&current_id).expect("Current thread is gone").context;
+        Some((from, to, ttbr0))
+    }
+
+    /// Free the threads that exited, except the one still on its stack
+    pub(super) fn reap(&mut self) {
+        let current = self.current;
+        self.threads
+            .retain(|&id, thread| thread.state != State::Exited || Some(id) == current);
+    }
+}
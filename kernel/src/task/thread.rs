@@ -0,0 +1,157 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+
+use sync::SpinLockIrq;
+
+use crate::mm::{PAGE_SIZE, frame, paging};
+use crate::time::Instant;
+
+// Usable stack of every spawned thread, there's an unmapped guard page
+// below it on top of this
+const STACK_PAGES: usize = 16;
+
+// What a spawned thread runs, boxed once more to pass it through x19
+pub(super) type Entry = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(pub(super) u64);
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum State {
+    Ready,
+    Running,
+    Sleeping(Instant),
+    Blocked,
+    Exited,
+}
+
+// Registers a switched out thread needs back, saved by switch_context in
+// src/arch/aarch64/switch.S
+#[repr(C)]
+#[derive(Debug, Default)]
+pub(super) struct Context {
+    x19_x28: [u64; 10],
+    fp: u64,
+    lr: u64,
+    sp: u64,
+    d8_d15: [u64; 8],
+    fpcr: u64,
+    fpsr: u64,
+    tpidr_el0: u64,
+}
+
+const _: () = assert!(size_of::<Context>() == 192);
+
+unsafe extern "C" {
+    fn thread_trampoline();
+}
+
+impl Context {
+    /// Start at thread_trampoline on `stack_top`, which hands `entry` to
+    /// thread_start
+    pub(super) fn new(stack_top: u64, entry: *mut Entry) -> Context {
+        let mut context = Context {
+            lr: thread_trampoline as *const () as u64,
+            sp: stack_top,
+            ..Context::default()
+        };
+        context.x19_x28[0] = entry as u64;
+        context
+    }
+}
+
+// Room each stack takes in `paging::KERNEL_STACKS`, its guard page and
+// then the stack
+const SLOT_SIZE: u64 = ((STACK_PAGES + 1) * PAGE_SIZE) as u64;
+
+// Slots handed out so far and the ones freed again
+struct Slots {
+    next: u64,
+    free: Vec<u64>,
+}
+
+static SLOTS: SpinLockIrq<Slots> = SpinLockIrq::new(Slots {
+    next: paging::KERNEL_STACKS,
+    free: Vec::new(),
+});
+
+// A thread's stack with an unmapped guard page underneath, so running off
+// the end faults instead of scribbling over whatever comes next
+pub(super) struct Stack {
+    guard: u64,
+    frames: u64,
+}
+
+impl Stack {
+    pub(super) fn new() -> Option<Stack> {
+        let frames = frame::alloc_frames(STACK_PAGES, PAGE_SIZE)?;
+        let Some(guard) = alloc_slot() else {
+            frame::free_frames(frames, STACK_PAGES);
+            return None;
+        };
+
+        let stack = Stack { guard, frames };
+        let mapped = paging::kernel_map_at(
+            stack.bottom(),
+            frames,
+            (STACK_PAGES * PAGE_SIZE) as u64,
+            paging::Flags::KERNEL_DATA,
+        );
+        // Dropping it unmaps whatever did get mapped
+        mapped.is_ok().then_some(stack)
+    }
+
+    pub(super) fn bottom(&self) -> u64 {
+        self.guard + PAGE_SIZE as u64
+    }
+
+    pub(super) fn top(&self) -> u64 {
+        self.bottom() + (STACK_PAGES * PAGE_SIZE) as u64
+    }
+
+    pub(super) fn guard_contains(&self, address: u64) -> bool {
+        (self.guard..self.bottom()).contains(&address)
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        paging::kernel_unmap(self.bottom(), (STACK_PAGES * PAGE_SIZE) as u64).expect("Out of kernel page tables");
+        frame::free_frames(self.frames, STACK_PAGES);
+        SLOTS.lock().free.push(self.guard);
+    }
+}
+
+fn alloc_slot() -> Option<u64> {
+    let mut slots = SLOTS.lock();
+    if let Some(slot) = slots.free.pop() {
+        return Some(slot);
+    }
+    if slots.next + SLOT_SIZE > paging::KERNEL_STACKS + paging::KERNEL_STACKS_SIZE {
+        return None;
+    }
+    slots.next += SLOT_SIZE;
+    Some(slots.next - SLOT_SIZE)
+}
+
+pub(super) struct Thread {
+    pub(super) id: ThreadId,
+    pub(super) name: &'static str,
+    pub(super) state: State,
+    pub(super) context: Context,
+    /// None for the boot flow, which keeps the stack it came with
+    pub(super) stack: Option<Stack>,
+    /// Translation tables it runs under, the kernel's unless it belongs to
+    /// a process
+    pub(super) ttbr0: u64,
+    /// A `wake` arrived while the thread wasn't parked, so the next `park`
+    /// returns right away
+    pub(super) wake_pending: bool,
+}
@@ -0,0 +1,236 @@
+use core::ops::{Add, Sub};
+use core::time::Duration;
+
+use devtree::DevTree;
+use sync::{Once, SpinLockIrq};
+
+use crate::drivers::arch_timer;
+use crate::drivers::irqchip::{self, Interrupt};
+use crate::{machine, smp};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * NANOS_PER_SEC / arch_timer::frequency() as u128;
+    Duration::new((nanos / NANOS_PER_SEC) as u64, (nanos % NANOS_PER_SEC) as u32)
+}
+
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks = duration.as_nanos() * arch_timer::frequency() as u128 / NANOS_PER_SEC;
+    ticks.min(u64::MAX as u128) as u64
+}
+
+// A point on the monotonic clock, in counter ticks since the CPU was reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Instant(arch_timer::counter())
+    }
+
+    /// Time since `earlier`, or zero if it's actually later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration_to_ticks(duration)).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).expect("Instant overflowed")
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Time since the CPU came out of reset
+pub fn uptime() -> Duration {
+    ticks_to_duration(arch_timer::counter())
+}
+
+/// Spin until `duration` has passed, works before interrupts are set up
+pub fn busy_wait(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+// Interrupt the timer raises, the same PPI on every CPU
+static TIMER_INTERRUPT: Once<Interrupt> = Once::new();
+
+// Called on every tick of whichever CPU started one
+static TICK_HANDLER: SpinLockIrq<Option<fn()>> = SpinLockIrq::new(None);
+
+// Timer interrupt state of one CPU, kept in its `PerCpu`
+//
+// Each CPU has its own timer, serving both its tick and `sleep`, always
+// armed for whichever of the two comes first. Sleeping blocks the whole
+// CPU, so there's never more than one deadline.
+#[derive(Debug)]
+pub struct TimerState {
+    initialized: bool,
+    tick_period: Option<u64>,
+    next_tick: Option<u64>,
+    sleep_deadline: Option<u64>,
+}
+
+impl TimerState {
+    pub const fn new() -> Self {
+        TimerState {
+            initialized: false,
+            tick_period: None,
+            next_tick: None,
+            sleep_deadline: None,
+        }
+    }
+
+    // Only ever called on the CPU owning the state, as that's the timer
+    // it programs
+    fn rearm(&self) {
+        let deadline = match (self.next_tick, self.sleep_deadline) {
+            (Some(tick), Some(sleep)) => Some(tick.min(sleep)),
+            (tick, sleep) => tick.or(sleep),
+        };
+        match deadline {
+            Some(deadline) => arch_timer::set_deadline(deadline),
+            None => arch_timer::stop(),
+        }
+    }
+}
+
+fn with_state<R>(f: impl FnOnce(&mut TimerState) -> R) -> R {
+    f(&mut smp::current().timer.lock())
+}
+
+/// Hook the timer interrupt up on the boot CPU, returns whether the device
+/// tree has one
+pub fn init(devtree: &DevTree) -> bool {
+    let Some(interrupt) = arch_timer::interrupt(devtree) else {
+        return false;
+    };
+
+    TIMER_INTERRUPT.call_once(|| interrupt);
+    init_cpu();
+    true
+}
+
+/// Hook the calling secondary CPU's timer up, if `init` found one
+pub fn init_cpu() {
+    let Some(&interrupt) = TIMER_INTERRUPT.get() else {
+        return;
+    };
+
+    arch_timer::stop();
+    with_state(|state| state.initialized = true);
+    // The PPI's enable and trigger are banked per CPU
+    irqchip::register_interrupt(interrupt, handle_timer_irq);
+}
+
+fn handle_timer_irq(_irq: u32) {
+    let tick_due = with_state(|state| {
+        let now = arch_timer::counter();
+
+        if state.sleep_deadline.is_some_and(|deadline| deadline <= now) {
+            state.sleep_deadline = None;
+        }
+
+        let mut tick_due = false;
+        if let Some(next_tick) = state.next_tick
+            && next_tick <= now
+        {
+            tick_due = true;
+            // Skip ticks we were too late for instead of firing them in a burst
+            state.next_tick = state.tick_period.map(|period| {
+                let missed = (now - next_tick) / period;
+                next_tick + (missed + 1) * period
+            });
+        }
+
+        state.rearm();
+        tick_due
+    });
+
+    // Unlocked first, the handler may well reprogram the tick
+    let handler = *TICK_HANDLER.lock();
+    if tick_due && let Some(handler) = handler {
+        handler();
+    }
+}
+
+/// Put the calling CPU to sleep until `duration` has passed, falling back
+/// to spinning if its timer interrupt isn't set up
+pub fn sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    if !smp::try_current().is_some_and(|cpu| cpu.timer.lock().initialized) {
+        busy_wait(duration);
+        return;
+    }
+
+    with_state(|state| {
+        state.sleep_deadline = Some(deadline.0);
+        state.rearm();
+    });
+
+    loop {
+        // WFI wakes up on a pending interrupt even while it's masked, so
+        // checking with IRQs off can't miss the one we're waiting for.
+        // The caller's mask comes back after, a caller with IRQs masked
+        // just doesn't take the timer interrupt until it unmasks them.
+        let daif = machine::save_and_disable_irqs();
+        if Instant::now() >= deadline {
+            machine::restore_irqs(daif);
+            return;
+        }
+        machine::wait_for_interrupt();
+        machine::restore_irqs(daif);
+    }
+}
+
+/// Set the function called on every tick, from interrupt context
+pub fn set_tick_handler(handler: fn()) {
+    *TICK_HANDLER.lock() = Some(handler);
+}
+
+/// Tick every `period` from now on, on the calling CPU
+pub fn start_periodic_tick(period: Duration) {
+    let period = duration_to_ticks(period).max(1);
+    with_state(|state| {
+        state.tick_period = Some(period);
+        state.next_tick = Some(arch_timer::counter() + period);
+        state.rearm();
+    });
+}
+
+/// Tick once at `at` on the calling CPU, replacing any periodic tick
+pub fn schedule_tick(at: Instant) {
+    with_state(|state| {
+        state.tick_period = None;
+        state.next_tick = Some(at.0);
+        state.rearm();
+    });
+}
+
+pub fn stop_tick() {
+    with_state(|state| {
+        state.tick_period = None;
+        state.next_tick = None;
+        state.rearm();
+    });
+}
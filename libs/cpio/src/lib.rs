@@ -0,0 +1,322 @@
+#![cfg_attr(not(test), no_std)]
+
+//! Reading of `newc` cpio archives, the format Linux initramfs images use
+//!
+//! Entries borrow their names and data from the archive, nothing is copied.
+//! Like the kernel's own unpacker, several archives concatenated with zero
+//! padding between them read as one.
+
+use core::fmt;
+
+const MAGIC: &[u8; 6] = b"070701";
+// Same layout with a checksum of the data in the last field
+const MAGIC_CRC: &[u8; 6] = b"070702";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+// File type bits of `mode`, as in `st_mode`
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpioError {
+    /// Ends in the middle of an entry
+    Truncated,
+    BadMagic,
+    /// A header field that isn't 8 hex digits
+    BadHeader,
+    /// A name without its NUL or that isn't UTF-8
+    BadName,
+    BadChecksum,
+}
+
+impl fmt::Display for CpioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CpioError::Truncated => "archive is truncated",
+            CpioError::BadMagic => "not a newc cpio archive",
+            CpioError::BadHeader => "malformed entry header",
+            CpioError::BadName => "malformed entry name",
+            CpioError::BadChecksum => "entry data doesn't match its checksum",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    Unknown,
+}
+
+// One file in the archive
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    /// Path relative to where the archive gets unpacked, usually without a
+    /// leading "./"
+    pub name: &'a str,
+    pub ino: u32,
+    /// Type and permission bits, as in `st_mode`
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub mtime: u32,
+    /// Device the file was on, as (major, minor)
+    pub dev: (u32, u32),
+    /// For device nodes, the device they stand for
+    pub rdev: (u32, u32),
+    /// File contents, or the target of a symlink
+    pub data: &'a [u8],
+}
+
+impl Entry<'_> {
+    pub fn kind(&self) -> EntryKind {
+        match self.mode & S_IFMT {
+            S_IFREG => EntryKind::File,
+            S_IFDIR => EntryKind::Directory,
+            S_IFLNK => EntryKind::Symlink,
+            S_IFCHR => EntryKind::CharDevice,
+            S_IFBLK => EntryKind::BlockDevice,
+            S_IFIFO => EntryKind::Fifo,
+            S_IFSOCK => EntryKind::Socket,
+            _ => EntryKind::Unknown,
+        }
+    }
+
+    /// Permission bits, including setuid, setgid and sticky
+    pub fn permissions(&self) -> u16 {
+        (self.mode & 0o7777) as u16
+    }
+}
+
+// A cpio archive in memory
+#[derive(Debug, Clone, Copy)]
+pub struct Archive<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(data: &'a [u8]) -> Archive<'a> {
+        Archive { data }
+    }
+
+    /// Every entry up to the last trailer. Iteration stops after the first
+    /// error.
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            data: self.data,
+            offset: 0,
+            done: false,
+        }
+    }
+}
+
+pub struct Entries<'a> {
+    data: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+fn align4(value: usize) -> usize {
+    value.next_multiple_of(4)
+}
+
+fn hex_field(header: &[u8], index: usize) -> Result<u32, CpioError> {
+    let digits = &header[6 + index * 8..6 + (index + 1) * 8];
+    let digits = core::str::from_utf8(digits).map_err(|_| CpioError::BadHeader)?;
+    u32::from_str_radix(digits, 16).map_err(|_| CpioError::BadHeader)
+}
+
+impl<'a> Entries<'a> {
+    // Past a trailer, zeroes until the next archive or the end
+    fn skip_padding(&mut self) {
+        while self.offset < self.data.len() && self.data[self.offset] == 0 {
+            self.offset += 1;
+        }
+        // Headers only start on 4 byte boundaries
+        if self.offset < self.data.len() {
+            self.offset &= !3;
+        }
+    }
+
+    fn parse(&mut self) -> Result<Option<Entry<'a>>, CpioError> {
+        loop {
+            let rest = &self.data[self.offset..];
+            if rest.is_empty() {
+                return Ok(None);
+            }
+            if rest.len() < HEADER_SIZE {
+                return Err(if rest.starts_with(&MAGIC[..rest.len().min(6)]) {
+                    CpioError::Truncated
+                } else {
+                    CpioError::BadMagic
+                });
+            }
+            let header = &rest[..HEADER_SIZE];
+            let has_checksum = match &header[..6] {
+                magic if magic == MAGIC => false,
+                magic if magic == MAGIC_CRC => true,
+                _ => return Err(CpioError::BadMagic),
+            };
+
+            let field = |index| hex_field(header, index);
+            let name_size = field(11)? as usize;
+            let file_size = field(6)? as usize;
+            if name_size == 0 {
+                return Err(CpioError::BadName);
+            }
+
+            let name_end = HEADER_SIZE + name_size;
+            let data_start = align4(name_end);
+            let data_end = data_start.checked_add(file_size).ok_or(CpioError::Truncated)?;
+            if rest.len() < name_end || rest.len() < data_end {
+                return Err(CpioError::Truncated);
+            }
+
+            let name = &rest[HEADER_SIZE..name_end];
+            let Some((&0, name)) = name.split_last() else {
+                return Err(CpioError::BadName);
+            };
+            let name = core::str::from_utf8(name).map_err(|_| CpioError::BadName)?;
+            let data = &rest[data_start..data_end];
+
+            self.offset += align4(data_end).min(rest.len());
+            if name == TRAILER {
+                self.skip_padding();
+                continue;
+            }
+
+            if has_checksum {
+                let sum = data.iter().fold(0u32, |sum, &byte| sum.wrapping_add(byte as u32));
+                if sum != field(12)? {
+                    return Err(CpioError::BadChecksum);
+                }
+            }
+
+            return Ok(Some(Entry {
+                name,
+                ino: field(0)?,
+                mode: field(1)?,
+                uid: field(2)?,
+                gid: field(3)?,
+                nlink: field(4)?,
+                mtime: field(5)?,
+                dev: (field(7)?, field(8)?),
+                rdev: (field(9)?, field(10)?),
+                data,
+            }));
+        }
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, CpioError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = self.parse().transpose();
+        if !matches!(entry, Some(Ok(_))) {
+            self.done = true;
+        }
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Append one newc entry to `archive`
+    fn push_entry(archive: &mut Vec<u8>, magic: &[u8; 6], name: &str, mode: u32, data: &[u8], check: u32) {
+        archive.extend_from_slice(magic);
+        let fields = [1, mode, 0, 0, 1, 0, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, check];
+        for field in fields {
+            archive.extend_from_slice(format!("{:08X}", field).as_bytes());
+        }
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(align4(archive.len()), 0);
+        archive.extend_from_slice(data);
+        archive.resize(align4(archive.len()), 0);
+    }
+
+    fn archive(entries: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for &(name, mode, data) in entries {
+            push_entry(&mut archive, MAGIC, name, mode, data, 0);
+        }
+        push_entry(&mut archive, MAGIC, TRAILER, 0, &[], 0);
+        archive
+    }
+
+    #[test]
+    fn reads_entries() {
+        let data = archive(&[
+            (".", 0o040755, b""),
+            ("bin", 0o040755, b""),
+            ("bin/hello", 0o100755, b"hello world"),
+            ("bin/sh", 0o120777, b"hello"),
+        ]);
+        let entries: Vec<_> = Archive::new(&data).entries().collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[1].name, "bin");
+        assert_eq!(entries[1].kind(), EntryKind::Directory);
+        assert_eq!(entries[2].kind(), EntryKind::File);
+        assert_eq!(entries[2].permissions(), 0o755);
+        assert_eq!(entries[2].data, b"hello world");
+        assert_eq!(entries[3].kind(), EntryKind::Symlink);
+        assert_eq!(entries[3].data, b"hello");
+    }
+
+    #[test]
+    fn reads_concatenated_archives() {
+        let mut data = archive(&[("a", 0o100644, b"1")]);
+        data.resize(data.len() + 512, 0);
+        data.extend(archive(&[("b", 0o100644, b"22")]));
+        data.resize(data.len() + 101, 0);
+
+        let names: Vec<_> = Archive::new(&data).entries().map(|entry| entry.unwrap().name).collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn checks_checksums() {
+        let mut data = Vec::new();
+        push_entry(&mut data, MAGIC_CRC, "good", 0o100644, b"ab", 0x61 + 0x62);
+        push_entry(&mut data, MAGIC_CRC, "bad", 0o100644, b"ab", 1);
+
+        let mut entries = Archive::new(&data).entries();
+        assert_eq!(entries.next().unwrap().unwrap().name, "good");
+        assert_eq!(entries.next().unwrap().unwrap_err(), CpioError::BadChecksum);
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_archives() {
+        let data = archive(&[("file", 0o100644, b"contents")]);
+        let first_error = |data: &[u8]| Archive::new(data).entries().find_map(Result::err);
+
+        assert_eq!(first_error(&data[..HEADER_SIZE + 8]), Some(CpioError::Truncated));
+        assert_eq!(first_error(&data[..3]), Some(CpioError::Truncated));
+        assert_eq!(first_error(b"070707 old binary format, not newc............"), Some(CpioError::BadMagic));
+
+        let mut bad_hex = data.clone();
+        bad_hex[6] = b'x';
+        assert_eq!(first_error(&bad_hex), Some(CpioError::BadHeader));
+    }
+}
@@ -0,0 +1,300 @@
+use core::fmt;
+
+use crate::program::{PROGRAM_HEADER_SIZE, ProgramHeader, SegmentFlags, SegmentKind};
+use crate::{read_u16, read_u32, read_u64};
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const HEADER_SIZE: usize = 64;
+
+// e_ident fields
+const CLASS_64: u8 = 2;
+const DATA_LSB: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const OSABI_SYSV: u8 = 0;
+const OSABI_LINUX: u8 = 3;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_AARCH64: u16 = 183;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfType {
+    /// Linked to run at fixed addresses
+    Executable,
+    /// Position independent, loaded wherever there's room
+    SharedObject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// Shorter than its headers say it is
+    Truncated,
+    BadMagic,
+    /// 32-bit, big-endian, or a version or OS ABI other than the usual
+    UnsupportedFormat,
+    UnsupportedMachine(u16),
+    /// Neither an executable nor a position independent one
+    UnsupportedType(u16),
+    /// Program header entries of a size other than 56 bytes
+    BadProgramHeaders,
+    /// A loadable segment with impossible sizes, alignment or addresses
+    BadSegment(usize),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::Truncated => f.write_str("file is truncated"),
+            ElfError::BadMagic => f.write_str("not an ELF file"),
+            ElfError::UnsupportedFormat => f.write_str("not a little-endian ELF64 file"),
+            ElfError::UnsupportedMachine(machine) => write!(f, "built for machine {}, not AArch64", machine),
+            ElfError::UnsupportedType(kind) => write!(f, "ELF type {} isn't executable", kind),
+            ElfError::BadProgramHeaders => f.write_str("malformed program headers"),
+            ElfError::BadSegment(index) => write!(f, "malformed segment {}", index),
+        }
+    }
+}
+
+// A validated ELF64 file in memory
+#[derive(Debug, Clone, Copy)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    kind: ElfType,
+    entry: u64,
+    phoff: usize,
+    phnum: usize,
+}
+
+impl<'a> Elf<'a> {
+    /// Check `data` is an AArch64 executable whose program headers and
+    /// loadable segments all make sense
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(if data.starts_with(&MAGIC[..data.len().min(4)]) {
+                ElfError::Truncated
+            } else {
+                ElfError::BadMagic
+            });
+        }
+        if &data[..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != CLASS_64
+            || data[5] != DATA_LSB
+            || data[6] != VERSION_CURRENT
+            || ![OSABI_SYSV, OSABI_LINUX].contains(&data[7])
+            || read_u32(data, 20) != VERSION_CURRENT as u32
+        {
+            return Err(ElfError::UnsupportedFormat);
+        }
+
+        let kind = match read_u16(data, 16) {
+            ET_EXEC => ElfType::Executable,
+            ET_DYN => ElfType::SharedObject,
+            other => return Err(ElfError::UnsupportedType(other)),
+        };
+        let machine = read_u16(data, 18);
+        if machine != EM_AARCH64 {
+            return Err(ElfError::UnsupportedMachine(machine));
+        }
+
+        let phoff = read_u64(data, 32);
+        let phentsize = read_u16(data, 54) as usize;
+        let phnum = read_u16(data, 56) as usize;
+        if phnum == 0 || phentsize != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::BadProgramHeaders);
+        }
+        let end = phoff.checked_add((phnum * PROGRAM_HEADER_SIZE) as u64);
+        if end.is_none_or(|end| end > data.len() as u64) {
+            return Err(ElfError::Truncated);
+        }
+
+        let elf = Elf {
+            data,
+            kind,
+            entry: read_u64(data, 24),
+            phoff: phoff as usize,
+            phnum,
+        };
+        for (index, ph) in elf.program_headers().enumerate() {
+            if ph.kind == SegmentKind::Load || ph.kind == SegmentKind::Interp {
+                elf.check_segment(index, &ph)?;
+            }
+        }
+        if elf.segments().next().is_none() {
+            return Err(ElfError::BadProgramHeaders);
+        }
+        Ok(elf)
+    }
+
+    fn check_segment(&self, index: usize, ph: &ProgramHeader) -> Result<(), ElfError> {
+        let bad = Err(ElfError::BadSegment(index));
+        if ph.file_size > ph.mem_size || ph.vaddr.checked_add(ph.mem_size).is_none() {
+            return bad;
+        }
+        if ph.offset.checked_add(ph.file_size).is_none_or(|end| end > self.data.len() as u64) {
+            return Err(ElfError::Truncated);
+        }
+        // Mapping works in whole pages, so the file and memory offsets have
+        // to agree within one
+        if ph.align > 1 && (!ph.align.is_power_of_two() || ph.offset % ph.align != ph.vaddr % ph.align) {
+            return bad;
+        }
+        Ok(())
+    }
+
+    pub fn kind(&self) -> ElfType {
+        self.kind
+    }
+
+    /// Address to start at, before relocation for position independent ones
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Offset of the program headers in the file
+    pub fn program_header_offset(&self) -> u64 {
+        self.phoff as u64
+    }
+
+    pub fn program_header_count(&self) -> usize {
+        self.phnum
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let phoff = self.phoff;
+        (0..self.phnum).map(move |i| ProgramHeader::read(data, phoff + i * PROGRAM_HEADER_SIZE))
+    }
+
+    /// The `PT_LOAD` segments, in file order
+    pub fn segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.program_headers().filter(|ph| ph.kind == SegmentKind::Load)
+    }
+
+    /// The `PT_LOAD` segments that take up memory, the ones a loader maps
+    /// and copies. Empty ones are allowed but have nowhere to go.
+    pub fn loaded_segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.segments().filter(|ph| ph.mem_size > 0)
+    }
+
+    /// The part of the file backing `ph`, `file_size` bytes long
+    pub fn segment_data(&self, ph: &ProgramHeader) -> &'a [u8] {
+        &self.data[ph.offset as usize..(ph.offset + ph.file_size) as usize]
+    }
+
+    /// Path of the dynamic linker the program asks for, if any
+    pub fn interpreter(&self) -> Option<&'a [u8]> {
+        let ph = self.program_headers().find(|ph| ph.kind == SegmentKind::Interp)?;
+        let path = self.segment_data(&ph);
+        Some(path.strip_suffix(b"\0").unwrap_or(path))
+    }
+
+    /// Permissions and size `PT_GNU_STACK` asks for, read-write and no
+    /// size in particular (0) without one
+    pub fn stack(&self) -> (SegmentFlags, u64) {
+        self.program_headers()
+            .find(|ph| ph.kind == SegmentKind::GnuStack)
+            .map(|ph| (ph.flags, ph.mem_size))
+            .unwrap_or((SegmentFlags::READ | SegmentFlags::WRITE, 0))
+    }
+
+    /// Where the program headers end up in memory before relocation, for
+    /// `AT_PHDR`: `PT_PHDR` if there is one, otherwise wherever the
+    /// loadable segment holding them puts them
+    pub fn program_header_address(&self) -> Option<u64> {
+        if let Some(ph) = self.program_headers().find(|ph| ph.kind == SegmentKind::Phdr) {
+            return Some(ph.vaddr);
+        }
+        let phoff = self.phoff as u64;
+        self.segments()
+            .find(|ph| (ph.offset..ph.offset + ph.file_size).contains(&phoff))
+            .map(|ph| ph.vaddr + (phoff - ph.offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::ElfBuilder;
+
+    const PT_LOAD: u32 = 1;
+    const PT_GNU_STACK: u32 = 0x6474_e551;
+
+    #[test]
+    fn parses_static_executable() {
+        let mut builder = ElfBuilder::new();
+        builder.segment(PT_LOAD, 0b101, 0x40_0000, &[0xaa; 0x80], 0x80);
+        builder.segment(PT_LOAD, 0b110, 0x41_0010, &[0xbb; 0x20], 0x1000);
+        builder.segment(PT_GNU_STACK, 0b110, 0, &[], 0);
+        let file = builder.build();
+        let elf = Elf::parse(&file).unwrap();
+
+        assert_eq!(elf.kind(), ElfType::Executable);
+        assert_eq!(elf.entry(), 0x40_0000);
+        assert_eq!(elf.program_headers().count(), 3);
+
+        let segments: Vec<_> = elf.segments().collect();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].flags, SegmentFlags::READ | SegmentFlags::EXECUTE);
+        assert_eq!(elf.segment_data(&segments[1]), [0xbb; 0x20]);
+        assert_eq!(segments[1].mem_size, 0x1000);
+
+        let (stack, _) = elf.stack();
+        assert!(stack.contains(SegmentFlags::WRITE) && !stack.contains(SegmentFlags::EXECUTE));
+        assert!(elf.interpreter().is_none());
+    }
+
+    #[test]
+    fn skips_empty_segments_when_loading() {
+        let mut builder = ElfBuilder::new();
+        builder.segment(PT_LOAD, 0b101, 0x40_0000, &[0xaa; 0x80], 0x80);
+        builder.segment(PT_LOAD, 0b110, 0x50_0000, &[], 0);
+        builder.segment(PT_LOAD, 0b110, 0x41_0000, &[], 0x1000);
+        let file = builder.build();
+        let elf = Elf::parse(&file).unwrap();
+
+        assert_eq!(elf.segments().count(), 3);
+        let loaded: Vec<_> = elf.loaded_segments().map(|ph| ph.vaddr).collect();
+        assert_eq!(loaded, [0x40_0000, 0x41_0000]);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut builder = ElfBuilder::new();
+        builder.segment(PT_LOAD, 0b101, 0x40_0000, &[0; 16], 16);
+        let file = builder.build();
+
+        assert_eq!(Elf::parse(&file[..40]).unwrap_err(), ElfError::Truncated);
+        assert_eq!(Elf::parse(b"#!/bin/sh\n").unwrap_err(), ElfError::BadMagic);
+
+        let mut big_endian = file.clone();
+        big_endian[5] = 2;
+        assert_eq!(Elf::parse(&big_endian).unwrap_err(), ElfError::UnsupportedFormat);
+
+        builder.machine = 62;
+        assert_eq!(Elf::parse(&builder.build()).unwrap_err(), ElfError::UnsupportedMachine(62));
+        builder.machine = 183;
+        builder.kind = 1;
+        assert_eq!(Elf::parse(&builder.build()).unwrap_err(), ElfError::UnsupportedType(1));
+    }
+
+    #[test]
+    fn rejects_bad_segments() {
+        // More in the file than in memory
+        let mut builder = ElfBuilder::new();
+        builder.segment(PT_LOAD, 0b110, 0x40_0000, &[0; 32], 16);
+        assert_eq!(Elf::parse(&builder.build()).unwrap_err(), ElfError::BadSegment(0));
+
+        // Data past the end of the file
+        let mut builder = ElfBuilder::new();
+        builder.segment(PT_LOAD, 0b110, 0x40_0000, &[0; 32], 32);
+        let file = builder.build();
+        assert_eq!(Elf::parse(&file[..file.len() - 1]).unwrap_err(), ElfError::Truncated);
+
+        // No loadable segments at all
+        let mut builder = ElfBuilder::new();
+        builder.segment(PT_GNU_STACK, 0b110, 0, &[], 0);
+        assert_eq!(Elf::parse(&builder.build()).unwrap_err(), ElfError::BadProgramHeaders);
+    }
+}
@@ -0,0 +1,107 @@
+#![cfg_attr(not(test), no_std)]
+
+//! Parsing of AArch64 ELF64 executables, and the initial stack the SysV ABI
+//! has them start with
+//!
+//! Only what loading a program needs is here: the file header, program
+//! headers and the data behind them. Everything is checked up front in
+//! `Elf::parse`, so a parsed file can be mapped without further bounds
+//! checks.
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+mod header;
+mod program;
+#[cfg(feature = "alloc")]
+pub mod stack;
+
+pub use header::{Elf, ElfError, ElfType};
+pub use program::{PROGRAM_HEADER_SIZE, ProgramHeader, SegmentFlags, SegmentKind};
+
+// Little-endian field readers, callers have already checked the bounds
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    // Minimal ELF writer so tests can describe files inline
+    pub(crate) struct ElfBuilder {
+        pub(crate) kind: u16,
+        pub(crate) machine: u16,
+        pub(crate) entry: u64,
+        segments: Vec<(u32, u32, u64, Vec<u8>, u64)>,
+    }
+
+    impl ElfBuilder {
+        pub(crate) fn new() -> Self {
+            ElfBuilder {
+                kind: 2,
+                machine: 183,
+                entry: 0x40_0000,
+                segments: Vec::new(),
+            }
+        }
+
+        /// Add a segment of `kind` at `vaddr` holding `data`, `memsz` bytes
+        /// long in memory
+        pub(crate) fn segment(&mut self, kind: u32, flags: u32, vaddr: u64, data: &[u8], memsz: u64) {
+            self.segments.push((kind, flags, vaddr, Vec::from(data), memsz));
+        }
+
+        /// Lay out the headers followed by every segment's data, each
+        /// starting at the same offset in a page as its address
+        pub(crate) fn build(&self) -> Vec<u8> {
+            let phoff = 64;
+            let mut file = vec![0; phoff + self.segments.len() * 56];
+            file[..4].copy_from_slice(b"\x7fELF");
+            file[4] = 2;
+            file[5] = 1;
+            file[6] = 1;
+            file[16..18].copy_from_slice(&self.kind.to_le_bytes());
+            file[18..20].copy_from_slice(&self.machine.to_le_bytes());
+            file[20..24].copy_from_slice(&1u32.to_le_bytes());
+            file[24..32].copy_from_slice(&self.entry.to_le_bytes());
+            file[32..40].copy_from_slice(&(phoff as u64).to_le_bytes());
+            file[52..54].copy_from_slice(&64u16.to_le_bytes());
+            file[54..56].copy_from_slice(&56u16.to_le_bytes());
+            file[56..58].copy_from_slice(&(self.segments.len() as u16).to_le_bytes());
+
+            for (i, (kind, flags, vaddr, data, memsz)) in self.segments.iter().enumerate() {
+                // Nothing to point at without data, so just agree with the
+                // address within a page
+                let mut offset = vaddr % 0x1000;
+                if !data.is_empty() {
+                    offset = (file.len() as u64).next_multiple_of(0x1000) + vaddr % 0x1000;
+                    file.resize(offset as usize, 0);
+                    file.extend_from_slice(data);
+                }
+
+                let header = phoff + i * 56;
+                let ph = &mut file[header..header + 56];
+                ph[0..4].copy_from_slice(&kind.to_le_bytes());
+                ph[4..8].copy_from_slice(&flags.to_le_bytes());
+                ph[8..16].copy_from_slice(&offset.to_le_bytes());
+                ph[16..24].copy_from_slice(&vaddr.to_le_bytes());
+                ph[24..32].copy_from_slice(&vaddr.to_le_bytes());
+                ph[32..40].copy_from_slice(&(data.len() as u64).to_le_bytes());
+                ph[40..48].copy_from_slice(&memsz.to_le_bytes());
+                ph[48..56].copy_from_slice(&0x1000u64.to_le_bytes());
+            }
+            file
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use core::ops::BitOr;
+
+use crate::{read_u32, read_u64};
+
+/// Size of a program header table entry, `AT_PHENT`
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Null,
+    Load,
+    Dynamic,
+    Interp,
+    Note,
+    Phdr,
+    Tls,
+    /// Permissions for the stack
+    GnuStack,
+    /// Read-only after relocation
+    GnuRelro,
+    Other(u32),
+}
+
+impl SegmentKind {
+    fn from_raw(kind: u32) -> Self {
+        match kind {
+            0 => SegmentKind::Null,
+            1 => SegmentKind::Load,
+            2 => SegmentKind::Dynamic,
+            3 => SegmentKind::Interp,
+            4 => SegmentKind::Note,
+            6 => SegmentKind::Phdr,
+            7 => SegmentKind::Tls,
+            0x6474_e551 => SegmentKind::GnuStack,
+            0x6474_e552 => SegmentKind::GnuRelro,
+            other => SegmentKind::Other(other),
+        }
+    }
+}
+
+// p_flags of a segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentFlags(u32);
+
+impl SegmentFlags {
+    pub const EXECUTE: SegmentFlags = SegmentFlags(1 << 0);
+    pub const WRITE: SegmentFlags = SegmentFlags(1 << 1);
+    pub const READ: SegmentFlags = SegmentFlags(1 << 2);
+
+    pub fn contains(self, other: SegmentFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for SegmentFlags {
+    type Output = SegmentFlags;
+
+    fn bitor(self, other: SegmentFlags) -> SegmentFlags {
+        SegmentFlags(self.0 | other.0)
+    }
+}
+
+// One entry of the program header table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: SegmentKind,
+    pub flags: SegmentFlags,
+    /// Where the segment's data starts in the file
+    pub offset: u64,
+    pub vaddr: u64,
+    /// Bytes of data in the file, the rest up to `mem_size` is zeroes
+    pub file_size: u64,
+    pub mem_size: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    pub(crate) fn read(data: &[u8], offset: usize) -> Self {
+        ProgramHeader {
+            kind: SegmentKind::from_raw(read_u32(data, offset)),
+            flags: SegmentFlags(read_u32(data, offset + 4)),
+            offset: read_u64(data, offset + 8),
+            vaddr: read_u64(data, offset + 16),
+            file_size: read_u64(data, offset + 32),
+            mem_size: read_u64(data, offset + 40),
+            align: read_u64(data, offset + 48),
+        }
+    }
+}
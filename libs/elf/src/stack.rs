@@ -0,0 +1,127 @@
+//! The stack a program starts with under the SysV ABI
+//!
+//! From the stack pointer up: argc, the argv pointers, a null, the envp
+//! pointers, a null, then the auxiliary vector as (type, value) pairs ending
+//! in `AT_NULL`. The strings and other data those point to sit above all
+//! of that, under the top of the stack.
+
+use alloc::vec::Vec;
+
+// Auxiliary vector entry types
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_BASE: u64 = 7;
+pub const AT_FLAGS: u64 = 8;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_UID: u64 = 11;
+pub const AT_EUID: u64 = 12;
+pub const AT_GID: u64 = 13;
+pub const AT_EGID: u64 = 14;
+pub const AT_HWCAP: u64 = 16;
+pub const AT_CLKTCK: u64 = 17;
+pub const AT_SECURE: u64 = 23;
+pub const AT_RANDOM: u64 = 25;
+pub const AT_EXECFN: u64 = 31;
+
+// What goes on the initial stack
+//
+// `auxv` holds everything but `AT_RANDOM` and `AT_EXECFN`, which point into
+// the stack and get added by `build`, and the final `AT_NULL`. Strings must
+// not contain NULs.
+pub struct InitialStack<'a> {
+    pub argv: &'a [&'a str],
+    pub envp: &'a [&'a str],
+    pub auxv: &'a [(u64, u64)],
+    /// Path the program was started from
+    pub execfn: &'a str,
+    /// Seed for the C library, e.g. for stack protector canaries
+    pub random: [u8; 16],
+}
+
+impl InitialStack<'_> {
+    /// Lay the stack out to end at `top`, returns the stack pointer to start
+    /// with and the bytes to copy there, which run up to `top`
+    pub fn build(&self, top: u64) -> (u64, Vec<u8>) {
+        let strings = self.argv.iter().chain(self.envp).chain([&self.execfn]);
+        let strings_size: u64 = strings.clone().map(|s| s.len() as u64 + 1).sum();
+        let data_start = (top - self.random.len() as u64 - strings_size) & !15;
+
+        let mut data = Vec::from(self.random);
+        let mut pointers = Vec::new();
+        for string in strings {
+            pointers.push(data_start + data.len() as u64);
+            data.extend_from_slice(string.as_bytes());
+            data.push(0);
+        }
+        let (argv, rest) = pointers.split_at(self.argv.len());
+        let (envp, execfn) = rest.split_at(self.envp.len());
+
+        let mut words = Vec::new();
+        words.push(self.argv.len() as u64);
+        words.extend_from_slice(argv);
+        words.push(0);
+        words.extend_from_slice(envp);
+        words.push(0);
+        for &(kind, value) in self.auxv {
+            words.extend_from_slice(&[kind, value]);
+        }
+        words.extend_from_slice(&[AT_RANDOM, data_start, AT_EXECFN, execfn[0], AT_NULL, 0]);
+
+        // The ABI wants the stack pointer 16-byte aligned
+        let sp = (data_start - words.len() as u64 * 8) & !15;
+        let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        bytes.resize((data_start - sp) as usize, 0);
+        bytes.extend_from_slice(&data);
+        bytes.resize((top - sp) as usize, 0);
+        (sp, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_out_sysv_stack() {
+        let top = 0x7fff_0000;
+        let stack = InitialStack {
+            argv: &["/init", "-v"],
+            envp: &["HOME=/"],
+            auxv: &[(AT_PAGESZ, 4096), (AT_ENTRY, 0x40_0000)],
+            execfn: "/init",
+            random: [7; 16],
+        };
+        let (sp, bytes) = stack.build(top);
+        assert_eq!(sp % 16, 0);
+        assert_eq!(sp + bytes.len() as u64, top);
+
+        let word = |address: u64| {
+            let offset = (address - sp) as usize;
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+        };
+        let string = |address: u64| {
+            let offset = (address - sp) as usize;
+            let len = bytes[offset..].iter().position(|&b| b == 0).unwrap();
+            core::str::from_utf8(&bytes[offset..offset + len]).unwrap()
+        };
+
+        assert_eq!(word(sp), 2);
+        assert_eq!(string(word(sp + 8)), "/init");
+        assert_eq!(string(word(sp + 16)), "-v");
+        assert_eq!(word(sp + 24), 0);
+        assert_eq!(string(word(sp + 32)), "HOME=/");
+        assert_eq!(word(sp + 40), 0);
+
+        let auxv: Vec<_> = (0..5).map(|i| (word(sp + 48 + i * 16), word(sp + 56 + i * 16))).collect();
+        assert_eq!(auxv[..2], [(AT_PAGESZ, 4096), (AT_ENTRY, 0x40_0000)]);
+        assert_eq!(auxv[2].0, AT_RANDOM);
+        let random = (auxv[2].1 - sp) as usize;
+        assert_eq!(bytes[random..random + 16], [7; 16]);
+        assert_eq!(auxv[3].0, AT_EXECFN);
+        assert_eq!(string(auxv[3].1), "/init");
+        assert_eq!(auxv[4], (AT_NULL, 0));
+    }
+}
@@ -68,6 +68,13 @@ impl DevTreeHeader {
     }
 }
 
+// Memory reservation block entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemReservation {
+    pub address: u64,
+    pub size: u64,
+}
+
 // Zero-copy Device Tree representation
 #[derive(Debug)]
 pub struct DevTree {
@@ -79,7 +86,13 @@ pub struct DevTree {
 }
 
 impl DevTree {
-    pub fn new(fdt_ptr: *const u8) -> Option<Self> {
+    /// Parse the blob at `fdt_ptr`
+    ///
+    /// # Safety
+    ///
+    /// `fdt_ptr` must point to a DTB that stays mapped and unmodified for the
+    /// rest of the program, aligned to 8 bytes.
+    pub unsafe fn new(fdt_ptr: *const u8) -> Option<Self> {
         unsafe {
             // Cast pointer to FDT header
             let header = &*(fdt_ptr as *const DevTreeHeader);
@@ -114,9 +127,10 @@ impl DevTree {
             let off_mem_rsvmap = header.off_mem_rsvmap() as usize;
             let size_mem_rsvmap = Self::count_mem_rsv_entries(fdt_ptr.add(off_mem_rsvmap) as *const u64);
 
+            // Each entry is an (address, size) pair
             let mem_rsvmap: &'static [u64] = core::slice::from_raw_parts(
                 fdt_ptr.add(off_mem_rsvmap) as *const u64,
-                size_mem_rsvmap,
+                size_mem_rsvmap * 2,
             );
             
             // Create root node
@@ -132,10 +146,48 @@ impl DevTree {
         }
     }
     
+    /// Parse a blob held in memory, checking every header offset against
+    /// the slice first. Useful when the DTB comes from an untrusted file.
+    pub fn from_bytes(blob: &'static [u8]) -> Option<Self> {
+        if blob.len() < size_of::<DevTreeHeader>() || blob.as_ptr().align_offset(8) != 0 {
+            return None;
+        }
+
+        let header = unsafe { &*(blob.as_ptr() as *const DevTreeHeader) };
+        if header.magic() != 0xd00dfeed || header.totalsize() as usize > blob.len() {
+            return None;
+        }
+
+        let totalsize = header.totalsize() as usize;
+        let in_bounds = |offset: u32, size: u32| {
+            (offset as usize).checked_add(size as usize).is_some_and(|end| end <= totalsize)
+        };
+        if !in_bounds(header.off_dt_struct(), header.size_dt_struct())
+            || !in_bounds(header.off_dt_strings(), header.size_dt_strings())
+        {
+            return None;
+        }
+
+        // The reservation map must be aligned and terminated inside the blob
+        let off_mem_rsvmap = header.off_mem_rsvmap() as usize;
+        if !off_mem_rsvmap.is_multiple_of(8) {
+            return None;
+        }
+        let terminated = blob
+            .get(off_mem_rsvmap..totalsize)?
+            .chunks_exact(16)
+            .any(|entry| entry.iter().all(|&byte| byte == 0));
+        if !terminated {
+            return None;
+        }
+
+        unsafe { Self::new(blob.as_ptr()) }
+    }
+
     fn count_mem_rsv_entries(rsv_ptr: *const u64) -> usize {
         unsafe {
             let mut count = 0;
-            let mut ptr = rsv_ptr as *const u64;
+            let mut ptr = rsv_ptr;
             
             loop {
                 let addr = *ptr;
@@ -158,13 +210,51 @@ impl DevTree {
         &self.root_node
     }
     
+    /// Find a node by its full path, e.g. `/cpus/cpu@0`
+    ///
+    /// Walks the tree from the root; build a `DevTreeIndex` when doing many
+    /// lookups.
+    pub fn find_node(&self, path: &str) -> Option<DevTreeNode<'static>> {
+        if !path.starts_with('/') {
+            return None;
+        }
+
+        let mut node = self.root_node;
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            node = node.children().find(|child| child.matches_path_component(component))?;
+        }
+        Some(node)
+    }
+
+    /// Find the node that declares the given phandle
+    pub fn node_by_phandle(&self, phandle: u32) -> Option<DevTreeNode<'static>> {
+        self.root_node
+            .iter_descendants()
+            .find(|node| node.phandle() == Some(phandle))
+    }
+
+    /// Find the first enabled node compatible with `compat`
+    pub fn find_compatible(&self, compat: &str) -> Option<DevTreeNode<'static>> {
+        self.root_node
+            .iter_descendants()
+            .find(|node| node.is_compatible(compat) && node.is_enabled())
+    }
+
     /// Get the header information
     pub fn header(&self) -> &DevTreeHeader {
         self.header
     }
     
-    /// Get memory reservation map
+    /// Get memory reservation map as raw big-endian (address, size) pairs
     pub fn mem_rsvmap(&self) -> &[u64] {
         self.mem_rsvmap
     }
+
+    /// Iterate over the decoded memory reservation entries
+    pub fn mem_reservations(&self) -> impl Iterator<Item = MemReservation> + '_ {
+        self.mem_rsvmap.chunks_exact(2).map(|entry| MemReservation {
+            address: u64::from_be(entry[0]),
+            size: u64::from_be(entry[1]),
+        })
+    }
 }
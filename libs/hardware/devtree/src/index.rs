@@ -0,0 +1,409 @@
+use crate::devtree::DevTree;
+use crate::node::{
+    DevTreeNode, PropertyIterator, FDT_BEGIN_NODE, FDT_END, FDT_END_NODE, FDT_NOP, FDT_PROP,
+};
+use crate::property::Property;
+
+// Words each node takes in the scratch buffer: its entry, plus a slot in
+// the phandle table after all the entries
+pub const INDEX_ENTRY_WORDS: usize = NODE_WORDS + 1;
+
+// Layout of an entry
+const NODE_WORDS: usize = 6;
+const OFFSET: usize = 0; // Offset of FDT_BEGIN_NODE in the structure block
+const END: usize = 1; // Offset right after the matching FDT_END_NODE
+const PARENT: usize = 2;
+const FIRST_CHILD: usize = 3;
+const NEXT_SIBLING: usize = 4;
+const PHANDLE: usize = 5; // 0 when the node has none
+
+const NONE: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexError {
+    /// The scratch buffer can't hold every node, `needed` is in words
+    BufferTooSmall { needed: usize },
+    /// The structure block is truncated or has unexpected tokens
+    Malformed,
+}
+
+// Offset index over a DevTree, built without allocating
+//
+// Every node gets NODE_WORDS words in a caller-provided buffer with its
+// offsets, parent, first child, next sibling and phandle, so walking the
+// tree no longer means skipping over whole subtrees token by token. After
+// the entries comes the ID of every node with a phandle, sorted by it, for
+// binary searching.
+#[derive(Debug, Clone, Copy)]
+pub struct DevTreeIndex<'i, 'a> {
+    entries: &'i [u32],
+    by_phandle: &'i [u32],
+    dt_struct: &'a [u8],
+    dt_strings: &'a [u8],
+}
+
+impl<'i, 'a> DevTreeIndex<'i, 'a> {
+    /// Words of scratch space needed to index `devtree`
+    pub fn required_words(devtree: &DevTree) -> usize {
+        devtree.root().iter_descendants().count() * INDEX_ENTRY_WORDS
+    }
+
+    /// Index `devtree` in a single pass over its structure block
+    pub fn build(devtree: &'a DevTree, scratch: &'i mut [u32]) -> Result<Self, IndexError> {
+        let root = devtree.root();
+        let dt_struct = root.dt_struct();
+        let dt_strings = root.dt_strings();
+        let capacity = scratch.len() / INDEX_ENTRY_WORDS;
+        // Entries go first, the phandle table once there's a node count
+        let (entries, _) = scratch.split_at_mut(capacity * NODE_WORDS);
+
+        let mut count = 0;
+        let mut current = NONE;
+        let mut last_closed = NONE;
+        let mut offset = root.struct_offset();
+
+        loop {
+            let token = DevTreeNode::read_struct_token(dt_struct, offset).ok_or(IndexError::Malformed)?;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    if count == capacity {
+                        return Err(IndexError::BufferTooSmall {
+                            needed: Self::required_words(devtree),
+                        });
+                    }
+
+                    let node = DevTreeNode::new_at_offset(dt_struct, dt_strings, offset)
+                        .ok_or(IndexError::Malformed)?;
+                    let id = count as u32;
+                    count += 1;
+
+                    let entry = &mut entries[id as usize * NODE_WORDS..][..NODE_WORDS];
+                    entry[OFFSET] = offset as u32;
+                    entry[END] = 0;
+                    entry[PARENT] = current;
+                    entry[FIRST_CHILD] = NONE;
+                    entry[NEXT_SIBLING] = NONE;
+                    entry[PHANDLE] = 0;
+
+                    // Properties can't follow child nodes, so if the last
+                    // node closed shares our parent it's our previous sibling
+                    if current != NONE {
+                        if last_closed != NONE && Self::word(entries, last_closed, PARENT) == current {
+                            entries[last_closed as usize * NODE_WORDS + NEXT_SIBLING] = id;
+                        } else {
+                            entries[current as usize * NODE_WORDS + FIRST_CHILD] = id;
+                        }
+                    }
+
+                    current = id;
+                    offset = DevTreeNode::align_offset(offset + 4 + node.name().len() + 1);
+                }
+                FDT_END_NODE => {
+                    if current == NONE {
+                        return Err(IndexError::Malformed);
+                    }
+                    offset += 4;
+                    entries[current as usize * NODE_WORDS + END] = offset as u32;
+                    last_closed = current;
+                    current = Self::word(entries, current, PARENT);
+
+                    if current == NONE {
+                        break; // Root node closed
+                    }
+                }
+                FDT_PROP => {
+                    let (prop, new_offset) = DevTreeNode::parse_property(dt_struct, dt_strings, offset + 4)
+                        .ok_or(IndexError::Malformed)?;
+                    let is_phandle = prop.name() == "phandle" || prop.name() == "linux,phandle";
+                    if is_phandle && current != NONE && let Some(phandle) = prop.as_u32() {
+                        entries[current as usize * NODE_WORDS + PHANDLE] = phandle;
+                    }
+                    offset = new_offset;
+                }
+                FDT_NOP => {
+                    offset += 4;
+                }
+                FDT_END => return Err(IndexError::Malformed), // Root never closed
+                _ => return Err(IndexError::Malformed),
+            }
+        }
+
+        let (entries, rest) = scratch.split_at_mut(count * NODE_WORDS);
+        let mut with_phandle = 0;
+        for id in 0..count as u32 {
+            if Self::word(entries, id, PHANDLE) != 0 {
+                rest[with_phandle] = id;
+                with_phandle += 1;
+            }
+        }
+        let by_phandle = &mut rest[..with_phandle];
+        by_phandle.sort_unstable_by_key(|&id| Self::word(entries, id, PHANDLE));
+
+        Ok(DevTreeIndex {
+            entries,
+            by_phandle,
+            dt_struct,
+            dt_strings,
+        })
+    }
+
+    fn word(entries: &[u32], id: u32, field: usize) -> u32 {
+        entries[id as usize * NODE_WORDS + field]
+    }
+
+    fn link(&self, id: u32) -> Option<IndexedNode<'i, 'a>> {
+        if id == NONE {
+            None
+        } else {
+            Some(IndexedNode { index: *self, id })
+        }
+    }
+
+    /// Number of indexed nodes
+    pub fn len(&self) -> usize {
+        self.entries.len() / NODE_WORDS
+    }
+
+    /// Whether the index has no nodes at all
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the root node
+    pub fn root(&self) -> IndexedNode<'i, 'a> {
+        IndexedNode { index: *self, id: 0 }
+    }
+
+    /// Iterate over every node in structure block order
+    pub fn nodes(&self) -> impl Iterator<Item = IndexedNode<'i, 'a>> {
+        let index = *self;
+        (0..self.len() as u32).map(move |id| IndexedNode { index, id })
+    }
+
+    /// Find a node by its full path, e.g. `/cpus/cpu@0`
+    pub fn find_node(&self, path: &str) -> Option<IndexedNode<'i, 'a>> {
+        if !path.starts_with('/') {
+            return None;
+        }
+
+        let mut node = self.root();
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            node = node.children().find(|child| child.node().matches_path_component(component))?;
+        }
+        Some(node)
+    }
+
+    /// Find the node that declares the given phandle
+    pub fn node_by_phandle(&self, phandle: u32) -> Option<IndexedNode<'i, 'a>> {
+        if phandle == 0 || phandle == NONE {
+            return None;
+        }
+        let found = self
+            .by_phandle
+            .binary_search_by_key(&phandle, |&id| Self::word(self.entries, id, PHANDLE))
+            .ok()?;
+        self.link(self.by_phandle[found])
+    }
+}
+
+// Node reached through a DevTreeIndex
+#[derive(Debug, Clone, Copy)]
+pub struct IndexedNode<'i, 'a> {
+    index: DevTreeIndex<'i, 'a>,
+    id: u32,
+}
+
+impl<'i, 'a> IndexedNode<'i, 'a> {
+    fn word(&self, field: usize) -> u32 {
+        DevTreeIndex::word(self.index.entries, self.id, field)
+    }
+
+    /// Position of the node in the index
+    pub fn id(&self) -> usize {
+        self.id as usize
+    }
+
+    /// Get the underlying zero-copy node
+    pub fn node(&self) -> DevTreeNode<'a> {
+        DevTreeNode::new_at_offset(self.index.dt_struct, self.index.dt_strings, self.word(OFFSET) as usize)
+            .expect("index entries point at nodes")
+    }
+
+    /// Get the node name
+    pub fn name(&self) -> &'a str {
+        self.node().name()
+    }
+
+    /// Bytes the node takes in the structure block, children included
+    pub fn struct_size(&self) -> usize {
+        (self.word(END) - self.word(OFFSET)) as usize
+    }
+
+    /// Get the parent node, `None` for the root
+    pub fn parent(&self) -> Option<IndexedNode<'i, 'a>> {
+        self.index.link(self.word(PARENT))
+    }
+
+    /// Get all child nodes
+    pub fn children(&self) -> IndexedChildIterator<'i, 'a> {
+        IndexedChildIterator {
+            next: self.index.link(self.word(FIRST_CHILD)),
+        }
+    }
+
+    /// Find a child node by name
+    pub fn child(&self, name: &str) -> Option<IndexedNode<'i, 'a>> {
+        self.children().find(|child| child.name() == name)
+    }
+
+    /// Get all properties of this node
+    pub fn properties(&self) -> PropertyIterator<'a> {
+        self.node().properties()
+    }
+
+    /// Find a property by name
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.node().property(name)
+    }
+
+    /// Get the phandle declared by this node, if any
+    pub fn phandle(&self) -> Option<u32> {
+        match self.word(PHANDLE) {
+            0 => None,
+            phandle => Some(phandle),
+        }
+    }
+}
+
+// Iterator for indexed child nodes, following sibling links
+pub struct IndexedChildIterator<'i, 'a> {
+    next: Option<IndexedNode<'i, 'a>>,
+}
+
+impl<'i, 'a> Iterator for IndexedChildIterator<'i, 'a> {
+    type Item = IndexedNode<'i, 'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next?;
+        self.next = node.index.link(node.word(NEXT_SIBLING));
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::FdtBuilder;
+
+    fn sample() -> DevTree {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("");
+        fdt.prop_u32("#address-cells", 2);
+        fdt.begin_node("chosen");
+        fdt.prop_str("stdout-path", "/pl011@9000000");
+        fdt.end_node();
+        fdt.begin_node("cpus");
+        fdt.begin_node("cpu@0");
+        fdt.nop();
+        fdt.prop_u32("phandle", 0x10);
+        fdt.end_node();
+        fdt.begin_node("cpu@1");
+        fdt.prop_u32("phandle", 0x11);
+        fdt.end_node();
+        fdt.end_node();
+        fdt.begin_node("memory@40000000");
+        fdt.prop_str("device_type", "memory");
+        fdt.end_node();
+        fdt.begin_node("pl011@9000000");
+        fdt.prop_str("compatible", "arm,pl011");
+        fdt.prop_u32("phandle", 0x1);
+        fdt.end_node();
+        fdt.end_node();
+        fdt.build()
+    }
+
+    #[test]
+    fn links_nodes() {
+        let devtree = sample();
+        let mut scratch = [0u32; 64];
+        let index = DevTreeIndex::build(&devtree, &mut scratch).unwrap();
+        assert_eq!(index.len(), 7);
+
+        let root = index.root();
+        let names: Vec<_> = root.children().map(|child| child.name()).collect();
+        assert_eq!(names, ["chosen", "cpus", "memory@40000000", "pl011@9000000"]);
+
+        let cpus = root.child("cpus").unwrap();
+        let cpu1 = cpus.child("cpu@1").unwrap();
+        assert_eq!(cpu1.parent().unwrap().name(), "cpus");
+        assert_eq!(cpus.parent().unwrap().id(), root.id());
+        assert!(root.parent().is_none());
+        assert_eq!(root.property("#address-cells").unwrap().as_u32(), Some(2));
+    }
+
+    #[test]
+    fn finds_paths_and_phandles() {
+        let devtree = sample();
+        let mut scratch = [0u32; 64];
+        let index = DevTreeIndex::build(&devtree, &mut scratch).unwrap();
+
+        assert_eq!(index.find_node("/cpus/cpu@1").unwrap().phandle(), Some(0x11));
+        assert_eq!(index.find_node("/memory").unwrap().name(), "memory@40000000");
+        assert_eq!(index.find_node("/").unwrap().id(), 0);
+        assert!(index.find_node("/cpus/cpu@2").is_none());
+        assert!(index.find_node("cpus").is_none());
+
+        // Found whatever order they're declared in
+        assert_eq!(index.node_by_phandle(0x10).unwrap().name(), "cpu@0");
+        assert_eq!(index.node_by_phandle(0x11).unwrap().name(), "cpu@1");
+        assert_eq!(index.node_by_phandle(0x1).unwrap().name(), "pl011@9000000");
+        assert!(index.node_by_phandle(0x12).is_none());
+        assert!(index.node_by_phandle(0).is_none());
+
+        // Agrees with the unindexed lookups
+        let chosen = devtree.find_node("/chosen").unwrap();
+        assert_eq!(chosen.property("stdout-path").unwrap().as_string(), Some("/pl011@9000000"));
+        assert_eq!(devtree.node_by_phandle(0x11).unwrap().name(), "cpu@1");
+    }
+
+    #[test]
+    fn agrees_with_devtree_past_nops() {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("");
+        fdt.prop_u32("#address-cells", 2);
+        fdt.nop();
+        fdt.begin_node("kid");
+        fdt.nop();
+        fdt.prop_u32("phandle", 0x2);
+        fdt.end_node();
+        fdt.nop();
+        fdt.begin_node("kid2");
+        fdt.begin_node("grandkid@0");
+        fdt.end_node();
+        fdt.end_node();
+        fdt.end_node();
+        let devtree = fdt.build();
+        let mut scratch = [0u32; 64];
+        let index = DevTreeIndex::build(&devtree, &mut scratch).unwrap();
+
+        for path in ["/", "/kid", "/kid2", "/kid2/grandkid", "/kid2/grandkid@0", "/kid3"] {
+            let direct = devtree.find_node(path).map(|node| node.name());
+            let indexed = index.find_node(path).map(|node| node.name());
+            assert_eq!(direct, indexed, "{}", path);
+        }
+        assert_eq!(devtree.find_node("/kid2").unwrap().name(), "kid2");
+        assert_eq!(devtree.node_by_phandle(0x2).unwrap().name(), index.node_by_phandle(0x2).unwrap().name());
+    }
+
+    #[test]
+    fn reports_needed_space() {
+        let devtree = sample();
+        let mut scratch = [0u32; 8];
+        assert_eq!(
+            DevTreeIndex::build(&devtree, &mut scratch).unwrap_err(),
+            IndexError::BufferTooSmall { needed: 7 * INDEX_ENTRY_WORDS }
+        );
+        assert_eq!(DevTreeIndex::required_words(&devtree), 7 * INDEX_ENTRY_WORDS);
+    }
+}
@@ -1,23 +1,282 @@
 #![cfg_attr(not(test), no_std)]
 
-// #[cfg(not(test))]
-// extern crate alloc;
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
 pub mod devtree;
+pub mod index;
 pub mod node;
+#[cfg(feature = "alloc")]
+pub mod owned;
 pub mod property;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod specifier;
 
-pub use devtree::DevTree;
+pub use devtree::{DevTree, MemReservation};
+pub use index::{DevTreeIndex, IndexError, IndexedNode};
 pub use node::DevTreeNode;
-pub use property::Property;
+#[cfg(feature = "alloc")]
+pub use owned::{NodeId, OwnedDevTree, OwnedNode, OwnedProperty};
+pub use property::{Cells, Property, PropertyValue, StringList};
+pub use specifier::{PhandleResolver, Specifier, SpecifierError};
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Minimal DTB writer so tests can describe trees inline
+    pub(crate) struct FdtBuilder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+        reservations: Vec<(u64, u64)>,
+    }
+
+    impl FdtBuilder {
+        pub(crate) fn new() -> Self {
+            FdtBuilder {
+                structs: Vec::new(),
+                strings: Vec::new(),
+                reservations: Vec::new(),
+            }
+        }
+
+        fn token(&mut self, token: u32) {
+            self.structs.extend_from_slice(&token.to_be_bytes());
+        }
+
+        fn pad(&mut self) {
+            while !self.structs.len().is_multiple_of(4) {
+                self.structs.push(0);
+            }
+        }
+
+        pub(crate) fn reserve(&mut self, address: u64, size: u64) {
+            self.reservations.push((address, size));
+        }
+
+        pub(crate) fn begin_node(&mut self, name: &str) {
+            self.token(0x1);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+        }
+
+        pub(crate) fn end_node(&mut self) {
+            self.token(0x2);
+        }
+
+        pub(crate) fn nop(&mut self) {
+            self.token(0x4);
+        }
+
+        pub(crate) fn prop(&mut self, name: &str, value: &[u8]) {
+            let nameoff = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+
+            self.token(0x3);
+            self.structs.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            self.structs.extend_from_slice(&nameoff.to_be_bytes());
+            self.structs.extend_from_slice(value);
+            self.pad();
+        }
+
+        pub(crate) fn prop_u32(&mut self, name: &str, value: u32) {
+            self.prop(name, &value.to_be_bytes());
+        }
+
+        pub(crate) fn prop_cells(&mut self, name: &str, cells: &[u32]) {
+            let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+            self.prop(name, &value);
+        }
+
+        pub(crate) fn prop_str(&mut self, name: &str, value: &str) {
+            let mut bytes = Vec::from(value.as_bytes());
+            bytes.push(0);
+            self.prop(name, &bytes);
+        }
+
+        /// Lay out the blob in leaked, 8-byte aligned memory and parse it
+        pub(crate) fn build(self) -> DevTree {
+            DevTree::from_bytes(self.build_bytes()).expect("built blob should parse")
+        }
+
+        /// Lay out the blob in leaked, 8-byte aligned memory
+        pub(crate) fn build_bytes(mut self) -> &'static mut [u8] {
+            self.token(0x9);
+
+            let off_mem_rsvmap = 48;
+            let off_dt_struct = off_mem_rsvmap + (self.reservations.len() + 1) * 16;
+            let off_dt_strings = off_dt_struct + self.structs.len();
+            let totalsize = off_dt_strings + self.strings.len();
+
+            let mut blob = Vec::with_capacity(totalsize);
+            for field in [
+                0xd00dfeed,
+                totalsize as u32,
+                off_dt_struct as u32,
+                off_dt_strings as u32,
+                off_mem_rsvmap as u32,
+                17,
+                16,
+                0,
+                self.strings.len() as u32,
+                self.structs.len() as u32,
+            ] {
+                blob.extend_from_slice(&u32::to_be_bytes(field));
+            }
+            blob.resize(off_mem_rsvmap, 0);
+            for (address, size) in self.reservations.iter().chain([(0, 0)].iter()) {
+                blob.extend_from_slice(&address.to_be_bytes());
+                blob.extend_from_slice(&size.to_be_bytes());
+            }
+            blob.extend_from_slice(&self.structs);
+            blob.extend_from_slice(&self.strings);
+
+            let words = vec![0u64; totalsize.div_ceil(8)].leak();
+            let bytes = unsafe { core::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, totalsize) };
+            bytes.copy_from_slice(&blob);
+            bytes
+        }
+    }
+
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_sum() {
         let sum: i32 = vec![1, 2, 3].iter().sum();
         assert_eq!(sum, 6);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_iter_descendants() {
+        let mut fdt = FdtBuilder::new();
+        fdt.reserve(0x4000_0000, 0x20_0000);
+        fdt.begin_node("");
+        fdt.prop_u32("#size-cells", 2);
+        fdt.begin_node("cpus");
+        fdt.nop();
+        fdt.prop_u32("#address-cells", 1);
+        fdt.begin_node("cpu@0");
+        fdt.end_node();
+        fdt.end_node();
+        fdt.begin_node("memory@40000000");
+        fdt.prop_cells("reg", &[0, 0x4000_0000, 0, 0x800_0000]);
+        fdt.end_node();
+        fdt.end_node();
+        let devtree = fdt.build();
+
+        let names: Vec<_> = devtree.root().iter_descendants().map(|node| node.name()).collect();
+        assert_eq!(names, ["", "cpus", "cpu@0", "memory@40000000"]);
+
+        let cpus = devtree.root().child("cpus").unwrap();
+        assert_eq!(cpus.property("#address-cells").unwrap().as_u32(), Some(1));
+        assert_eq!(cpus.iter_descendants().count(), 2);
+
+        let reservations: Vec<_> = devtree.mem_reservations().collect();
+        assert_eq!(reservations, [MemReservation { address: 0x4000_0000, size: 0x20_0000 }]);
+    }
+
+    #[test]
+    fn test_children_skip_nops() {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("");
+        fdt.prop_u32("#address-cells", 2);
+        fdt.nop();
+        fdt.prop_u32("#size-cells", 2);
+        fdt.nop();
+        fdt.begin_node("kid");
+        fdt.end_node();
+        fdt.nop();
+        fdt.begin_node("kid2");
+        fdt.begin_node("grandkid");
+        fdt.end_node();
+        fdt.end_node();
+        fdt.end_node();
+        let devtree = fdt.build();
+
+        let root = devtree.root();
+        let children: Vec<_> = root.children().map(|child| child.name()).collect();
+        assert_eq!(children, ["kid", "kid2"]);
+        let names: Vec<_> = root.iter_descendants().map(|node| node.name()).collect();
+        assert_eq!(names, ["", "kid", "kid2", "grandkid"]);
+        assert_eq!(root.size_cells(), 2);
+        assert_eq!(devtree.find_node("/kid2/grandkid").unwrap().name(), "grandkid");
+    }
+
+    #[test]
+    fn test_reg_and_compatible() {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("");
+        fdt.prop_u32("#address-cells", 2);
+        fdt.prop_u32("#size-cells", 2);
+        fdt.begin_node("pl011@9000000");
+        fdt.prop("compatible", b"arm,pl011\0arm,primecell\0");
+        fdt.prop_cells("reg", &[0, 0x900_0000, 0, 0x1000]);
+        fdt.prop_str("status", "disabled");
+        fdt.end_node();
+        fdt.begin_node("pl011@9040000");
+        fdt.prop("compatible", b"arm,pl011\0arm,primecell\0");
+        fdt.prop_cells("reg", &[0, 0x904_0000, 0, 0x1000, 0, 0x905_0000, 0, 0x1000]);
+        fdt.end_node();
+        fdt.end_node();
+        let devtree = fdt.build();
+
+        let root = devtree.root();
+        assert_eq!((root.address_cells(), root.size_cells()), (2, 2));
+
+        let uart = devtree.find_compatible("arm,primecell").unwrap();
+        assert_eq!(uart.name(), "pl011@9040000");
+        let reg: Vec<_> = uart.reg(root.address_cells(), root.size_cells()).collect();
+        assert_eq!(reg, [(0x904_0000, 0x1000), (0x905_0000, 0x1000)]);
+
+        let disabled = devtree.find_node("/pl011@9000000").unwrap();
+        assert!(disabled.is_compatible("arm,pl011") && !disabled.is_enabled());
+        assert!(devtree.find_compatible("arm,pl031").is_none());
+    }
+
+    #[test]
+    fn test_from_bytes_checks_bounds() {
+        // FdtBuilder::build already goes through from_bytes for valid blobs
+
+        // Header claims more than the slice holds
+        let words = vec![0u64; 8].leak();
+        let blob = unsafe { core::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, 64) };
+        blob[..4].copy_from_slice(&0xd00dfeedu32.to_be_bytes());
+        blob[4..8].copy_from_slice(&0x1000u32.to_be_bytes());
+        assert!(DevTree::from_bytes(blob).is_none());
+
+        // Not a DTB at all
+        let words = vec![0u64; 8].leak();
+        let blob = unsafe { core::slice::from_raw_parts(words.as_ptr() as *const u8, 64) };
+        assert!(DevTree::from_bytes(blob).is_none());
+    }
+
+    #[test]
+    fn test_rejects_non_utf8_names() {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("");
+        fdt.prop_u32("good-prop", 1);
+        fdt.prop_u32("bad-prop", 2);
+        fdt.begin_node("good");
+        fdt.end_node();
+        fdt.begin_node("bad-node");
+        fdt.end_node();
+        fdt.end_node();
+        let blob = fdt.build_bytes();
+
+        // Break the first byte of both "bad" names. The property gets a
+        // placeholder name, the node ends the walk.
+        for name in [&b"bad-prop"[..], &b"bad-node"[..]] {
+            let at = blob.windows(name.len()).position(|window| window == name).unwrap();
+            blob[at] = 0xff;
+        }
+
+        let devtree = DevTree::from_bytes(blob).unwrap();
+        let root = devtree.root();
+        let props: Vec<_> = root.properties().map(|prop| prop.name()).collect();
+        assert_eq!(props, ["good-prop", "\u{fffd}"]);
+        let children: Vec<_> = root.children().map(|child| child.name()).collect();
+        assert_eq!(children, ["good"]);
+    }
+}
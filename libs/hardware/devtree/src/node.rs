@@ -1,11 +1,14 @@
-use crate::property::Property;
+use crate::property::{Cells, Property};
 
 // FDT Token constants
-const FDT_BEGIN_NODE: u32 = 0x00000001;
-const FDT_END_NODE: u32 = 0x00000002;
-const FDT_PROP: u32 = 0x00000003;
-const FDT_NOP: u32 = 0x00000004;
-const FDT_END: u32 = 0x00000009;
+pub(crate) const FDT_BEGIN_NODE: u32 = 0x00000001;
+pub(crate) const FDT_END_NODE: u32 = 0x00000002;
+pub(crate) const FDT_PROP: u32 = 0x00000003;
+pub(crate) const FDT_NOP: u32 = 0x00000004;
+pub(crate) const FDT_END: u32 = 0x00000009;
+
+// Stands in for property names that aren't UTF-8
+const INVALID_NAME: &str = "\u{fffd}";
 
 // Device Tree Node representation
 #[derive(Debug, Clone, Copy)]
@@ -22,7 +25,7 @@ impl<'a> DevTreeNode<'a> {
         Self::read_struct_token(self.dt_struct, offset)
     }
 
-    fn read_struct_token(dt_struct: &'a [u8], offset: usize) -> Option<u32> {
+    pub(crate) fn read_struct_token(dt_struct: &'a [u8], offset: usize) -> Option<u32> {
         if offset + 4 > dt_struct.len() {
             return None;
         }
@@ -30,16 +33,8 @@ impl<'a> DevTreeNode<'a> {
         Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
     
-    /// Read a byte from dt_struct at the given offset
-    fn read_byte(&self, offset: usize) -> Option<u8> {
-        if offset >= self.dt_struct.len() {
-            return None;
-        }
-        Some(self.dt_struct[offset])
-    }
-    
     /// Align offset to 4-byte boundary
-    fn align_offset(offset: usize) -> usize {
+    pub(crate) fn align_offset(offset: usize) -> usize {
         (offset + 3) & !3
     }
     
@@ -48,7 +43,7 @@ impl<'a> DevTreeNode<'a> {
         Self::new_at_offset(dt_struct, dt_strings, 0)
     }
     
-    fn new_at_offset(dt_struct: &'a [u8], dt_strings: &'a [u8], offset: usize) -> Option<Self> {
+    pub(crate) fn new_at_offset(dt_struct: &'a [u8], dt_strings: &'a [u8], offset: usize) -> Option<Self> {
         // Check FDT_BEGIN_NODE token
         if Self::read_struct_token(dt_struct, offset)? != FDT_BEGIN_NODE {
             return None;
@@ -66,10 +61,11 @@ impl<'a> DevTreeNode<'a> {
         // // Align to 4-byte boundary
         // current_offset = Self::align_offset(current_offset);
         
-        let name = unsafe {
-            let name_len = current_offset - name_start - 1;
-            core::str::from_utf8_unchecked(&dt_struct[name_start..name_start + name_len])
-        };
+        // Blobs can come from anywhere. Offsets past here depend on the name
+        // length, so one that isn't UTF-8 can't be swapped out and ends the
+        // walk instead.
+        let name_len = current_offset - name_start - 1;
+        let name = core::str::from_utf8(&dt_struct[name_start..name_start + name_len]).ok()?;
         
         Some(DevTreeNode {
             name,
@@ -79,7 +75,7 @@ impl<'a> DevTreeNode<'a> {
         })
     }
     
-    fn parse_property(dt_struct: &'a [u8], dt_strings: &'a [u8], offset: usize) -> Option<(Property<'a>, usize)> {
+    pub(crate) fn parse_property(dt_struct: &'a [u8], dt_strings: &'a [u8], offset: usize) -> Option<(Property<'a>, usize)> {
         if offset + 8 > dt_struct.len() {
             return None;
         }
@@ -102,9 +98,9 @@ impl<'a> DevTreeNode<'a> {
             name_end += 1;
         }
         
-        let name = unsafe {
-            core::str::from_utf8_unchecked(&dt_strings[name_start..name_end])
-        };
+        // A name that isn't UTF-8 can't match anything, but the value is
+        // still where the header says
+        let name = core::str::from_utf8(&dt_strings[name_start..name_end]).unwrap_or(INVALID_NAME);
         
         // Get property value
         let value_offset = offset + 8; // Skip len and nameoff (8 bytes total)
@@ -165,41 +161,105 @@ impl<'a> DevTreeNode<'a> {
     pub fn name(&self) -> &'a str {
         self.name
     }
+
+    /// Whether this node is what a path component refers to. A component
+    /// without a unit address (`memory`) also matches `memory@40000000`.
+    pub fn matches_path_component(&self, component: &str) -> bool {
+        if self.name == component {
+            return true;
+        }
+        !component.contains('@')
+            && self.name.split('@').next() == Some(component)
+    }
+
+    /// Whether `compatible` lists `compat`
+    pub fn is_compatible(&self, compat: &str) -> bool {
+        self.property("compatible")
+            .is_some_and(|prop| prop.strings().any(|c| c == compat))
+    }
+
+    /// Whether the node is usable, i.e. `status` is missing or "okay"
+    pub fn is_enabled(&self) -> bool {
+        match self.property("status").and_then(|prop| prop.as_string()) {
+            None => true,
+            Some(status) => status == "okay" || status == "ok",
+        }
+    }
+
+    /// `#address-cells` this node declares for its children (2 if missing)
+    pub fn address_cells(&self) -> usize {
+        self.property("#address-cells").and_then(|prop| prop.as_u32()).unwrap_or(2) as usize
+    }
+
+    /// `#size-cells` this node declares for its children (1 if missing)
+    pub fn size_cells(&self) -> usize {
+        self.property("#size-cells").and_then(|prop| prop.as_u32()).unwrap_or(1) as usize
+    }
+
+    /// Iterate over the (address, size) pairs of `reg`, sized with the
+    /// parent's `#address-cells` and `#size-cells`
+    pub fn reg(&self, address_cells: usize, size_cells: usize) -> impl Iterator<Item = (u64, u64)> + use<'a> {
+        let mut cells = self.property("reg").map(|prop| prop.cells()).unwrap_or(Cells::new(&[]));
+        core::iter::from_fn(move || {
+            let address = cells.next_number(address_cells)?;
+            let size = cells.next_number(size_cells)?;
+            Some((address, size))
+        })
+    }
+
+    /// Get the phandle declared by this node, if any
+    pub fn phandle(&self) -> Option<u32> {
+        self.properties()
+            .find(|prop| prop.name() == "phandle" || prop.name() == "linux,phandle")
+            .and_then(|prop| prop.as_u32())
+    }
+
+    pub(crate) fn struct_offset(&self) -> usize {
+        self.struct_offset
+    }
+
+    pub(crate) fn dt_struct(&self) -> &'a [u8] {
+        self.dt_struct
+    }
+
+    pub(crate) fn dt_strings(&self) -> &'a [u8] {
+        self.dt_strings
+    }
     
     /// Get all properties of this node
-    pub fn properties(&'a self) -> PropertyIterator<'a> {
+    pub fn properties(&self) -> PropertyIterator<'a> {
         PropertyIterator::new(self)
     }
     
     /// Get all child nodes
-    pub fn children(&'a self) -> ChildNodeIterator<'a> {
+    pub fn children(&self) -> ChildNodeIterator<'a> {
         ChildNodeIterator::new(self)
     }
     
     /// Find a property by name
-    pub fn property(&'a self, name: &str) -> Option<Property<'a>> {
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
         self.properties().find(|prop| prop.name() == name)
     }
     
     /// Find a child node by name
-    pub fn child(&'a self, name: &str) -> Option<DevTreeNode<'a>> {
+    pub fn child(&self, name: &str) -> Option<DevTreeNode<'a>> {
         self.children().find(|child| child.name() == name)
     }
     
     /// Iterate over all descendant nodes (depth-first)
-    pub fn iter_descendants(&'a self) -> NodeIterator<'a> {
+    pub fn iter_descendants(&self) -> NodeIterator<'a> {
         NodeIterator::new(self)
     }
 }
 
 // Iterator for properties
 pub struct PropertyIterator<'a> {
-    node: &'a DevTreeNode<'a>,
+    node: DevTreeNode<'a>,
     current_offset: usize,
 }
 
 impl<'a> PropertyIterator<'a> {
-    fn new(node: &'a DevTreeNode<'a>) -> Self {
+    fn new(node: &DevTreeNode<'a>) -> Self {
         // Find the start of properties after the node name
         let mut offset = node.struct_offset + 4; // Skip FDT_BEGIN_NODE token
         
@@ -211,7 +271,7 @@ impl<'a> PropertyIterator<'a> {
         offset = DevTreeNode::align_offset(offset); // Align to 4-byte boundary
         
         PropertyIterator {
-            node,
+            node: *node,
             current_offset: offset,
         }
     }
@@ -221,6 +281,11 @@ impl<'a> Iterator for PropertyIterator<'a> {
     type Item = Property<'a>;
     
     fn next(&mut self) -> Option<Self::Item> {
+        // Skip padding left behind by in-place edits
+        while self.node.read_token(self.current_offset)? == FDT_NOP {
+            self.current_offset += 4;
+        }
+
         // Check for FDT_PROP token
         if self.node.read_token(self.current_offset)? != FDT_PROP {
             return None; // Not a property token
@@ -241,12 +306,12 @@ impl<'a> Iterator for PropertyIterator<'a> {
 
 // Iterator for child nodes
 pub struct ChildNodeIterator<'a> {
-    node: &'a DevTreeNode<'a>,
+    node: DevTreeNode<'a>,
     current_offset: usize,
 }
 
 impl<'a> ChildNodeIterator<'a> {
-    fn new(node: &'a DevTreeNode<'a>) -> Self {
+    fn new(node: &DevTreeNode<'a>) -> Self {
         // Find the start of children after properties
         let mut offset = node.struct_offset + 4; // Skip FDT_BEGIN_NODE token
         
@@ -257,9 +322,11 @@ impl<'a> ChildNodeIterator<'a> {
         offset += 1; // Skip null terminator
         offset = DevTreeNode::align_offset(offset); // Align to 4-byte boundary
         
-        // Skip properties
+        // Skip properties, and any padding between them
         while let Some(token) = node.read_token(offset) {
-            if token == FDT_PROP {
+            if token == FDT_NOP {
+                offset += 4;
+            } else if token == FDT_PROP {
                 // Property - skip it
                 if let Some((_, new_offset)) = DevTreeNode::parse_property(
                     node.dt_struct, 
@@ -276,7 +343,7 @@ impl<'a> ChildNodeIterator<'a> {
         }
         
         ChildNodeIterator {
-            node,
+            node: *node,
             current_offset: offset,
         }
     }
@@ -286,6 +353,11 @@ impl<'a> Iterator for ChildNodeIterator<'a> {
     type Item = DevTreeNode<'a>;
     
     fn next(&mut self) -> Option<Self::Item> {
+        // Skip padding between siblings
+        while self.node.read_token(self.current_offset)? == FDT_NOP {
+            self.current_offset += 4;
+        }
+
         // Check for FDT_BEGIN_NODE token
         if self.node.read_token(self.current_offset)? != FDT_BEGIN_NODE {
             return None; // Not a begin node token
@@ -306,14 +378,18 @@ impl<'a> Iterator for ChildNodeIterator<'a> {
 
 // Iterator for traversing all descendant nodes
 pub struct NodeIterator<'a> {
-    current: Option<DevTreeNode<'a>>,
+    dt_struct: &'a [u8],
+    dt_strings: &'a [u8],
+    current_offset: Option<usize>,
     depth: usize,
 }
 
 impl<'a> NodeIterator<'a> {
-    fn new(root: &'a DevTreeNode<'a>) -> Self {
+    fn new(root: &DevTreeNode<'a>) -> Self {
         NodeIterator {
-            current: Some(*root),
+            dt_struct: root.dt_struct,
+            dt_strings: root.dt_strings,
+            current_offset: Some(root.struct_offset),
             depth: 0,
         }
     }
@@ -323,13 +399,43 @@ impl<'a> Iterator for NodeIterator<'a> {
     type Item = DevTreeNode<'a>;
     
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(node) = self.current {
-            // For now, just return the current node
-            // A full implementation would need to track the traversal state
-            self.current = None;
-            Some(node)
-        } else {
-            None
+        let mut offset = self.current_offset?;
+
+        // Walk the token stream until the next node begins or the starting
+        // node ends
+        while let Some(token) = DevTreeNode::read_struct_token(self.dt_struct, offset) {
+            match token {
+                FDT_BEGIN_NODE => {
+                    let node = match DevTreeNode::new_at_offset(self.dt_struct, self.dt_strings, offset) {
+                        Some(node) => node,
+                        None => break,
+                    };
+                    self.depth += 1;
+                    self.current_offset = Some(DevTreeNode::align_offset(offset + 4 + node.name().len() + 1));
+                    return Some(node);
+                }
+                FDT_END_NODE => {
+                    offset += 4;
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        break;
+                    }
+                }
+                FDT_PROP => {
+                    match DevTreeNode::parse_property(self.dt_struct, self.dt_strings, offset + 4) {
+                        Some((_, new_offset)) => offset = new_offset,
+                        None => break,
+                    }
+                }
+                FDT_NOP => {
+                    offset += 4;
+                }
+                FDT_END => break,
+                _ => break, // Unknown token, the blob is malformed
+            }
         }
+
+        self.current_offset = None;
+        None
     }
 }
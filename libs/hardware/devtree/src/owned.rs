@@ -0,0 +1,330 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use hashbrown::HashMap;
+
+use crate::devtree::{DevTree, MemReservation};
+use crate::node::DevTreeNode;
+use crate::property::Property;
+
+// Index of a node inside an OwnedDevTree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+// Device Tree Property copied out of the blob
+#[derive(Debug, Clone)]
+pub struct OwnedProperty {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl OwnedProperty {
+    /// Get the property name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the property value as bytes
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Borrow as a zero-copy property, to reuse its value decoders
+    pub fn as_property(&self) -> Property<'_> {
+        Property::new(&self.name, &self.value)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct NodeData {
+    name: String,
+    path: String,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    properties: Vec<OwnedProperty>,
+    property_index: HashMap<String, usize>,
+}
+
+// Owned Device Tree representation
+//
+// Nodes live in a flat arena and link to each other by NodeId, so parent
+// walks and phandle/path lookups don't need to rescan anything. Once built,
+// nothing points back into the original blob.
+#[derive(Debug, Clone)]
+pub struct OwnedDevTree {
+    nodes: Vec<NodeData>,
+    paths: HashMap<String, NodeId>,
+    phandles: HashMap<u32, NodeId>,
+    mem_reservations: Vec<MemReservation>,
+    version: u32,
+    boot_cpuid_phys: u32,
+}
+
+impl OwnedDevTree {
+    /// Copy a parsed blob into an owned tree
+    pub fn from_devtree(devtree: &DevTree) -> Self {
+        let mut tree = OwnedDevTree {
+            nodes: Vec::new(),
+            paths: HashMap::new(),
+            phandles: HashMap::new(),
+            mem_reservations: devtree.mem_reservations().collect(),
+            version: devtree.header().version(),
+            boot_cpuid_phys: devtree.header().boot_cpuid_phys(),
+        };
+
+        tree.copy_node(devtree.root(), None);
+        tree
+    }
+
+    fn copy_node(&mut self, node: &DevTreeNode<'_>, parent: Option<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+
+        let path = match parent {
+            None => String::from("/"),
+            Some(parent) => {
+                let parent_path = &self.nodes[parent.0].path;
+                if parent_path == "/" {
+                    format!("/{}", node.name())
+                } else {
+                    format!("{}/{}", parent_path, node.name())
+                }
+            }
+        };
+
+        let mut properties = Vec::new();
+        let mut property_index = HashMap::new();
+        for prop in node.properties() {
+            property_index.insert(String::from(prop.name()), properties.len());
+            properties.push(OwnedProperty {
+                name: String::from(prop.name()),
+                value: Vec::from(prop.value()),
+            });
+        }
+
+        let phandle = ["phandle", "linux,phandle"]
+            .iter()
+            .filter_map(|name| property_index.get(*name))
+            .find_map(|&index| properties[index].as_property().as_u32());
+        if let Some(phandle) = phandle {
+            self.phandles.insert(phandle, id);
+        }
+
+        self.paths.insert(path.clone(), id);
+        self.nodes.push(NodeData {
+            name: String::from(node.name()),
+            path,
+            parent,
+            children: Vec::new(),
+            properties,
+            property_index,
+        });
+
+        for child in node.children() {
+            let child_id = self.copy_node(&child, Some(id));
+            self.nodes[id.0].children.push(child_id);
+        }
+
+        id
+    }
+
+    /// Get the root node of the device tree
+    pub fn root(&self) -> OwnedNode<'_> {
+        self.node(NodeId(0))
+    }
+
+    /// Get a node by its id
+    pub fn node(&self, id: NodeId) -> OwnedNode<'_> {
+        OwnedNode { tree: self, id }
+    }
+
+    /// Number of nodes in the tree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether the tree has no nodes at all
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Iterate over every node, parents before children
+    pub fn nodes(&self) -> impl Iterator<Item = OwnedNode<'_>> {
+        (0..self.nodes.len()).map(|index| self.node(NodeId(index)))
+    }
+
+    /// Find a node by its full path, e.g. `/cpus/cpu@0`
+    pub fn find_node(&self, path: &str) -> Option<OwnedNode<'_>> {
+        self.paths.get(path).map(|&id| self.node(id))
+    }
+
+    /// Find the node that declares the given phandle
+    pub fn node_by_phandle(&self, phandle: u32) -> Option<OwnedNode<'_>> {
+        self.phandles.get(&phandle).map(|&id| self.node(id))
+    }
+
+    /// Get the decoded memory reservation entries
+    pub fn mem_reservations(&self) -> &[MemReservation] {
+        &self.mem_reservations
+    }
+
+    /// Get the format version from the blob header
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Get the boot CPU physical ID from the blob header
+    pub fn boot_cpuid_phys(&self) -> u32 {
+        self.boot_cpuid_phys
+    }
+}
+
+impl From<&DevTree> for OwnedDevTree {
+    fn from(devtree: &DevTree) -> Self {
+        Self::from_devtree(devtree)
+    }
+}
+
+// Handle to a node of an OwnedDevTree
+#[derive(Debug, Clone, Copy)]
+pub struct OwnedNode<'t> {
+    tree: &'t OwnedDevTree,
+    id: NodeId,
+}
+
+impl<'t> OwnedNode<'t> {
+    fn data(&self) -> &'t NodeData {
+        &self.tree.nodes[self.id.0]
+    }
+
+    /// Get the node id
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Get the node name
+    pub fn name(&self) -> &'t str {
+        &self.data().name
+    }
+
+    /// Get the full path of the node
+    pub fn path(&self) -> &'t str {
+        &self.data().path
+    }
+
+    /// Get the parent node, `None` for the root
+    pub fn parent(&self) -> Option<OwnedNode<'t>> {
+        self.data().parent.map(|id| self.tree.node(id))
+    }
+
+    /// Get all properties of this node
+    pub fn properties(&self) -> impl Iterator<Item = &'t OwnedProperty> {
+        self.data().properties.iter()
+    }
+
+    /// Get all child nodes
+    pub fn children(&self) -> impl Iterator<Item = OwnedNode<'t>> {
+        let tree = self.tree;
+        self.data().children.iter().map(move |&id| tree.node(id))
+    }
+
+    /// Find a property by name
+    pub fn property(&self, name: &str) -> Option<&'t OwnedProperty> {
+        let data = self.data();
+        data.property_index.get(name).map(|&index| &data.properties[index])
+    }
+
+    /// Find a child node by name
+    pub fn child(&self, name: &str) -> Option<OwnedNode<'t>> {
+        self.children().find(|child| child.name() == name)
+    }
+
+    /// Get the phandle declared by this node, if any
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|prop| prop.as_property().as_u32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::FdtBuilder;
+
+    fn sample() -> OwnedDevTree {
+        let mut fdt = FdtBuilder::new();
+        fdt.reserve(0x4800_0000, 0x1000);
+        fdt.begin_node("");
+        fdt.prop_str("compatible", "linux,dummy-virt");
+        fdt.prop_u32("#address-cells", 2);
+        fdt.begin_node("cpus");
+        fdt.begin_node("cpu@0");
+        fdt.prop_str("device_type", "cpu");
+        fdt.prop_u32("phandle", 1);
+        fdt.end_node();
+        fdt.begin_node("cpu@1");
+        fdt.prop_u32("linux,phandle", 2);
+        fdt.end_node();
+        fdt.end_node();
+        fdt.begin_node("intc@8000000");
+        fdt.prop_u32("phandle", 0x8001);
+        fdt.end_node();
+        fdt.end_node();
+
+        OwnedDevTree::from_devtree(&fdt.build())
+    }
+
+    #[test]
+    fn copies_structure() {
+        let tree = sample();
+        assert_eq!(tree.len(), 5);
+
+        let root = tree.root();
+        assert_eq!(root.path(), "/");
+        assert!(root.parent().is_none());
+        assert_eq!(root.children().map(|c| c.name()).collect::<Vec<_>>(), ["cpus", "intc@8000000"]);
+
+        let cpu = tree.find_node("/cpus/cpu@0").unwrap();
+        assert_eq!(cpu.parent().unwrap().path(), "/cpus");
+        assert_eq!(cpu.property("device_type").unwrap().as_property().as_string(), Some("cpu"));
+        assert!(cpu.property("status").is_none());
+        assert!(tree.find_node("/cpus/cpu@7").is_none());
+    }
+
+    #[test]
+    fn copies_past_nops() {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("");
+        fdt.prop_u32("#address-cells", 2);
+        fdt.nop();
+        fdt.begin_node("kid");
+        fdt.end_node();
+        fdt.nop();
+        fdt.begin_node("kid2");
+        fdt.end_node();
+        fdt.end_node();
+
+        let tree = OwnedDevTree::from_devtree(&fdt.build());
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.root().children().map(|c| c.name()).collect::<Vec<_>>(), ["kid", "kid2"]);
+    }
+
+    #[test]
+    fn looks_up_phandles() {
+        let tree = sample();
+        assert_eq!(tree.node_by_phandle(1).unwrap().path(), "/cpus/cpu@0");
+        assert_eq!(tree.node_by_phandle(2).unwrap().path(), "/cpus/cpu@1");
+        assert_eq!(tree.node_by_phandle(0x8001).unwrap().name(), "intc@8000000");
+        assert!(tree.node_by_phandle(3).is_none());
+    }
+
+    #[test]
+    fn copies_reservations() {
+        let tree = sample();
+        assert_eq!(
+            tree.mem_reservations(),
+            [MemReservation { address: 0x4800_0000, size: 0x1000 }]
+        );
+    }
+}
@@ -65,4 +65,186 @@ impl<'a> Property<'a> {
             None
         }
     }
+
+    /// Iterate over the value as a list of null-terminated strings
+    pub fn strings(&self) -> StringList<'a> {
+        StringList { data: self.value }
+    }
+
+    /// Iterate over the value as big-endian 32-bit cells
+    pub fn cells(&self) -> Cells<'a> {
+        Cells::new(self.value)
+    }
+
+    /// Guess the value type the way `dtc -O dts` does when decompiling
+    pub fn decode(&self) -> PropertyValue<'a> {
+        if self.value.is_empty() {
+            PropertyValue::Empty
+        } else if Self::is_string_list(self.value) {
+            PropertyValue::Strings(self.strings())
+        } else if self.value.len().is_multiple_of(4) {
+            PropertyValue::Cells(self.cells())
+        } else {
+            PropertyValue::Bytes(self.value)
+        }
+    }
+
+    fn is_string_list(value: &[u8]) -> bool {
+        if value.last() != Some(&0) {
+            return false;
+        }
+        value[..value.len() - 1]
+            .split(|&byte| byte == 0)
+            .all(|s| !s.is_empty() && s.iter().all(|&byte| byte.is_ascii_graphic() || byte == b' '))
+    }
+}
+
+// Best-effort decoded property value
+#[derive(Debug, Clone, Copy)]
+pub enum PropertyValue<'a> {
+    Empty,
+    Strings(StringList<'a>),
+    Cells(Cells<'a>),
+    Bytes(&'a [u8]),
+}
+
+impl core::fmt::Display for PropertyValue<'_> {
+    /// Format using dts syntax: `"a", "b"`, `<0x1 0x2>` or `[01 02]`
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PropertyValue::Empty => Ok(()),
+            PropertyValue::Strings(strings) => {
+                for (i, s) in strings.enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "\"{}\"", s)?;
+                }
+                Ok(())
+            }
+            PropertyValue::Cells(cells) => {
+                write!(f, "<")?;
+                for (i, cell) in cells.enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{:#x}", cell)?;
+                }
+                write!(f, ">")
+            }
+            PropertyValue::Bytes(bytes) => {
+                write!(f, "[")?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+// Iterator over a null-terminated string list, like `compatible`
+#[derive(Debug, Clone, Copy)]
+pub struct StringList<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for StringList<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let end = self.data.iter().position(|&byte| byte == 0).unwrap_or(self.data.len());
+        let s = core::str::from_utf8(&self.data[..end]).ok();
+        self.data = &self.data[(end + 1).min(self.data.len())..];
+        s
+    }
+}
+
+// Iterator over big-endian 32-bit cells, ignoring any trailing partial cell
+#[derive(Debug, Clone, Copy)]
+pub struct Cells<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cells<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Cells { data }
+    }
+
+    /// Number of cells left
+    pub fn remaining(&self) -> usize {
+        self.data.len() / 4
+    }
+
+    /// Get the cell at `index` without consuming anything
+    pub fn get(&self, index: usize) -> Option<u32> {
+        let bytes = self.data.get(index * 4..index * 4 + 4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Split off the next `count` cells
+    pub fn take_cells(&mut self, count: usize) -> Option<Cells<'a>> {
+        if self.remaining() < count {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(count * 4);
+        self.data = rest;
+        Some(Cells { data: taken })
+    }
+
+    /// Read `count` cells (at most 2) as one big-endian number, e.g. an
+    /// address sized by `#address-cells`
+    pub fn next_number(&mut self, count: usize) -> Option<u64> {
+        if count > 2 || self.remaining() < count {
+            return None;
+        }
+        let mut value = 0u64;
+        for _ in 0..count {
+            value = (value << 32) | self.next()? as u64;
+        }
+        Some(value)
+    }
+}
+
+impl<'a> Iterator for Cells<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cell = self.get(0)?;
+        self.data = &self.data[4..];
+        Some(cell)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_like_dtc() {
+        let compatible = Property::new("compatible", b"arm,pl011\0arm,primecell\0");
+        assert!(matches!(compatible.decode(), PropertyValue::Strings(_)));
+        assert_eq!(compatible.strings().collect::<Vec<_>>(), ["arm,pl011", "arm,primecell"]);
+        assert_eq!(compatible.decode().to_string(), "\"arm,pl011\", \"arm,primecell\"");
+
+        let reg = Property::new("reg", &[0, 0, 0, 0, 0x09, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10, 0]);
+        assert_eq!(reg.decode().to_string(), "<0x0 0x9000000 0x0 0x1000>");
+        let mut cells = reg.cells();
+        assert_eq!(cells.next_number(2), Some(0x900_0000));
+        assert_eq!(cells.next_number(2), Some(0x1000));
+        assert_eq!(cells.next_number(1), None);
+
+        // Looks like text but isn't terminated
+        let mac = Property::new("local-mac-address", &[0x52, 0x54, 0x00, 0x12, 0x34]);
+        assert_eq!(mac.decode().to_string(), "[52 54 00 12 34]");
+
+        assert!(matches!(Property::new("dma-coherent", &[]).decode(), PropertyValue::Empty));
+    }
 }
@@ -0,0 +1,203 @@
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, SerializeStruct, Serializer};
+
+use crate::devtree::{DevTree, MemReservation};
+use crate::node::DevTreeNode;
+use crate::property::{Property, PropertyValue};
+
+// Trees serialize close to `dtc -O yaml`, except that a node keeps its
+// properties and its children in separate maps by name, so the two can't
+// collide. A property is its decoded value.
+//
+// - empty properties become `true`
+// - a single string becomes a string, string lists an array of strings
+// - cell arrays become arrays of numbers
+// - anything else is kept as raw bytes
+
+impl Serialize for DevTree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tree = serializer.serialize_struct("DevTree", 4)?;
+        tree.serialize_field("version", &self.header().version())?;
+        tree.serialize_field("boot_cpuid_phys", &self.header().boot_cpuid_phys())?;
+        tree.serialize_field("memreserve", &Reservations(self))?;
+        tree.serialize_field("root", self.root())?;
+        tree.end()
+    }
+}
+
+struct Reservations<'t>(&'t DevTree);
+
+impl Serialize for Reservations<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.mem_reservations())
+    }
+}
+
+impl Serialize for MemReservation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entry = serializer.serialize_struct("MemReservation", 2)?;
+        entry.serialize_field("address", &self.address)?;
+        entry.serialize_field("size", &self.size)?;
+        entry.end()
+    }
+}
+
+impl Serialize for DevTreeNode<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let properties = ByName(|| self.properties().map(|prop| (prop.name(), prop)));
+        let children = ByName(|| self.children().map(|child| (child.name(), child)));
+        serialize_node(serializer, &properties, &children)
+    }
+}
+
+fn serialize_node<S: Serializer>(
+    serializer: S,
+    properties: &impl Serialize,
+    children: &impl Serialize,
+) -> Result<S::Ok, S::Error> {
+    let mut node = serializer.serialize_struct("Node", 2)?;
+    node.serialize_field("properties", properties)?;
+    node.serialize_field("children", children)?;
+    node.end()
+}
+
+// Map of whatever the closure iterates over, keyed by name
+struct ByName<F>(F);
+
+impl<'n, F, I, V> Serialize for ByName<F>
+where
+    F: Fn() -> I,
+    I: Iterator<Item = (&'n str, V)>,
+    V: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some((self.0)().count()))?;
+        for (name, value) in (self.0)() {
+            map.serialize_entry(name, &value)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Property<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.decode().serialize(serializer)
+    }
+}
+
+impl Serialize for PropertyValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            PropertyValue::Empty => serializer.serialize_bool(true),
+            PropertyValue::Strings(strings) => {
+                let mut first = strings;
+                match (first.next(), first.next()) {
+                    (Some(s), None) => serializer.serialize_str(s),
+                    _ => serializer.collect_seq(strings),
+                }
+            }
+            PropertyValue::Cells(cells) => {
+                let mut seq = serializer.serialize_seq(Some(cells.remaining()))?;
+                for cell in cells {
+                    seq.serialize_element(&cell)?;
+                }
+                seq.end()
+            }
+            PropertyValue::Bytes(bytes) => serializer.serialize_bytes(bytes),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod owned {
+    use super::*;
+    use crate::owned::{OwnedDevTree, OwnedNode, OwnedProperty};
+
+    impl Serialize for OwnedDevTree {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut tree = serializer.serialize_struct("DevTree", 4)?;
+            tree.serialize_field("version", &self.version())?;
+            tree.serialize_field("boot_cpuid_phys", &self.boot_cpuid_phys())?;
+            tree.serialize_field("memreserve", self.mem_reservations())?;
+            tree.serialize_field("root", &self.root())?;
+            tree.end()
+        }
+    }
+
+    impl Serialize for OwnedNode<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let properties = ByName(|| self.properties().map(|prop| (prop.name(), prop)));
+            let children = ByName(|| self.children().map(|child| (child.name(), child)));
+            serialize_node(serializer, &properties, &children)
+        }
+    }
+
+    impl Serialize for OwnedProperty {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.as_property().serialize(serializer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::owned::OwnedDevTree;
+    use crate::tests::FdtBuilder;
+
+    fn sample() -> crate::DevTree {
+        let mut fdt = FdtBuilder::new();
+        fdt.reserve(0x4800_0000, 0x1000);
+        fdt.begin_node("");
+        fdt.prop("compatible", b"linux,dummy-virt\0");
+        // Padding an in-place edit could leave behind
+        fdt.nop();
+        fdt.prop_u32("#address-cells", 2);
+        fdt.nop();
+        fdt.begin_node("pl011@9000000");
+        fdt.prop("compatible", b"arm,pl011\0arm,primecell\0");
+        fdt.prop_cells("reg", &[0, 0x900_0000, 0, 0x1000]);
+        fdt.prop("dma-coherent", &[]);
+        fdt.prop("local-mac-address", &[0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        fdt.end_node();
+        fdt.nop();
+        fdt.begin_node("compatible");
+        fdt.prop_str("status", "okay");
+        fdt.end_node();
+        fdt.end_node();
+        fdt.build()
+    }
+
+    #[test]
+    fn serializes_to_json() {
+        let devtree = sample();
+        let json = serde_json::to_value(&devtree).unwrap();
+
+        assert_eq!(json["version"], 17);
+        assert_eq!(json["memreserve"][0]["address"], 0x4800_0000);
+        let root = &json["root"]["properties"];
+        assert_eq!(root["compatible"], "linux,dummy-virt");
+        assert_eq!(root["#address-cells"], serde_json::json!([2]));
+
+        // Same name as a property, but kept apart from it
+        assert_eq!(json["root"]["children"]["compatible"]["properties"]["status"], "okay");
+
+        let uart = &json["root"]["children"]["pl011@9000000"]["properties"];
+        assert_eq!(uart["compatible"], serde_json::json!(["arm,pl011", "arm,primecell"]));
+        assert_eq!(uart["reg"], serde_json::json!([0, 0x900_0000, 0, 0x1000]));
+        assert_eq!(uart["dma-coherent"], true);
+        assert_eq!(uart["local-mac-address"], serde_json::json!([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]));
+    }
+
+    #[test]
+    fn owned_matches_borrowed() {
+        let devtree = sample();
+        let owned = OwnedDevTree::from_devtree(&devtree);
+
+        let borrowed = serde_json::to_value(&devtree).unwrap();
+        let copied = serde_json::to_value(&owned).unwrap();
+        assert_eq!(borrowed, copied);
+        assert_eq!(owned.len(), 3);
+        let children = borrowed["root"]["children"].as_object().unwrap();
+        assert_eq!(children.keys().collect::<Vec<_>>(), ["compatible", "pl011@9000000"]);
+        assert_eq!(copied["memreserve"][0]["size"], 0x1000);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::devtree::DevTree;
+use crate::index::DevTreeIndex;
+use crate::node::DevTreeNode;
+use crate::property::{Cells, Property};
+
+// Anything that can turn a phandle back into its node
+pub trait PhandleResolver<'a> {
+    fn resolve_phandle(&self, phandle: u32) -> Option<DevTreeNode<'a>>;
+}
+
+impl PhandleResolver<'static> for DevTree {
+    fn resolve_phandle(&self, phandle: u32) -> Option<DevTreeNode<'static>> {
+        self.node_by_phandle(phandle)
+    }
+}
+
+impl<'a> PhandleResolver<'a> for DevTreeIndex<'_, 'a> {
+    fn resolve_phandle(&self, phandle: u32) -> Option<DevTreeNode<'a>> {
+        self.node_by_phandle(phandle).map(|node| node.node())
+    }
+}
+
+// One `<&provider args...>` entry of a specifier list
+#[derive(Debug, Clone, Copy)]
+pub struct Specifier<'a> {
+    pub provider: DevTreeNode<'a>,
+    pub args: Cells<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecifierError {
+    /// The entry's phandle is 0, a placeholder that keeps later indices stable
+    Empty,
+    /// No node declares the phandle
+    UnknownPhandle(u32),
+    /// The provider doesn't say how many cells its specifiers take
+    MissingCells,
+    /// The list ends in the middle of an entry
+    Truncated,
+}
+
+// Iterator over a phandle-plus-args list like `clocks`, `resets` or `*-gpios`
+//
+// Every entry is a phandle followed by as many cells as the provider's
+// `#<kind>-cells` says, so each step needs the provider node to know where
+// the next entry starts. Errors that lose track of that end the iteration.
+pub struct SpecifierIterator<'a, 'r, R> {
+    resolver: &'r R,
+    cells: Cells<'a>,
+    cells_name: &'static str,
+}
+
+impl<'a, R: PhandleResolver<'a>> Iterator for SpecifierIterator<'a, '_, R> {
+    type Item = Result<Specifier<'a>, SpecifierError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let phandle = self.cells.next()?;
+        if phandle == 0 {
+            return Some(Err(SpecifierError::Empty));
+        }
+
+        let result = self.next_entry(phandle);
+        if result.is_err() {
+            self.cells = Cells::new(&[]);
+        }
+        Some(result)
+    }
+}
+
+impl<'a, R: PhandleResolver<'a>> SpecifierIterator<'a, '_, R> {
+    fn next_entry(&mut self, phandle: u32) -> Result<Specifier<'a>, SpecifierError> {
+        let provider = self
+            .resolver
+            .resolve_phandle(phandle)
+            .ok_or(SpecifierError::UnknownPhandle(phandle))?;
+        let count = provider
+            .property(self.cells_name)
+            .and_then(|prop| prop.as_u32())
+            .ok_or(SpecifierError::MissingCells)? as usize;
+
+        let args = self.cells.take_cells(count).ok_or(SpecifierError::Truncated)?;
+        Ok(Specifier { provider, args })
+    }
+}
+
+impl<'a> DevTreeNode<'a> {
+    /// Decode the specifier list in `list`, sizing each entry with the
+    /// provider's `cells_name` property
+    pub fn specifiers<'r, R: PhandleResolver<'a>>(
+        &self,
+        resolver: &'r R,
+        list: &str,
+        cells_name: &'static str,
+    ) -> SpecifierIterator<'a, 'r, R> {
+        Self::specifiers_in(self.property(list), resolver, cells_name)
+    }
+
+    /// Get the specifier that `names_prop` labels `name`, e.g. the "core"
+    /// entry of `resets` through `reset-names`
+    pub fn specifier_by_name<R: PhandleResolver<'a>>(
+        &self,
+        resolver: &R,
+        list: &str,
+        cells_name: &'static str,
+        names_prop: &str,
+        name: &str,
+    ) -> Option<Result<Specifier<'a>, SpecifierError>> {
+        let index = self.property(names_prop)?.strings().position(|n| n == name)?;
+        self.specifiers(resolver, list, cells_name).nth(index)
+    }
+
+    fn specifiers_in<'r, R: PhandleResolver<'a>>(
+        prop: Option<Property<'a>>,
+        resolver: &'r R,
+        cells_name: &'static str,
+    ) -> SpecifierIterator<'a, 'r, R> {
+        SpecifierIterator {
+            resolver,
+            cells: prop.map(|prop| prop.cells()).unwrap_or(Cells::new(&[])),
+            cells_name,
+        }
+    }
+
+    /// Get the GPIOs for `function`, i.e. `<function>-gpios` (or plain
+    /// `gpios` when `function` is empty), also accepting the legacy
+    /// `-gpio` suffix
+    pub fn gpios<'r, R: PhandleResolver<'a>>(&self, resolver: &'r R, function: &str) -> SpecifierIterator<'a, 'r, R> {
+        let prop = self.properties().find(|prop| {
+            let name = prop.name();
+            if function.is_empty() {
+                return name == "gpios" || name == "gpio";
+            }
+            name.strip_prefix(function)
+                .and_then(|suffix| suffix.strip_prefix('-'))
+                .is_some_and(|suffix| suffix == "gpios" || suffix == "gpio")
+        });
+        Self::specifiers_in(prop, resolver, "#gpio-cells")
+    }
+
+    /// Get the entries of `clocks`
+    pub fn clocks<'r, R: PhandleResolver<'a>>(&self, resolver: &'r R) -> SpecifierIterator<'a, 'r, R> {
+        self.specifiers(resolver, "clocks", "#clock-cells")
+    }
+
+    /// Get the `clocks` entry named `name` in `clock-names`
+    pub fn clock<R: PhandleResolver<'a>>(&self, resolver: &R, name: &str) -> Option<Result<Specifier<'a>, SpecifierError>> {
+        self.specifier_by_name(resolver, "clocks", "#clock-cells", "clock-names", name)
+    }
+
+    /// Get the entries of `resets`
+    pub fn resets<'r, R: PhandleResolver<'a>>(&self, resolver: &'r R) -> SpecifierIterator<'a, 'r, R> {
+        self.specifiers(resolver, "resets", "#reset-cells")
+    }
+
+    /// Get the `resets` entry named `name` in `reset-names`
+    pub fn reset<R: PhandleResolver<'a>>(&self, resolver: &R, name: &str) -> Option<Result<Specifier<'a>, SpecifierError>> {
+        self.specifier_by_name(resolver, "resets", "#reset-cells", "reset-names", name)
+    }
+
+    /// Get the entries of `power-domains`
+    pub fn power_domains<'r, R: PhandleResolver<'a>>(&self, resolver: &'r R) -> SpecifierIterator<'a, 'r, R> {
+        self.specifiers(resolver, "power-domains", "#power-domain-cells")
+    }
+
+    /// Get the `power-domains` entry named `name` in `power-domain-names`
+    pub fn power_domain<R: PhandleResolver<'a>>(&self, resolver: &R, name: &str) -> Option<Result<Specifier<'a>, SpecifierError>> {
+        self.specifier_by_name(resolver, "power-domains", "#power-domain-cells", "power-domain-names", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::INDEX_ENTRY_WORDS;
+    use crate::tests::FdtBuilder;
+
+    // Roughly what QEMU `virt` generates for its power button
+    fn sample() -> DevTree {
+        let mut fdt = FdtBuilder::new();
+        fdt.begin_node("");
+        fdt.begin_node("pl061@9030000");
+        fdt.prop_u32("phandle", 0x8003);
+        fdt.prop_u32("#gpio-cells", 2);
+        fdt.prop("gpio-controller", &[]);
+        fdt.end_node();
+        fdt.begin_node("gpio-keys");
+        fdt.prop_str("compatible", "gpio-keys");
+        fdt.begin_node("poweroff");
+        fdt.prop_cells("gpios", &[0x8003, 3, 0]);
+        fdt.prop_u32("linux,code", 0x74);
+        fdt.end_node();
+        fdt.end_node();
+        fdt.begin_node("reset-controller");
+        fdt.prop_u32("phandle", 0x10);
+        fdt.prop_u32("#reset-cells", 1);
+        fdt.end_node();
+        fdt.begin_node("power-controller");
+        fdt.prop_u32("phandle", 0x11);
+        fdt.prop_u32("#power-domain-cells", 0);
+        fdt.end_node();
+        fdt.begin_node("device");
+        fdt.prop_cells("resets", &[0x10, 5, 0x10, 7]);
+        fdt.prop("reset-names", b"core\0bus\0");
+        fdt.prop_cells("power-domains", &[0x11]);
+        fdt.prop_cells("enable-gpios", &[0, 0x8003, 1, 1]);
+        fdt.prop_cells("reset-gpios", &[0x8003, 4]);
+        fdt.prop_cells("clocks", &[0x99]);
+        fdt.end_node();
+        fdt.end_node();
+        fdt.build()
+    }
+
+    #[test]
+    fn decodes_gpio_keys() {
+        let devtree = sample();
+        let button = devtree.find_node("/gpio-keys/poweroff").unwrap();
+
+        let gpios: Vec<_> = button.gpios(&devtree, "").collect();
+        assert_eq!(gpios.len(), 1);
+        let gpio = gpios[0].unwrap();
+        assert_eq!(gpio.provider.name(), "pl061@9030000");
+        assert_eq!(gpio.args.collect::<Vec<_>>(), [3, 0]);
+    }
+
+    #[test]
+    fn decodes_named_entries() {
+        let devtree = sample();
+        let device = devtree.find_node("/device").unwrap();
+
+        let resets: Vec<_> = device.resets(&devtree).map(|r| r.unwrap().args.get(0)).collect();
+        assert_eq!(resets, [Some(5), Some(7)]);
+        assert_eq!(device.reset(&devtree, "bus").unwrap().unwrap().args.get(0), Some(7));
+        assert!(device.reset(&devtree, "phy").is_none());
+
+        let domain = device.power_domains(&devtree).next().unwrap().unwrap();
+        assert_eq!(domain.provider.name(), "power-controller");
+        assert_eq!(domain.args.remaining(), 0);
+    }
+
+    #[test]
+    fn reports_bad_entries() {
+        let devtree = sample();
+        let device = devtree.find_node("/device").unwrap();
+
+        let enable: Vec<_> = device.gpios(&devtree, "enable").collect();
+        assert_eq!(enable[0].unwrap_err(), SpecifierError::Empty);
+        assert_eq!(enable[1].unwrap().args.collect::<Vec<_>>(), [1, 1]);
+
+        let reset: Vec<_> = device.gpios(&devtree, "reset").map(|r| r.map(|_| ())).collect();
+        assert_eq!(reset, [Err(SpecifierError::Truncated)]);
+
+        let clocks: Vec<_> = device.clocks(&devtree).map(|r| r.map(|_| ())).collect();
+        assert_eq!(clocks, [Err(SpecifierError::UnknownPhandle(0x99))]);
+    }
+
+    #[test]
+    fn resolves_through_index() {
+        let devtree = sample();
+        let mut scratch = [0u32; 16 * INDEX_ENTRY_WORDS];
+        let index = DevTreeIndex::build(&devtree, &mut scratch).unwrap();
+
+        let button = index.find_node("/gpio-keys/poweroff").unwrap().node();
+        let gpio = button.gpios(&index, "").next().unwrap().unwrap();
+        assert_eq!(gpio.provider.name(), "pl061@9030000");
+    }
+}
@@ -0,0 +1,67 @@
+#[cfg(all(target_arch = "aarch64", target_os = "none"))]
+mod imp {
+    use core::arch::asm;
+
+    // Aff3..Aff0 of MPIDR_EL1
+    #[cfg(debug_assertions)]
+    const MPIDR_AFFINITY_MASK: u64 = 0xff_00ff_ffff;
+
+    const SCTLR_M: u64 = 1 << 0;
+    const SCTLR_C: u64 = 1 << 2;
+
+    #[cfg(debug_assertions)]
+    pub fn cpu_id() -> Option<usize> {
+        let mpidr: u64;
+        unsafe { asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack)) };
+        Some((mpidr & MPIDR_AFFINITY_MASK) as usize)
+    }
+
+    pub fn atomics_available() -> bool {
+        let sctlr: u64;
+        unsafe { asm!("mrs {}, sctlr_el1", out(reg) sctlr, options(nomem, nostack)) };
+        sctlr & (SCTLR_M | SCTLR_C) == SCTLR_M | SCTLR_C
+    }
+
+    pub fn save_and_disable_irqs() -> u64 {
+        let daif: u64;
+        unsafe { asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif, options(nostack)) };
+        daif
+    }
+
+    pub fn restore_irqs(daif: u64) {
+        unsafe { asm!("msr daif, {}", in(reg) daif, options(nostack)) };
+    }
+}
+
+// Hosted builds have no CPUs or interrupts to speak of, tests stand in a
+// thread for each CPU
+#[cfg(not(all(target_arch = "aarch64", target_os = "none")))]
+mod imp {
+    #[cfg(all(test, debug_assertions))]
+    pub fn cpu_id() -> Option<usize> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        std::thread_local! {
+            static ID: usize = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(ID.with(|id| *id))
+    }
+
+    #[cfg(all(not(test), debug_assertions))]
+    pub fn cpu_id() -> Option<usize> {
+        None
+    }
+
+    pub fn atomics_available() -> bool {
+        true
+    }
+
+    pub fn save_and_disable_irqs() -> u64 {
+        0
+    }
+
+    pub fn restore_irqs(_daif: u64) {}
+}
+
+pub use imp::*;
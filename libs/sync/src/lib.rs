@@ -0,0 +1,34 @@
+#![cfg_attr(not(test), no_std)]
+
+//! Locks and one-time initialization for kernel globals
+//!
+//! Everything here is built on atomic read-modify-write instructions, which
+//! on AArch64 are load/store-exclusive pairs. Those only work reliably on
+//! normal cacheable memory, so none of these types may be used before the
+//! MMU and data cache are on. Debug builds check that on every lock; code
+//! that runs earlier has to get by with plain cells and the fact that only
+//! the boot CPU is running.
+
+mod arch;
+mod mutex;
+mod once;
+mod owner;
+mod rwlock;
+mod spin;
+
+pub use mutex::{Mutex, MutexGuard, set_yield_hook};
+pub use once::{Lazy, Once};
+pub use owner::set_thread_hook;
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use spin::{SpinLock, SpinLockGuard, SpinLockIrq, SpinLockIrqGuard};
+
+/// Whether atomic read-modify-write operations work yet, i.e. whether the
+/// MMU and data cache are on. Always true off bare-metal AArch64.
+pub fn atomics_available() -> bool {
+    arch::atomics_available()
+}
+
+#[inline]
+fn check_atomics() {
+    debug_assert!(atomics_available(), "Lock used before the MMU and caches are on");
+}
@@ -0,0 +1,123 @@
+use core::fmt;
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+#[cfg(debug_assertions)]
+use crate::arch;
+
+// Names the running thread, null until a scheduler sets it
+static THREAD_HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Track lock holders by the thread `hook` names rather than by CPU, so a
+/// thread preempted while holding a lock doesn't look like a deadlock when
+/// the next one on its CPU wants it. `hook` returns none on CPUs that don't
+/// run threads. Only used in debug builds.
+pub fn set_thread_hook(hook: fn() -> Option<usize>) {
+    THREAD_HOOK.store(hook as *mut (), Ordering::Release);
+}
+
+// Whoever is taking a lock, the running thread where there is one. Never
+// known in release builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(debug_assertions), allow(dead_code))]
+pub(crate) enum Holder {
+    Cpu(usize),
+    Thread(usize),
+}
+
+impl Holder {
+    #[cfg(debug_assertions)]
+    fn current() -> Option<Holder> {
+        let hook = THREAD_HOOK.load(Ordering::Acquire);
+        if !hook.is_null() {
+            let hook: fn() -> Option<usize> = unsafe { core::mem::transmute(hook) };
+            if let Some(thread) = hook() {
+                return Some(Holder::Thread(thread));
+            }
+        }
+        arch::cpu_id().map(Holder::Cpu)
+    }
+
+    // Packed so it fits one atomic, the low bit telling the two apart and
+    // zero meaning nobody
+    #[cfg(debug_assertions)]
+    fn encode(holder: Option<Holder>) -> usize {
+        match holder {
+            None => 0,
+            Some(Holder::Cpu(cpu)) => (cpu << 1) + 2,
+            Some(Holder::Thread(thread)) => (thread << 1) + 3,
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn decode(value: usize) -> Option<Holder> {
+        let id = value.checked_sub(2)?;
+        Some(if id & 1 == 0 { Holder::Cpu(id >> 1) } else { Holder::Thread(id >> 1) })
+    }
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Holder::Cpu(cpu) => write!(f, "CPU {:#x}", cpu),
+            Holder::Thread(thread) => write!(f, "Thread {}", thread),
+        }
+    }
+}
+
+// The CPU or thread holding a lock, tracked in debug builds only
+//
+// Taking a spinlock you already hold, say from an interrupt handler that
+// preempted the holder, would spin forever. With the owner known that
+// becomes a panic naming the culprit instead. Handlers count as the thread
+// they interrupted.
+pub(crate) struct Owner {
+    // An encoded `Holder`, zero meaning nobody so locks in statics stay in
+    // .bss
+    #[cfg(debug_assertions)]
+    holder: AtomicUsize,
+}
+
+impl Owner {
+    pub(crate) const fn new() -> Owner {
+        Owner {
+            #[cfg(debug_assertions)]
+            holder: AtomicUsize::new(0),
+        }
+    }
+
+    /// Panic if the caller holds the lock, call before waiting for it
+    #[inline]
+    pub(crate) fn check_not_held(&self) {
+        #[cfg(debug_assertions)]
+        if let Some(holder) = Holder::current() {
+            assert!(
+                self.holder.load(Ordering::Relaxed) != Holder::encode(Some(holder)),
+                "Deadlock: {} already holds this lock",
+                holder
+            );
+        }
+    }
+
+    #[inline]
+    pub(crate) fn acquired(&self) {
+        #[cfg(debug_assertions)]
+        self.holder.store(Holder::encode(Holder::current()), Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn released(&self) {
+        #[cfg(debug_assertions)]
+        self.holder.store(0, Ordering::Relaxed);
+    }
+
+    /// Who holds the lock, only known in debug builds
+    pub(crate) fn get(&self) -> Option<Holder> {
+        #[cfg(debug_assertions)]
+        return Holder::decode(self.holder.load(Ordering::Relaxed));
+
+        #[cfg(not(debug_assertions))]
+        None
+    }
+}
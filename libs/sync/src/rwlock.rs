@@ -0,0 +1,197 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::check_atomics;
+use crate::owner::Owner;
+
+const WRITER: usize = 1;
+const WRITER_WAITING: usize = 1 << 1;
+const READER: usize = 1 << 2;
+
+// Spinning reader-writer lock
+//
+// Any number of readers or one writer. A waiting writer keeps new readers
+// out so a steady stream of them can't starve it. Doesn't mask interrupts,
+// so it's only for data interrupt handlers leave alone.
+pub struct RwLock<T: ?Sized> {
+    // Reader count above the two writer bits
+    state: AtomicUsize,
+    writer: Owner,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> RwLock<T> {
+        RwLock {
+            state: AtomicUsize::new(0),
+            writer: Owner::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Wait until no writer holds or waits for the lock
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        check_atomics();
+        self.writer.check_not_held();
+
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        check_atomics();
+
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITER | WRITER_WAITING) != 0 {
+            return None;
+        }
+        self.state
+            .compare_exchange_weak(state, state + READER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        Some(RwLockReadGuard { lock: self })
+    }
+
+    /// Wait until nobody else holds the lock
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        check_atomics();
+        self.writer.check_not_held();
+
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        check_atomics();
+
+        let state = self.state.load(Ordering::Relaxed);
+        if state & !WRITER_WAITING != 0 {
+            return None;
+        }
+        // Taking the lock clears the waiting bit, other waiting writers set
+        // it again on their next round
+        self.state
+            .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+
+        self.writer.acquired();
+        Some(RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => f.debug_struct("RwLock").field("data", &&*guard).finish(),
+            None => f.debug_struct("RwLock").field("writer", &self.writer.get()).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.writer.released();
+        // Keep the waiting bit of any writer queued behind us
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readers_share_writers_exclude() {
+        let lock = RwLock::new(vec![1, 2]);
+
+        let first = lock.read();
+        let second = lock.try_read().unwrap();
+        assert_eq!(first.len() + second.len(), 4);
+        assert!(lock.try_write().is_none());
+        drop((first, second));
+
+        let mut writer = lock.write();
+        writer.push(3);
+        assert!(lock.try_read().is_none());
+        drop(writer);
+
+        assert_eq!(*lock.read(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn waiting_writer_blocks_new_readers() {
+        let lock = RwLock::new(0);
+        let reader = lock.read();
+
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| *lock.write() += 1);
+            while lock.state.load(Ordering::Relaxed) & WRITER_WAITING == 0 {
+                std::thread::yield_now();
+            }
+            assert!(lock.try_read().is_none());
+            drop(reader);
+            writer.join().unwrap();
+        });
+
+        assert_eq!(*lock.read(), 1);
+    }
+}
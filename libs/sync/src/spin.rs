@@ -0,0 +1,287 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::owner::Owner;
+use crate::{arch, check_atomics};
+
+// Ticket spinlock
+//
+// Each CPU takes the next ticket and waits for it to be served, so the lock
+// goes around in arrival order and nobody starves. Doesn't mask interrupts,
+// use `SpinLockIrq` for anything an interrupt handler also takes.
+pub struct SpinLock<T: ?Sized> {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    owner: Owner,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> SpinLock<T> {
+        SpinLock {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            owner: Owner::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    /// Wait for the lock
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        check_atomics();
+        self.owner.check_not_held();
+
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            core::hint::spin_loop();
+        }
+
+        self.owner.acquired();
+        SpinLockGuard { lock: self }
+    }
+
+    /// Take the lock only if nobody holds or waits for it
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        check_atomics();
+
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        self.next_ticket
+            .compare_exchange(serving, serving.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+
+        self.owner.acquired();
+        Some(SpinLockGuard { lock: self })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.next_ticket.load(Ordering::Relaxed) != self.now_serving.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Pointer to the data, for code that provably runs alone, like the boot
+    /// CPU before the MMU is on
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    /// Release the lock without a guard, e.g. from a panic handler that
+    /// interrupted its holder
+    ///
+    /// # Safety
+    /// Whoever holds the lock must never touch the data again.
+    pub unsafe fn force_unlock(&self) {
+        if self.is_locked() {
+            self.unlock();
+        }
+    }
+
+    fn unlock(&self) {
+        self.owner.released();
+        // Only the holder writes `now_serving`
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        self.now_serving.store(serving.wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("SpinLock").field("data", &&*guard).finish(),
+            None => f.debug_struct("SpinLock").field("owner", &self.owner.get()).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+// Spinlock that masks IRQs on the holding CPU
+//
+// Data shared with interrupt handlers needs this, otherwise a handler can
+// interrupt the holder on its own CPU and spin on the lock forever. The
+// previous mask is restored on unlock, so these nest.
+pub struct SpinLockIrq<T: ?Sized> {
+    inner: SpinLock<T>,
+}
+
+pub struct SpinLockIrqGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<SpinLockGuard<'a, T>>,
+    daif: u64,
+}
+
+impl<T> SpinLockIrq<T> {
+    pub const fn new(data: T) -> SpinLockIrq<T> {
+        SpinLockIrq { inner: SpinLock::new(data) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLockIrq<T> {
+    /// Mask IRQs and wait for the lock
+    pub fn lock(&self) -> SpinLockIrqGuard<'_, T> {
+        let daif = arch::save_and_disable_irqs();
+        SpinLockIrqGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            daif,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockIrqGuard<'_, T>> {
+        let daif = arch::save_and_disable_irqs();
+        match self.inner.try_lock() {
+            Some(guard) => Some(SpinLockIrqGuard {
+                guard: ManuallyDrop::new(guard),
+                daif,
+            }),
+            None => {
+                arch::restore_irqs(daif);
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// See `SpinLock::data_ptr`
+    pub fn data_ptr(&self) -> *mut T {
+        self.inner.data_ptr()
+    }
+
+    /// See `SpinLock::force_unlock`, leaves IRQs as they are
+    ///
+    /// # Safety
+    /// Whoever holds the lock must never touch the data again.
+    pub unsafe fn force_unlock(&self) {
+        unsafe { self.inner.force_unlock() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinLockIrq<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("SpinLockIrq").field("data", &&*guard).finish(),
+            None => f.debug_struct("SpinLockIrq").field("owner", &self.inner.owner.get()).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockIrqGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockIrqGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockIrqGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unlock before unmasking, or an IRQ could find it still held
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        arch::restore_irqs(self.daif);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn counts_across_threads() {
+        let counter = Arc::new(SpinLock::new(0u64));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let counter = Arc::clone(&counter);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        *counter.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*counter.lock(), 4000);
+        assert!(!counter.is_locked());
+    }
+
+    #[test]
+    fn try_lock_fails_while_held() {
+        let lock = SpinLockIrq::new(1);
+        let guard = lock.lock();
+        assert!(lock.is_locked());
+        assert!(std::thread::scope(|s| s.spawn(|| lock.try_lock().is_none()).join().unwrap()));
+        drop(guard);
+        assert_eq!(*lock.try_lock().unwrap(), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "already holds this lock")]
+    fn catches_relocking_on_same_cpu() {
+        let lock = SpinLock::new(());
+        let _guard = lock.lock();
+        let _again = lock.lock();
+    }
+
+    #[test]
+    fn force_unlock_releases() {
+        let lock = SpinLock::new(5);
+        core::mem::forget(lock.lock());
+        unsafe { lock.force_unlock() };
+        assert_eq!(*lock.lock(), 5);
+    }
+}
@@ -0,0 +1,30 @@
+use std::{fs, path::Path};
+use anyhow::{anyhow, Context, Result};
+use devtree::{DevTree, OwnedDevTree};
+
+/// Print a DTB as JSON, for snapshotting it from test harnesses
+pub fn dtb_json(path: &Path) -> Result<()> {
+    let tree = load(path)?;
+    let json = serde_json::to_string_pretty(&tree)
+        .context("Failed to serialize device tree")?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Read and parse a DTB file into an owned tree
+pub fn load(path: &Path) -> Result<OwnedDevTree> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    parse(&bytes).ok_or_else(|| anyhow!("{} is not a valid DTB", path.display()))
+}
+
+/// Parse DTB bytes into an owned tree, none if they aren't a valid DTB
+pub fn parse(bytes: &[u8]) -> Option<OwnedDevTree> {
+    // The parser wants the blob 8-byte aligned and around for good
+    let words = vec![0u64; bytes.len().div_ceil(8)].leak();
+    let blob = unsafe { std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, bytes.len()) };
+    blob.copy_from_slice(bytes);
+
+    let devtree = DevTree::from_bytes(blob)?;
+    Some(OwnedDevTree::from_devtree(&devtree))
+}
@@ -0,0 +1,197 @@
+use std::{collections::BTreeSet, path::Path};
+use anyhow::Result;
+use devtree::{OwnedDevTree, OwnedNode, OwnedProperty, PropertyValue};
+
+use crate::dtb::load;
+
+/// Compare two DTB files and print what changed, returns whether they differ
+pub fn dtb_diff(old_path: &Path, new_path: &Path) -> Result<bool> {
+    let old = load(old_path)?;
+    let new = load(new_path)?;
+
+    println!("--- {}", old_path.display());
+    println!("+++ {}", new_path.display());
+    let differs = diff_trees(&old, &new);
+
+    if !differs {
+        println!("No differences");
+    }
+
+    Ok(differs)
+}
+
+// Print what changed from `old` to `new`, returns whether anything did
+fn diff_trees(old: &OwnedDevTree, new: &OwnedDevTree) -> bool {
+    let mut differs = false;
+
+    // Memory reservation block
+    let old_rsv: BTreeSet<_> = old.mem_reservations().iter().map(|r| (r.address, r.size)).collect();
+    let new_rsv: BTreeSet<_> = new.mem_reservations().iter().map(|r| (r.address, r.size)).collect();
+    for (address, size) in old_rsv.difference(&new_rsv) {
+        println!("- /memreserve/ {:#x} {:#x};", address, size);
+        differs = true;
+    }
+    for (address, size) in new_rsv.difference(&old_rsv) {
+        println!("+ /memreserve/ {:#x} {:#x};", address, size);
+        differs = true;
+    }
+
+    if old.boot_cpuid_phys() != new.boot_cpuid_phys() {
+        println!("- boot_cpuid_phys = {:#x}", old.boot_cpuid_phys());
+        println!("+ boot_cpuid_phys = {:#x}", new.boot_cpuid_phys());
+        differs = true;
+    }
+
+    // Removed and changed nodes, only reporting the top of removed subtrees
+    for node in old.nodes() {
+        match new.find_node(node.path()) {
+            Some(other) => differs |= diff_properties(node, other),
+            None => {
+                if node.parent().is_none_or(|parent| new.find_node(parent.path()).is_some()) {
+                    println!("- {}", node.path());
+                }
+                differs = true;
+            }
+        }
+    }
+
+    // Added nodes
+    for node in new.nodes() {
+        if old.find_node(node.path()).is_none() {
+            if node.parent().is_none_or(|parent| old.find_node(parent.path()).is_some()) {
+                println!("+ {}", node.path());
+            }
+            differs = true;
+        }
+    }
+
+    differs
+}
+
+fn diff_properties(old: OwnedNode<'_>, new: OwnedNode<'_>) -> bool {
+    let mut lines = Vec::new();
+
+    for prop in old.properties() {
+        match new.property(prop.name()) {
+            None => lines.push(format!("- {}", format_property(prop))),
+            Some(other) if other.value() != prop.value() => {
+                lines.push(format!("- {}", format_property(prop)));
+                lines.push(format!("+ {}", format_property(other)));
+            }
+            Some(_) => {}
+        }
+    }
+
+    for prop in new.properties() {
+        if old.property(prop.name()).is_none() {
+            lines.push(format!("+ {}", format_property(prop)));
+        }
+    }
+
+    if lines.is_empty() {
+        return false;
+    }
+
+    println!("~ {}", old.path());
+    for line in lines {
+        println!("    {}", line);
+    }
+    true
+}
+
+fn format_property(prop: &OwnedProperty) -> String {
+    match prop.as_property().decode() {
+        PropertyValue::Empty => format!("{};", prop.name()),
+        value => format!("{} = {};", prop.name(), value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtb::parse;
+
+    const FDT_BEGIN_NODE: u32 = 0x1;
+    const FDT_END_NODE: u32 = 0x2;
+    const FDT_PROP: u32 = 0x3;
+    const FDT_NOP: u32 = 0x4;
+    const FDT_END: u32 = 0x9;
+
+    enum Token<'a> {
+        Begin(&'a str),
+        End,
+        Prop(&'a str, u32),
+        Nop,
+    }
+    use Token::*;
+
+    // Just enough of a DTB writer for the trees below
+    fn dtb(tokens: &[Token]) -> Vec<u8> {
+        let mut structs = Vec::new();
+        let mut strings = Vec::new();
+        let word = |out: &mut Vec<u8>, value: u32| out.extend_from_slice(&value.to_be_bytes());
+        for token in tokens {
+            match *token {
+                Begin(name) => {
+                    word(&mut structs, FDT_BEGIN_NODE);
+                    structs.extend_from_slice(name.as_bytes());
+                    structs.push(0);
+                    structs.resize(structs.len().next_multiple_of(4), 0);
+                }
+                End => word(&mut structs, FDT_END_NODE),
+                Prop(name, value) => {
+                    word(&mut structs, FDT_PROP);
+                    word(&mut structs, 4);
+                    word(&mut structs, strings.len() as u32);
+                    word(&mut structs, value);
+                    strings.extend_from_slice(name.as_bytes());
+                    strings.push(0);
+                }
+                Nop => word(&mut structs, FDT_NOP),
+            }
+        }
+        word(&mut structs, FDT_END);
+
+        // Header, then an empty reservation block
+        let off_dt_struct = 40 + 16;
+        let off_dt_strings = off_dt_struct + structs.len();
+        let totalsize = off_dt_strings + strings.len();
+        let mut blob = Vec::new();
+        for field in [
+            0xd00dfeed,
+            totalsize as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            40,
+            17,
+            16,
+            0,
+            strings.len() as u32,
+            structs.len() as u32,
+        ] {
+            word(&mut blob, field);
+        }
+        blob.resize(off_dt_struct, 0);
+        blob.extend_from_slice(&structs);
+        blob.extend_from_slice(&strings);
+        blob
+    }
+
+    fn tree(value: u32, nops: bool) -> OwnedDevTree {
+        let nop = || nops.then_some(Nop);
+        let mut tokens = vec![Begin(""), Prop("#address-cells", 2)];
+        tokens.extend(nop());
+        tokens.extend([Begin("kid"), End]);
+        tokens.extend(nop());
+        tokens.extend([Begin("kid2"), Prop("value", value), End, End]);
+        parse(&dtb(&tokens)).unwrap()
+    }
+
+    #[test]
+    fn sees_nodes_past_nops() {
+        let (plain, padded) = (tree(1, false), tree(1, true));
+        assert_eq!(padded.len(), 3);
+        assert!(!diff_trees(&plain, &padded));
+        assert!(diff_trees(&tree(1, true), &tree(2, true)));
+    }
+}
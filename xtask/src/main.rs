@@ -1,7 +1,9 @@
-use std::{path::Path, process::Command, fs};
+use std::{path::{Path, PathBuf}, process::Command, fs};
 use clap::{Parser, Subcommand};
 use anyhow::{Context, Result};
 
+mod dtb;
+mod dtb_diff;
 mod qemu;
 
 #[derive(Parser, Debug)]
@@ -24,24 +26,45 @@ enum Cmd {
     Qemu {
         #[arg(long)]
         release: bool,
+        /// cpio archive to pass as the initrd, unpacked into the root
+        #[arg(long)]
+        initrd: Option<PathBuf>,
+    },
+    DebugQemu {
+        #[arg(long)]
+        initrd: Option<PathBuf>,
+    },
+    /// Compare two DTBs, e.g. from QEMU's `-machine dumpdtb=...`
+    DtbDiff {
+        old: PathBuf,
+        new: PathBuf,
+    },
+    /// Print a DTB as JSON
+    DtbJson {
+        path: PathBuf,
     },
-    DebugQemu,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
     match args.cmd {
-        Cmd::BuildKernel { release } => build_kernel(release)?,
-        Cmd::BuildImage { release } => build_image(release)?,
-        Cmd::Qemu { release } => qemu_aarch64(release, false)?,
-        Cmd::DebugQemu => qemu_aarch64(false, true)?,
+        Cmd::BuildKernel { release } => build_kernel(release, &[])?,
+        Cmd::BuildImage { release } => build_image(release, &[])?,
+        Cmd::Qemu { release, initrd } => qemu_aarch64(release, false, initrd.as_deref())?,
+        Cmd::DebugQemu { initrd } => qemu_aarch64(false, true, initrd.as_deref())?,
+        Cmd::DtbDiff { old, new } => {
+            if dtb_diff::dtb_diff(&old, &new)? {
+                std::process::exit(1);
+            }
+        }
+        Cmd::DtbJson { path } => dtb::dtb_json(&path)?,
     }
 
     Ok(())
 }
 
-fn build_kernel(release: bool) -> Result<()> {
+fn build_kernel(release: bool, features: &[&str]) -> Result<()> {
     let mut cmd = Command::new("cargo");
     cmd.arg("build");
 
@@ -49,6 +72,10 @@ fn build_kernel(release: bool) -> Result<()> {
         cmd.arg("--release");
     }
 
+    if !features.is_empty() {
+        cmd.args(["--features", &features.join(",")]);
+    }
+
     cmd.current_dir("kernel"); // workdir is `kernel` folder
 
     let status = cmd
@@ -63,8 +90,8 @@ fn build_kernel(release: bool) -> Result<()> {
     Ok(())
 }
 
-fn build_image(release: bool) -> Result<()> {
-    build_kernel(release)?;
+fn build_image(release: bool, features: &[&str]) -> Result<()> {
+    build_kernel(release, features)?;
     
     let target_dir = Path::new("target");
     if !target_dir.exists() {
@@ -121,14 +148,21 @@ fn build_image(release: bool) -> Result<()> {
     Ok(())
 }
 
-fn qemu_aarch64(release: bool, debug_mode: bool) -> Result<()> {
-    build_image(if debug_mode { false } else { release })?;
+fn qemu_aarch64(release: bool, debug_mode: bool, initrd: Option<&Path>) -> Result<()> {
+    // Let panics exit QEMU with a failure code
+    build_image(if debug_mode { false } else { release }, &["qemu-exit"])?;
     
     let mut cmd = if debug_mode {
         qemu::aarch64::debug_qemu()
     } else {
         qemu::aarch64::qemu(release)
     };
+    if let Some(initrd) = initrd {
+        if !initrd.exists() {
+            return Err(anyhow::anyhow!("Initrd {} does not exist", initrd.display()));
+        }
+        cmd.arg("-initrd").arg(initrd);
+    }
 
     println!("Running QEMU...\nSerial monitor enabled, use Ctrl+A X to exit and Ctrl+A C to switch to monitor mode");
     let status = cmd.status()
@@ -3,8 +3,11 @@ use std::process::Command;
 const QEMU_AARCH64_MACHINE_ARGS: &[&str] = &[
     "-M", "virt",
     "-cpu", "cortex-a57",
+    "-smp", "4",
     "-display", "none",
-    "-serial", "mon:stdio"
+    "-serial", "mon:stdio",
+    // Lets the kernel's `qemu-exit` feature report a status
+    "-semihosting",
 ];
 
 pub fn qemu(release: bool) -> Command {
@@ -13,13 +16,13 @@ pub fn qemu(release: bool) -> Command {
 
     let profile_dir = if release { "release" } else { "debug" };
     let kernel_path = format!("target/aarch64-unknown-none/{}/silly-kernel.bin", profile_dir);
-    cmd.args(&["-kernel", &kernel_path]);
+    cmd.args(["-kernel", &kernel_path]);
 
     cmd
 }
 
 pub fn debug_qemu() -> Command {
     let mut cmd = qemu(false);
-    cmd.args(&["-gdb", "tcp::1234", "-S"]);
+    cmd.args(["-gdb", "tcp::1234", "-S"]);
     cmd
 }